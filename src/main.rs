@@ -1,13 +1,18 @@
-use std::{process, sync::Arc};
+use std::{process, sync::Arc, time::Duration};
 
 use clap::Parser;
 use dotenvy::from_filename;
+use memory::{EvictionPolicy, Store};
 use replication::{INIT_ARGS, InitArgs, Replica};
 use tokio::{signal, sync::mpsc, task::JoinHandle};
 
 mod memory;
 mod replication;
+mod resp;
+mod rest;
 mod socket;
+#[cfg(test)]
+mod test_support;
 
 #[tokio::main]
 async fn main() {
@@ -24,10 +29,33 @@ async fn main() {
             process::exit(1);
         }
     };
-    let replica = Arc::new(Replica::new(node));
+    let store = match create_store() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Falha ao criar o cache: {}", e);
+            process::exit(1);
+        }
+    };
+    let cluster = match replication::create_cluster(&node) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Falha ao configurar o cluster: {}", e);
+            process::exit(1);
+        }
+    };
+    let failover_timeout = Duration::from_millis(INIT_ARGS.get().unwrap().failover_timeout);
+    let mut replica = Replica::with_store(node, store).with_failover_timeout(failover_timeout);
+    if let Some(cluster) = cluster {
+        replica = replica.with_cluster(cluster);
+    }
+    let replica = Arc::new(replica);
+
+    start_expiration_thread(replica.clone());
 
     let socket_replication_thread = start_replication_thread(replica.clone()).await;
     let socket_service_thread = start_socket_service(replica.clone()).await;
+    start_resp_service(replica.clone());
+    start_http_service(replica.clone());
 
     manage_shutdown_signals(socket_replication_thread, socket_service_thread).await;
 }
@@ -40,6 +68,12 @@ fn init_node() {
     }
 }
 
+fn create_store() -> Result<Store, memory::MemoryError> {
+    let args = INIT_ARGS.get().unwrap();
+    let policy = EvictionPolicy::try_from(args.eviction_policy.clone())?;
+    Ok(Store::with_limit(args.max_memory, policy))
+}
+
 async fn start_replication_thread(replica: Arc<Replica>) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async {
         let tasks = replication::start_replication_tasks(replica).await;
@@ -53,15 +87,48 @@ async fn start_replication_thread(replica: Arc<Replica>) -> tokio::task::JoinHan
     })
 }
 
+fn start_expiration_thread(replica: Arc<Replica>) -> JoinHandle<()> {
+    let store = replica.store.clone();
+    tokio::spawn(async move { memory::start_expiration(store, memory::EXPIRATION_INTERVAL).await })
+}
+
 async fn start_socket_service(replica: Arc<Replica>) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async {
-        if let Err(e) = socket::start(replica).await {
+    let Some(ipaddr) = replica.node().services().socket else {
+        eprintln!("Porta do serviço de socket invalida");
+        process::exit(1);
+    };
+    let ipaddr = replication::bind_address(ipaddr);
+
+    tokio::spawn(async move {
+        if let Err(e) = socket::start(replica, ipaddr).await {
             eprintln!("Falha ao iniciar o serviço de socket: {}", e);
             process::exit(1);
         }
     })
 }
 
+fn start_resp_service(replica: Arc<Replica>) -> Option<JoinHandle<()>> {
+    let ipaddr = replication::bind_address(replica.node().services().resp?);
+
+    Some(tokio::spawn(async move {
+        if let Err(e) = resp::start(replica, ipaddr).await {
+            eprintln!("Falha ao iniciar o serviço RESP: {}", e);
+            process::exit(1);
+        }
+    }))
+}
+
+fn start_http_service(replica: Arc<Replica>) -> Option<JoinHandle<()>> {
+    let ipaddr = replication::bind_address(replica.node().services().http?);
+
+    Some(tokio::spawn(async move {
+        if let Err(e) = rest::start(replica, ipaddr).await {
+            eprintln!("Falha ao iniciar o serviço HTTP: {}", e);
+            process::exit(1);
+        }
+    }))
+}
+
 async fn manage_shutdown_signals(
     socket_replication: JoinHandle<()>,
     socket_service: JoinHandle<()>,
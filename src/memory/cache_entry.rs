@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use super::{cache_value::CacheValue, clock, mutation::Mutation};
+
+/// Custo fixo aproximado, em bytes, de cada entrada no mapa alem da `key` e do valor.
+pub const ENTRY_OVERHEAD: u64 = 64;
+
+/// Entrada armazenada no mapa principal do `Store`.
+///
+/// Guarda o valor entregue pelo usuario junto aos metadados que o cache
+/// precisa internamente, como o momento em que a `key` deixa de ser valida
+/// e as informações de acesso usadas pelas politicas de remoção.
+#[derive(Debug)]
+pub struct CacheEntry {
+    /// Valor armazenado e devolvido ao usuario.
+    pub value: CacheValue,
+    /// Timestamp em milissegundos em que a entrada expira, `None` quando não possui tempo de vida.
+    pub expires_at: Option<i64>,
+    /// Versão da escrita que gerou a entrada, usada em escritas condicionais.
+    pub version: u64,
+    /// Timestamp em milissegundos do ultimo acesso, usado pela politica LRU.
+    last_access: AtomicI64,
+    /// Quantidade de acessos a entrada, usado pela politica LFU.
+    hits: AtomicU64,
+}
+
+impl CacheEntry {
+    pub fn new(value: CacheValue, expires_at: Option<i64>, now_timestamp: i64) -> Self {
+        Self {
+            value,
+            expires_at,
+            version: 0,
+            last_access: AtomicI64::new(now_timestamp),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Verifica se a entrada ja expirou em relação ao timestamp informado.
+    ///
+    /// Segue a mesma regra do `CacheTTLControl`, um timestamp so é considerado
+    /// expirado quando é menor que o ponto atual.
+    pub fn is_expired(&self, now_timestamp: i64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at < now_timestamp)
+    }
+
+    /// Registra um acesso a entrada.
+    ///
+    /// Os campos são atomicos para que a leitura continue usando somente o
+    /// guard de leitura do mapa.
+    pub fn touch(&self, now_timestamp: i64) {
+        self.last_access.store(now_timestamp, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn last_access(&self) -> i64 {
+        self.last_access.load(Ordering::Relaxed)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Alteração que grava essa entrada na `key`, com o prazo como timestamp Unix.
+    pub fn mutation(&self, key: &str) -> Mutation {
+        Mutation::Set {
+            key: key.to_string(),
+            value: self.value.clone(),
+            expires_at: self.expires_at.map(clock::to_unix_millis),
+        }
+    }
+
+    /// Tamanho aproximado em bytes ocupado pela entrada junto a sua `key`.
+    pub fn size(&self, key: &str) -> u64 {
+        (key.len() + self.value.as_bytes().len()) as u64 + ENTRY_OVERHEAD
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheEntry, CacheValue, ENTRY_OVERHEAD};
+
+    #[test]
+    fn test_is_expired() {
+        let entry = CacheEntry::new(CacheValue::new("value"), Some(10), 0);
+
+        assert!(!entry.is_expired(10), "Should not expire at the deadline");
+        assert!(entry.is_expired(11), "Should expire after the deadline");
+
+        let persistent = CacheEntry::new(CacheValue::new("value"), None, 0);
+        assert!(!persistent.is_expired(i64::MAX), "Should never expire");
+    }
+
+    #[test]
+    fn test_touch_and_size() {
+        let entry = CacheEntry::new(CacheValue::new("value"), None, 0);
+        entry.touch(42);
+        entry.touch(43);
+
+        assert_eq!(entry.last_access(), 43);
+        assert_eq!(entry.hits(), 2);
+        assert_eq!(entry.size("key"), 8 + ENTRY_OVERHEAD);
+    }
+}
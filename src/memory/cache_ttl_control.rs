@@ -1,12 +1,15 @@
-use chrono::Local;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, HashSet},
     sync::atomic::{AtomicU64, Ordering},
 };
 use tokio::sync::RwLock;
 
+use super::clock;
+
 /// Struct do controle do tempo de vida.
 ///
+/// Os timestamps são milissegundos do relógio monotônico do cache, ver `clock::now_millis`.
+///
 /// Aqui é onde o tempo de vida é controlado, o mapa contem de forma ordenada
 /// os itens mais antigos ao mais novo, o controle é feito separado para não
 /// interferir no cache principal e evitar locks prolongados.
@@ -18,83 +21,116 @@ use tokio::sync::RwLock;
 pub struct CacheTTLControl {
     /// Mostra o tamanho atual do mapa retornando o numero de itens.
     length: AtomicU64,
-    /// Mapa ordenado pelo tempo de vida junto a key usada no cache principal.
-    items: RwLock<BTreeMap<i64, String>>,
+    /// Indices do tempo de vida protegidos pelo mesmo lock.
+    items: RwLock<TTLItems>,
+}
+
+/// Indices do controle do tempo de vida.
+///
+/// Cada timestamp agrupa todas as `keys` que expiram nele, permitindo que
+/// varias `keys` compartilhem o mesmo ponto de expiração. O indice reverso
+/// guarda o timestamp atual de cada `key` para que uma nova expiração
+/// substitua a anterior sem percorrer o mapa ordenado.
+#[derive(Default)]
+struct TTLItems {
+    /// Mapa ordenado pelo tempo de vida junto as keys usadas no cache principal.
+    deadlines: BTreeMap<i64, HashSet<String>>,
+    /// Timestamp de expiração atual de cada key.
+    keys: HashMap<String, i64>,
 }
 
 impl CacheTTLControl {
     pub fn new() -> Self {
         Self {
             length: AtomicU64::new(0),
-            items: RwLock::new(BTreeMap::new()),
+            items: RwLock::new(TTLItems::default()),
         }
     }
 
     /// Retorna o o tamanho atual do mapa.
+    #[cfg(test)]
     pub fn len(&self) -> u64 {
         self.length.load(Ordering::Acquire)
     }
 
     /// Insere um novo item no mapa contendo o timestamp e a key que esta sendo armazenada no cache principal.
-    pub async fn set(&self, timestamp: i64, store_key: String) -> Option<String> {
+    ///
+    /// Se a key ja possuir um tempo de vida ele é substituido pelo novo timestamp.
+    /// Retorna o timestamp anterior da key quando existir.
+    pub async fn set(&self, timestamp: i64, store_key: String) -> Option<i64> {
         let mut items_guard = self.items.write().await;
-        let updated = items_guard.insert(timestamp, store_key.to_owned());
-        self.length.fetch_add(1, Ordering::AcqRel);
-        match updated {
-            Some(_) => Some(store_key),
-            _ => None,
+        let previous = items_guard.keys.insert(store_key.clone(), timestamp);
+
+        match previous {
+            Some(old_timestamp) => items_guard.unlink(old_timestamp, &store_key),
+            None => {
+                self.length.fetch_add(1, Ordering::AcqRel);
+            }
         }
+
+        items_guard
+            .deadlines
+            .entry(timestamp)
+            .or_default()
+            .insert(store_key);
+
+        previous
     }
 
     /// Limpeza ativa do mapa.
     ///
     /// Limpa ativamente os timestamps expirados comparando com o timestamp atual.
     /// Todo timestamp que estiver incluso no range ate o ponto atual é
-    /// mapeado para um vetor e removido do mapa  de controle do tempo de vida.
+    /// separado do mapa de controle do tempo de vida de uma só vez, o custo
+    /// cresce com o numero de keys expiradas e não com o tamanho do mapa.
     ///
     /// Retorna um vetor com as chaves expiradas.
     pub async fn cleanup_expired(&self) -> Option<Vec<String>> {
-        let now_timestamp = Local::now().timestamp();
+        let now_timestamp = clock::now_millis();
         let mut items_guard = self.items.write().await;
 
-        let expired_keys: Vec<String> = items_guard
-            .range(..now_timestamp)
-            .map(|(_, key)| key.to_owned())
-            .collect();
+        let alive = items_guard.deadlines.split_off(&now_timestamp);
+        let expired = std::mem::replace(&mut items_guard.deadlines, alive);
 
-        if expired_keys.len().eq(&0) {
+        let expired_keys: Vec<String> = expired.into_values().flatten().collect();
+        if expired_keys.is_empty() {
             return None;
         }
 
         for e_key in expired_keys.iter() {
-            items_guard.retain(|_, stored_k| *e_key != *stored_k);
-            self.length.fetch_sub(1, Ordering::AcqRel);
+            items_guard.keys.remove(e_key);
         }
+        self.length
+            .fetch_sub(expired_keys.len() as u64, Ordering::AcqRel);
 
         Some(expired_keys)
     }
 }
 
+impl TTLItems {
+    /// Remove a key do grupo do timestamp, descartando o grupo quando ficar vazio.
+    fn unlink(&mut self, timestamp: i64, key: &str) {
+        if let Some(group) = self.deadlines.get_mut(&timestamp) {
+            group.remove(key);
+            if group.is_empty() {
+                self.deadlines.remove(&timestamp);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use chrono::{DateTime, Duration, Local};
+    use std::time::Duration;
 
-    use super::CacheTTLControl;
+    use super::{CacheTTLControl, clock};
 
     fn future_point_in_seconds(seconds: i64) -> i64 {
-        let now: DateTime<Local> = Local::now();
-        let ttl = Duration::seconds(seconds);
-        let future = now + ttl;
-
-        future.timestamp()
+        future_point_in_milis(seconds * 1000)
     }
 
     fn future_point_in_milis(milis: i64) -> i64 {
-        let now: DateTime<Local> = Local::now();
-        let ttl = Duration::milliseconds(milis);
-        let future = now + ttl;
-
-        future.timestamp()
+        clock::now_millis() + milis
     }
 
     #[tokio::test]
@@ -139,4 +175,54 @@ mod test {
             "Should not contain value1s"
         );
     }
+
+    #[tokio::test]
+    async fn test_keys_sharing_timestamp() {
+        let expired = future_point_in_seconds(-1);
+
+        let ctc = CacheTTLControl::new();
+        for i in 0..1000 {
+            ctc.set(expired, format!("key-{}", i)).await;
+        }
+        assert_eq!(ctc.len(), 1000, "All keys should be tracked");
+
+        let expired_keys = ctc.cleanup_expired().await.expect("Should expire keys");
+        assert_eq!(expired_keys.len(), 1000, "All keys should expire together");
+        assert_eq!(ctc.len(), 0, "Should have a len of 0 after cleanup");
+    }
+
+    #[tokio::test]
+    async fn test_reset_replaces_timestamp() {
+        let expired = future_point_in_seconds(-1);
+        let alive = future_point_in_seconds(10);
+
+        let ctc = CacheTTLControl::new();
+        assert_eq!(ctc.set(expired, "key".to_owned()).await, None);
+        assert_eq!(ctc.set(alive, "key".to_owned()).await, Some(expired));
+        assert_eq!(ctc.len(), 1, "Should track the key only once");
+
+        assert_eq!(
+            ctc.cleanup_expired().await,
+            None,
+            "Old timestamp should no longer expire the key"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_sub_second() {
+        let _50ms = future_point_in_milis(50);
+        let _10sec = future_point_in_seconds(10);
+
+        let ctc = CacheTTLControl::new();
+        ctc.set(_50ms, "value50ms".to_owned()).await;
+        ctc.set(_10sec, "value10s".to_owned()).await;
+        assert_eq!(ctc.cleanup_expired().await, None, "Nothing expired yet");
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            ctc.cleanup_expired().await,
+            Some(vec!["value50ms".to_owned()]),
+            "Should expire with millisecond precision"
+        );
+    }
 }
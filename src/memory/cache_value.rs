@@ -1,10 +1,19 @@
-use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, SeqAccess, Visitor},
+};
 
 /// Representa um valor armazenado na memoria ligado a uma chave.
 ///
 /// Todo os dado que entrar sera salvo como bytes e devolvido como bytes.
 /// O interessado no dado tera a tarefas de convertelo para o tipo que quiser.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+///
+/// Em formatos de texto, como JSON, o valor é serializado como uma string
+/// base64, nos formatos binarios, como MessagePack, como bytes.
+#[derive(Debug, Clone, PartialEq)]
 pub struct CacheValue(Vec<u8>);
 
 impl CacheValue {
@@ -20,6 +29,72 @@ impl CacheValue {
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
+
+    /// Interpreta os bytes como um inteiro decimal, como `"-42"`.
+    ///
+    /// Retorna `None` quando o valor não é um inteiro valido de 64 bits.
+    pub fn as_integer(&self) -> Option<i64> {
+        std::str::from_utf8(&self.0).ok()?.parse().ok()
+    }
+
+    /// Interpreta os bytes como um numero decimal, como `"3.14"` ou `"10"`.
+    ///
+    /// Retorna `None` quando o valor não é um numero finito.
+    pub fn as_float(&self) -> Option<f64> {
+        let number: f64 = std::str::from_utf8(&self.0).ok()?.parse().ok()?;
+        number.is_finite().then_some(number)
+    }
+}
+
+impl Serialize for CacheValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CacheValue {
+    /// Aceita uma string base64, bytes ou uma lista de numeros, independente
+    /// do formato, ja que o serde pode bufferizar o valor antes de entrega-lo.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(CacheValueVisitor)
+    }
+}
+
+struct CacheValueVisitor;
+
+impl<'de> Visitor<'de> for CacheValueVisitor {
+    type Value = CacheValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a base64 string or a byte array")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        STANDARD
+            .decode(value)
+            .map(CacheValue)
+            .map_err(|e| E::custom(format!("invalid base64 value: {}", e)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(CacheValue::new(value))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(CacheValue(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Ok(CacheValue(bytes))
+    }
 }
 
 #[cfg(test)]
@@ -33,4 +108,33 @@ mod test {
 
         assert_eq!(expected.as_bytes(), value.as_bytes())
     }
+
+    #[test]
+    fn test_numeric_values() {
+        assert_eq!(CacheValue::new("-42").as_integer(), Some(-42));
+        assert_eq!(CacheValue::new("4.2").as_integer(), None);
+        assert_eq!(CacheValue::new(" 42").as_integer(), None);
+        assert_eq!(CacheValue::new("4.5").as_float(), Some(4.5));
+        assert_eq!(CacheValue::new("10").as_float(), Some(10.0));
+        assert_eq!(CacheValue::new("inf").as_float(), None);
+        assert_eq!(CacheValue::new("abc").as_float(), None);
+    }
+
+    #[test]
+    fn test_serialize_formats() {
+        let value = CacheValue::new("hi");
+
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, "\"aGk=\"");
+        assert_eq!(serde_json::from_str::<CacheValue>(&json).unwrap(), value);
+        assert_eq!(
+            serde_json::from_str::<CacheValue>("[104, 105]").unwrap(),
+            value
+        );
+        assert!(serde_json::from_str::<CacheValue>("\"not base64!\"").is_err());
+
+        let packed = rmp_serde::to_vec(&value).unwrap();
+        assert_eq!(packed, vec![0xc4, 2, b'h', b'i']);
+        assert_eq!(rmp_serde::from_slice::<CacheValue>(&packed).unwrap(), value);
+    }
 }
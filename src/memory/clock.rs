@@ -0,0 +1,106 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+
+/// Ponto de partida do relógio do cache.
+///
+/// Guarda o `Instant` e o timestamp Unix em milissegundos do mesmo momento,
+/// assim o tempo decorrido vem de uma fonte monotônica enquanto os valores
+/// continuam proximos de um timestamp Unix comum.
+static ANCHOR: OnceLock<(Instant, i64)> = OnceLock::new();
+
+fn anchor() -> &'static (Instant, i64) {
+    ANCHOR.get_or_init(|| (Instant::now(), Utc::now().timestamp_millis()))
+}
+
+/// Retorna o timestamp atual do cache em milissegundos.
+///
+/// O valor nunca anda para tras, mesmo que o relógio do sistema seja ajustado
+/// por NTP ou horario de verão.
+pub fn now_millis() -> i64 {
+    let (instant, unix_millis) = anchor();
+    unix_millis + instant.elapsed().as_millis() as i64
+}
+
+/// Calcula o timestamp de expiração para um tempo de vida relativo ao momento atual.
+///
+/// Tempos de vida que não cabem em um `i64` ficam presos no maior timestamp.
+pub fn deadline_from_ttl(ttl: Duration) -> i64 {
+    let ttl_millis = i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX);
+    now_millis().saturating_add(ttl_millis)
+}
+
+/// Converte um timestamp Unix em milissegundos para o relógio do cache.
+///
+/// O tempo restante é medido contra o relógio do sistema no momento da
+/// conversão e a partir dai passa a ser contado pelo relógio monotônico.
+pub fn deadline_from_unix_millis(unix_millis: i64) -> i64 {
+    let remaining = unix_millis.saturating_sub(Utc::now().timestamp_millis());
+    now_millis().saturating_add(remaining)
+}
+
+/// Converte um timestamp do relógio do cache para um timestamp Unix em milissegundos.
+///
+/// Operação inversa de `deadline_from_unix_millis`, usada ao enviar prazos a outros nós.
+pub fn to_unix_millis(timestamp: i64) -> i64 {
+    let remaining = timestamp.saturating_sub(now_millis());
+    Utc::now().timestamp_millis().saturating_add(remaining)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn test_now_millis_is_monotonic() {
+        let first = now_millis();
+        std::thread::sleep(Duration::from_millis(5));
+        let second = now_millis();
+
+        assert!(second >= first + 5, "Clock should advance in milliseconds");
+    }
+
+    #[test]
+    fn test_deadline_from_large_ttl() {
+        assert_eq!(deadline_from_ttl(Duration::MAX), i64::MAX);
+        assert_eq!(
+            deadline_from_ttl(Duration::from_millis(u64::MAX)),
+            i64::MAX,
+            "Should saturate instead of wrapping"
+        );
+    }
+
+    #[test]
+    fn test_deadline_from_unix_millis() {
+        let unix_deadline = Utc::now().timestamp_millis() + 1_500;
+        let deadline = deadline_from_unix_millis(unix_deadline);
+        let remaining = deadline - now_millis();
+
+        assert!(
+            (1_400..=1_500).contains(&remaining),
+            "Remaining lifetime should be kept, got {}",
+            remaining
+        );
+    }
+
+    #[test]
+    fn test_to_unix_millis() {
+        let deadline = now_millis() + 2_000;
+        let unix_deadline = to_unix_millis(deadline);
+        let remaining = unix_deadline - Utc::now().timestamp_millis();
+
+        assert!(
+            (1_900..=2_000).contains(&remaining),
+            "Remaining lifetime should be kept, got {}",
+            remaining
+        );
+        assert!((deadline_from_unix_millis(unix_deadline) - deadline).abs() <= 5);
+    }
+}
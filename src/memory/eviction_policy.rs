@@ -0,0 +1,66 @@
+use std::fmt::Display;
+
+use super::{MemoryError, cache_entry::CacheEntry};
+
+/// Politicas de remoção aplicadas quando o `Store` atinge o limite de memória.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Nenhuma `key` é removida, novas escritas são rejeitadas.
+    NoEviction,
+    /// Remove as `keys` acessadas a mais tempo.
+    AllKeysLru,
+    /// Remove as `keys` acessadas com menos frequencia.
+    AllKeysLfu,
+    /// Remove somente `keys` com tempo de vida, as mais proximas de expirar primeiro.
+    VolatileTtl,
+    /// Remove `keys` aleatorias entre as sorteadas na amostra.
+    Random,
+}
+
+impl EvictionPolicy {
+    /// Pontuação da entrada para a politica, as menores pontuações são removidas primeiro.
+    ///
+    /// A amostra comparada ja é sorteada, por isso a politica aleatoria da a
+    /// mesma pontuação a todas as entradas. Retorna `None` quando a entrada não
+    /// pode ser removida pela politica.
+    pub fn score(&self, entry: &CacheEntry) -> Option<i64> {
+        match self {
+            EvictionPolicy::NoEviction => None,
+            EvictionPolicy::AllKeysLru => Some(entry.last_access()),
+            EvictionPolicy::AllKeysLfu => Some(entry.hits().min(i64::MAX as u64) as i64),
+            EvictionPolicy::VolatileTtl => entry.expires_at,
+            EvictionPolicy::Random => Some(0),
+        }
+    }
+}
+
+impl TryFrom<String> for EvictionPolicy {
+    type Error = MemoryError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            "random" => Ok(EvictionPolicy::Random),
+            _ => Err(MemoryError::ParseError(format!(
+                "invalid eviction policy: {}",
+                value
+            ))),
+        }
+    }
+}
+
+impl Display for EvictionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let policy_str = match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+            EvictionPolicy::Random => "random",
+        };
+        write!(f, "{}", policy_str)
+    }
+}
@@ -1,7 +1,53 @@
+mod cache_entry;
 mod cache_ttl_control;
 mod cache_value;
+pub mod clock;
+mod eviction_policy;
+mod mutation;
 mod store;
+mod store_stats;
+mod write_condition;
 
-pub use cache_ttl_control::*;
 pub use cache_value::*;
+pub use eviction_policy::*;
+pub use mutation::*;
 pub use store::*;
+pub use store_stats::*;
+pub use write_condition::*;
+
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+/// Intervalo padrão entre cada execução da limpeza ativa das `keys` expiradas.
+pub const EXPIRATION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tarefa de limpeza ativa do cache.
+///
+/// Roda indefinidamente removendo do `Store` as `keys` expiradas a cada intervalo.
+pub async fn start_expiration(store: Arc<Store>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        store.purge_expired().await;
+    }
+}
+
+#[derive(Debug)]
+pub enum MemoryError {
+    NotNumeric(String),
+    OutOfMemory(String),
+    Overflow(String),
+    ParseError(String),
+}
+
+impl std::error::Error for MemoryError {}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryError::NotNumeric(msg) => write!(f, "Not numeric: {}", msg),
+            MemoryError::OutOfMemory(msg) => write!(f, "Out of memory: {}", msg),
+            MemoryError::Overflow(msg) => write!(f, "Overflow: {}", msg),
+            MemoryError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use super::cache_value::CacheValue;
+
+/// Alteração aplicada no `Store`, enviada aos observadores na ordem em que acontece.
+///
+/// Os prazos de expiração são timestamps Unix em milissegundos, independentes
+/// do relógio do nó que gerou a alteração.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mutation", content = "data")]
+pub enum Mutation {
+    /// A `key` passou a ter o valor informado, substituindo o anterior e seu tempo de vida.
+    Set {
+        key: String,
+        value: CacheValue,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<i64>,
+    },
+    /// A `key` foi removida.
+    Delete(String),
+    /// O tempo de vida da `key` mudou, `None` quando ela deixou de expirar.
+    Expire {
+        key: String,
+        expires_at: Option<i64>,
+    },
+    /// Todas as `keys` foram removidas.
+    Clear,
+}
+
+/// Recebe as alterações do `Store` assim que são aplicadas.
+///
+/// É chamado com o lock do shard da `key` ainda travado, garantindo que as
+/// alterações de uma mesma `key` cheguem na ordem em que foram aplicadas.
+/// Por isso a implementação nunca deve bloquear ou acessar o `Store`.
+pub trait MutationObserver: Send + Sync {
+    fn on_mutation(&self, mutation: Mutation);
+}
@@ -1,11 +1,45 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicU64, Ordering},
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::{
+        Arc, Mutex, OnceLock, RwLock, RwLockReadGuard,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
 };
 
-use dashmap::DashMap;
+use dashmap::{DashMap, mapref::entry::Entry};
 
-use super::cache_value::CacheValue;
+use super::{
+    MemoryError,
+    cache_entry::{CacheEntry, ENTRY_OVERHEAD},
+    cache_ttl_control::CacheTTLControl,
+    cache_value::CacheValue,
+    clock,
+    eviction_policy::EvictionPolicy,
+    mutation::{Mutation, MutationObserver},
+    store_stats::StoreStats,
+    write_condition::{WriteCondition, WriteOutcome},
+};
+
+/// Percentual do limite de memória buscado ao remover `keys`.
+///
+/// Remover um pouco alem do necessario evita que cada escrita seguinte
+/// precise remover `keys` novamente.
+const EVICTION_TARGET_PERCENT: u64 = 95;
+
+/// Quantidade de entradas sorteadas para escolher cada `key` removida.
+const EVICTION_SAMPLES: usize = 16;
+
+/// Sorteios feitos por entrada da amostra antes de desistir de completa-la.
+const EVICTION_DRAWS_PER_SAMPLE: usize = 8;
+
+/// Amostras seguidas sem nenhuma `key` removivel antes de rejeitar a escrita.
+const EVICTION_MAX_MISSES: usize = 8;
+
+/// Abaixo dessa quantidade de `keys` a amostra é o mapa inteiro.
+const EVICTION_FULL_SCAN_LEN: u64 = 1024;
+
+/// Tamanho, em bytes, verificado antes do calculo para a representação decimal de um numero.
+const MAX_NUMBER_LEN: u64 = 32;
 
 /// Struct Gerenciadora do Cache.
 ///
@@ -15,56 +49,850 @@ use super::cache_value::CacheValue;
 pub struct Store {
     /// Mostra o tamanho do cache atualmente retornando o numero de itens.
     length: AtomicU64,
+    /// Quantidade aproximada de bytes ocupados pelas entradas do cache.
+    used_memory: AtomicU64,
+    /// Soma exata do tamanho dos valores armazenados.
+    value_bytes: AtomicU64,
+    /// Quantidade de `keys` que possuem tempo de vida.
+    volatile_keys: AtomicU64,
+    /// Leituras que encontraram a `key`.
+    hits: AtomicU64,
+    /// Leituras que não encontraram a `key` ou a encontraram expirada.
+    misses: AtomicU64,
+    /// Ultima versão entregue a uma escrita, cresce a cada escrita em qualquer `key`.
+    last_version: AtomicU64,
+    /// Limite de memória em bytes, `0` desabilita o limite.
+    max_memory: u64,
+    /// Politica usada para liberar memória quando o limite é atingido.
+    eviction_policy: EvictionPolicy,
+    /// Barreira entre as escritas e `clear`.
+    ///
+    /// Toda alteração do mapa segura a barreira para leitura enquanto é
+    /// aplicada e enviada ao observador, `clear` a segura para escrita.
+    clear_barrier: RwLock<()>,
+    /// Garante que somente uma remoção por limite de memória rode por vez.
+    eviction_lock: Mutex<()>,
+    /// Gerador dos sorteios das amostras de remoção.
+    eviction_hasher: RandomState,
+    /// Contador alimentado ao `eviction_hasher` a cada sorteio.
+    eviction_draws: AtomicU64,
     /// Cache em memoria usando DashMap para uma abordagem mais limpa
     /// enquanto mantem Safe Thread e imutabilidade local.
-    memory_map: Arc<DashMap<String, CacheValue>>,
+    memory_map: Arc<DashMap<String, CacheEntry>>,
+    /// Controle do tempo de vida das `keys` que possuem expiração.
+    ttl_control: CacheTTLControl,
+    /// Recebe cada alteração aplicada, como o log de replicação.
+    observer: OnceLock<Arc<dyn MutationObserver>>,
+    /// Quando ativo as `keys` expiradas somente são escondidas e esperam a
+    /// remoção enviada pelo master, como acontece nos slaves.
+    expiry_from_master: AtomicBool,
 }
 
 impl Store {
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_limit(0, EvictionPolicy::NoEviction)
+    }
+
+    /// Cria o cache limitado a `max_memory` bytes, liberando espaço com a politica informada.
+    pub fn with_limit(max_memory: u64, eviction_policy: EvictionPolicy) -> Self {
         Self {
             length: AtomicU64::new(0),
+            used_memory: AtomicU64::new(0),
+            value_bytes: AtomicU64::new(0),
+            volatile_keys: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            last_version: AtomicU64::new(0),
+            max_memory,
+            eviction_policy,
+            clear_barrier: RwLock::new(()),
+            eviction_lock: Mutex::new(()),
+            eviction_hasher: RandomState::new(),
+            eviction_draws: AtomicU64::new(0),
             memory_map: Arc::new(DashMap::new()),
+            ttl_control: CacheTTLControl::new(),
+            observer: OnceLock::new(),
+            expiry_from_master: AtomicBool::new(false),
         }
     }
 
+    /// Define se a remoção das `keys` expiradas vem do master.
+    ///
+    /// Com ela ativa o cache nunca remove uma `key` por expiração, nem na
+    /// leitura nem na limpeza ativa, mas continua tratando as expiradas como
+    /// inexistentes. Assim master e slaves concordam sobre quais `keys`
+    /// existem mesmo com relógios diferentes.
+    pub fn set_expiry_from_master(&self, enabled: bool) {
+        self.expiry_from_master.store(enabled, Ordering::Release);
+    }
+
+    fn expiry_from_master(&self) -> bool {
+        self.expiry_from_master.load(Ordering::Acquire)
+    }
+
+    /// Registra o observador que recebera todas as alterações do cache.
+    ///
+    /// Somente um observador pode ser registrado, retorna `false` se ja existir um.
+    pub fn observe(&self, observer: Arc<dyn MutationObserver>) -> bool {
+        self.observer.set(observer).is_ok()
+    }
+
     /// Busca o tamanho atual do mapa na memória.
     pub fn len(&self) -> u64 {
         self.length.load(Ordering::Acquire)
     }
 
+    /// Busca a quantidade aproximada de bytes ocupados pelo cache.
+    pub fn used_memory(&self) -> u64 {
+        self.used_memory.load(Ordering::Acquire)
+    }
+
+    /// Monta uma fotografia dos contadores do cache.
+    ///
+    /// Cada contador é lido de forma independente, com escritas concorrentes
+    /// os valores podem representar momentos levemente diferentes.
+    pub fn stats(&self) -> StoreStats {
+        StoreStats {
+            keys: self.len(),
+            value_bytes: self.value_bytes.load(Ordering::Acquire),
+            used_memory: self.used_memory(),
+            max_memory: self.max_memory,
+            eviction_policy: self.eviction_policy.to_string(),
+            volatile_keys: self.volatile_keys.load(Ordering::Acquire),
+            hits: self.hits.load(Ordering::Acquire),
+            misses: self.misses.load(Ordering::Acquire),
+        }
+    }
+
     /// Busca um item no cache com base em uma `key`.
     ///
     /// O mapa ira criar um guard protegendo a referencia ate o fim dessa função,
     /// o clone irá garantir que receberemos uma copia do valor valida enquanto o
     /// guard é dropado.
+    ///
+    /// Uma `key` expirada nunca é devolvida, mesmo que a limpeza ativa ainda não
+    /// tenha passado por ela, nesse caso ela é removida aqui mesmo.
     pub fn get(&self, key: &str) -> Option<CacheValue> {
-        self.memory_map.get(key).map(|guard| guard.value().clone())
+        self.get_versioned(key).map(|(value, _)| value)
+    }
+
+    /// Busca um item no cache junto a versão da escrita que o gerou.
+    ///
+    /// A versão pode ser usada depois em `WriteCondition::Version` para um compare-and-swap.
+    pub fn get_versioned(&self, key: &str) -> Option<(CacheValue, u64)> {
+        let now_timestamp = clock::now_millis();
+        let hit = self.memory_map.get(key).map(|guard| {
+            let entry = guard.value();
+            if entry.is_expired(now_timestamp) {
+                return None;
+            }
+            entry.touch(now_timestamp);
+            Some((entry.value.clone(), entry.version))
+        });
+
+        match hit {
+            Some(Some(value)) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            }
+            Some(None) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.delete_expired(key, now_timestamp);
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
     }
 
     /// Insere um novo valor no cache enquanto aumenta o tamanho de length.
-    pub fn set(&self, key: String, value: CacheValue) {
-        self.memory_map.insert(key, value);
-        self.length.fetch_add(1, Ordering::AcqRel);
+    ///
+    /// O valor inserido não possui tempo de vida, substituindo qualquer expiração anterior da `key`.
+    pub fn set(&self, key: String, value: CacheValue) -> Result<(), MemoryError> {
+        let entry = CacheEntry::new(value, None, clock::now_millis());
+        self.write_entry(key, entry, None).map(|_| ())
     }
 
-    /// Remove um valor no cache, se for encontrado um valor com a `key`, é diminuido o valor de length.
-    pub fn delete(&self, key: &str) {
-        if self.memory_map.remove(key).is_some() {
-            self.length.fetch_sub(1, Ordering::AcqRel);
+    /// Verifica se a `key` existe e não expirou, sem contar como leitura nas estatisticas.
+    pub fn exists(&self, key: &str) -> bool {
+        let now_timestamp = clock::now_millis();
+        self.memory_map
+            .get(key)
+            .is_some_and(|entry| !entry.is_expired(now_timestamp))
+    }
+
+    /// Tempo de vida restante da `key` em milissegundos.
+    ///
+    /// Retorna `None` quando a `key` não existe e `Some(None)` quando ela não expira.
+    pub fn ttl(&self, key: &str) -> Option<Option<i64>> {
+        let now_timestamp = clock::now_millis();
+        let entry = self.memory_map.get(key)?;
+        if entry.is_expired(now_timestamp) {
+            return None;
+        }
+        Some(
+            entry
+                .expires_at
+                .map(|expires_at| (expires_at - now_timestamp).max(0)),
+        )
+    }
+
+    /// Altera o timestamp em que uma `key` existente expira, `None` remove a expiração.
+    ///
+    /// O valor e a versão da `key` são mantidos. Retorna `false` quando a `key` não existe.
+    /// Com a expiração vinda do master, uma `key` ja expirada localmente ainda
+    /// é alterada, ja que para o master ela pode continuar valida.
+    pub async fn expire(&self, key: &str, expires_at: Option<i64>) -> bool {
+        let now_timestamp = clock::now_millis();
+        let from_master = self.expiry_from_master();
+        let updated = {
+            let _clear_guard = self.write_guard();
+            match self.memory_map.get_mut(key) {
+                Some(mut entry) if from_master || !entry.is_expired(now_timestamp) => {
+                    match (entry.expires_at.is_some(), expires_at.is_some()) {
+                        (false, true) => {
+                            self.volatile_keys.fetch_add(1, Ordering::AcqRel);
+                        }
+                        (true, false) => {
+                            self.volatile_keys.fetch_sub(1, Ordering::AcqRel);
+                        }
+                        _ => {}
+                    }
+                    entry.expires_at = expires_at;
+                    self.emit(|| Mutation::Expire {
+                        key: key.to_string(),
+                        expires_at: expires_at.map(clock::to_unix_millis),
+                    });
+                    true
+                }
+                _ => false,
+            }
         };
+
+        if let (true, Some(timestamp)) = (updated, expires_at) {
+            self.ttl_control.set(timestamp, key.to_string()).await;
+        }
+        updated
+    }
+
+    /// Busca varias `keys` de uma vez, na mesma ordem em que foram pedidas.
+    ///
+    /// Cada posição traz `None` quando a `key` correspondente não existe.
+    pub fn get_many(&self, keys: &[String]) -> Vec<Option<CacheValue>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Insere varios valores sem tempo de vida de uma vez.
+    ///
+    /// A escrita é tudo ou nada: o espaço de todas as entradas é reservado
+    /// antes da primeira escrita, se não couberem nada é escrito. Leitores
+    /// concorrentes ainda podem ver parte das `keys` antes do fim da operação.
+    pub fn set_many(&self, items: Vec<(String, CacheValue)>) -> Result<(), MemoryError> {
+        let now_timestamp = clock::now_millis();
+        let entries: Vec<(String, CacheEntry)> = items
+            .into_iter()
+            .map(|(key, value)| (key, CacheEntry::new(value, None, now_timestamp)))
+            .collect();
+
+        let size = entries
+            .iter()
+            .map(|(key, entry)| self.growth(key, entry.size(key)))
+            .sum();
+        let keys: Vec<&str> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        self.reserve(size, &keys)?;
+
+        for (key, entry) in entries {
+            self.write_reserved(key, entry, None);
+        }
+        Ok(())
+    }
+
+    /// Insere um novo valor no cache que expira no timestamp informado.
+    ///
+    /// O timestamp esta em milissegundos no relógio do cache, timestamps Unix
+    /// devem ser convertidos antes por `clock::deadline_from_unix_millis`.
+    ///
+    /// O timestamp tambem é registrado no `CacheTTLControl` para que a limpeza
+    /// ativa remova a `key` sem depender de uma leitura.
+    pub async fn set_with_deadline(
+        &self,
+        key: String,
+        value: CacheValue,
+        timestamp: i64,
+    ) -> Result<(), MemoryError> {
+        self.write(key, value, Some(timestamp), None)
+            .await
+            .map(|_| ())
+    }
+
+    /// Escreve um valor no cache se a condição for atendida.
+    ///
+    /// A condição é avaliada e o valor escrito com o lock do shard da `key`
+    /// no DashMap, nenhuma outra escrita na mesma `key` acontece entre os dois.
+    /// Sem condição a escrita sempre acontece, como em `set`.
+    ///
+    /// Retorna o valor anterior e a nova versão quando escrito.
+    pub async fn write(
+        &self,
+        key: String,
+        value: CacheValue,
+        expires_at: Option<i64>,
+        condition: Option<&WriteCondition>,
+    ) -> Result<WriteOutcome, MemoryError> {
+        let entry = CacheEntry::new(value, expires_at, clock::now_millis());
+        let outcome = self.write_entry(key.clone(), entry, condition)?;
+
+        if let (Some(timestamp), WriteOutcome::Written { .. }) = (expires_at, &outcome) {
+            self.ttl_control.set(timestamp, key).await;
+        }
+        Ok(outcome)
+    }
+
+    /// Soma `delta` ao inteiro decimal armazenado na `key` e devolve o novo valor.
+    ///
+    /// Uma `key` inexistente começa em `0` e o tempo de vida atual é mantido.
+    /// Um valor que não é inteiro, ou um resultado fora de 64 bits, é rejeitado
+    /// sem alterar a `key`.
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64, MemoryError> {
+        self.update_number(key, |current| {
+            let number = match current {
+                Some(value) => value
+                    .as_integer()
+                    .ok_or_else(|| MemoryError::NotNumeric("value is not an integer".into()))?,
+                None => 0,
+            };
+            let result = number.checked_add(delta).ok_or_else(|| {
+                MemoryError::Overflow("increment or decrement would overflow".into())
+            })?;
+            Ok((CacheValue::new(result.to_string()), result))
+        })
+    }
+
+    /// Soma `delta` ao numero decimal armazenado na `key` e devolve o novo valor.
+    ///
+    /// Segue as mesmas regras de `incr_by`, aceitando valores com casas decimais.
+    pub fn incr_by_float(&self, key: &str, delta: f64) -> Result<f64, MemoryError> {
+        self.update_number(key, |current| {
+            let number = match current {
+                Some(value) => value
+                    .as_float()
+                    .ok_or_else(|| MemoryError::NotNumeric("value is not a number".into()))?,
+                None => 0.0,
+            };
+            let result = number + delta;
+            if !result.is_finite() {
+                return Err(MemoryError::Overflow(
+                    "increment would produce NaN or Infinity".into(),
+                ));
+            }
+            Ok((CacheValue::new(result.to_string()), result))
+        })
+    }
+
+    /// Remove um valor no cache, se for encontrado um valor com a `key`, é diminuido o valor de length.
+    ///
+    /// Retorna `true` quando a `key` existia e foi removida. Uma `key` expirada
+    /// que ainda não foi limpa também é removida, como em `delete_expired`, mas
+    /// conta como inexistente.
+    pub fn delete(&self, key: &str) -> bool {
+        let now_timestamp = clock::now_millis();
+        let _clear_guard = self.write_guard();
+        match self.memory_map.entry(key.to_string()) {
+            Entry::Occupied(occupied) => {
+                let expired = occupied.get().is_expired(now_timestamp);
+                self.emit(|| Mutation::Delete(occupied.key().clone()));
+                let (key, entry) = occupied.remove_entry();
+                self.release(&key, &entry);
+                !expired
+            }
+            Entry::Vacant(_) => false,
+        }
+    }
+
+    /// Remove varias `keys` de uma vez, retornando quantas existiam.
+    pub fn delete_many(&self, keys: &[String]) -> u64 {
+        keys.iter().filter(|key| self.delete(key)).count() as u64
     }
 
     /// Limpa todas as `key/value` da memoria e zera o valor de length.
+    ///
+    /// A limpeza segura a barreira das escritas com exclusividade, as escritas
+    /// concorrentes acontecem inteiras antes dela, e os observadores as recebem
+    /// antes de `Mutation::Clear`, ou inteiras depois. Assim quem aplica as
+    /// alterações na ordem recebida chega ao mesmo mapa.
     pub fn clear(&self) {
-        self.memory_map.clear();
-        self.length.store(0, Ordering::Release);
+        let _clear_guard = self
+            .clear_barrier
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        self.emit(|| Mutation::Clear);
+        self.memory_map.retain(|key, entry| {
+            self.release(key, entry);
+            false
+        });
+    }
+
+    /// Limpeza ativa das `keys` expiradas.
+    ///
+    /// Busca no `CacheTTLControl` os timestamps vencidos e remove do cache as
+    /// `keys` correspondentes. Uma `key` so é removida se a entrada atual ainda
+    /// estiver expirada, evitando apagar um valor que foi sobrescrito depois.
+    ///
+    /// Cada remoção é enviada aos observadores como `Mutation::Delete`. Não
+    /// remove nada enquanto a expiração vier do master, nesse caso somente as
+    /// `keys` que ainda esperam a remoção do master voltam para o controle,
+    /// as ja removidas saem dele.
+    ///
+    /// Retorna a quantidade de `keys` removidas.
+    pub async fn purge_expired(&self) -> u64 {
+        let Some(expired_keys) = self.ttl_control.cleanup_expired().await else {
+            return 0;
+        };
+
+        let now_timestamp = clock::now_millis();
+        if self.expiry_from_master() {
+            for key in expired_keys {
+                let expires_at = self
+                    .memory_map
+                    .get(&key)
+                    .and_then(|entry| entry.expires_at)
+                    .filter(|expires_at| *expires_at < now_timestamp);
+                if let Some(expires_at) = expires_at {
+                    self.ttl_control.set(expires_at, key).await;
+                }
+            }
+            return 0;
+        }
+
+        expired_keys
+            .iter()
+            .filter(|key| self.delete_expired(key, now_timestamp))
+            .count() as u64
+    }
+
+    /// Quantidade de shards do mapa, usada para copia-lo com `snapshot_shard`.
+    pub fn shard_count(&self) -> usize {
+        self.memory_map.shards().len()
+    }
+
+    /// Copia as `keys` validas de um shard como alterações `Mutation::Set`, com
+    /// seus prazos de expiração.
+    ///
+    /// Usada na sincronização completa de um slave, que copia o mapa um shard
+    /// por vez sem precisar de uma copia inteira dele. Escritas concorrentes em
+    /// outros shards podem ou não aparecer na copia.
+    pub fn snapshot_shard(&self, index: usize) -> Vec<Mutation> {
+        let now_timestamp = clock::now_millis();
+        let shard = self.memory_map.shards()[index].read();
+        // SAFETY: o iterador e as referencias não sobrevivem ao guard de leitura do shard.
+        unsafe {
+            shard
+                .iter()
+                .map(|bucket| bucket.as_ref())
+                .filter(|(_, entry)| !entry.get().is_expired(now_timestamp))
+                .map(|(key, entry)| entry.get().mutation(key))
+                .collect()
+        }
+    }
+
+    /// Aplica uma alteração gerada por outro nó, como o master da replicação.
+    ///
+    /// Os prazos recebidos como timestamps Unix são convertidos para o relógio local.
+    /// Assim como no Redis, o limite de memória não vale para as alterações
+    /// replicadas, que nunca removem `keys` nem são rejeitadas por falta de
+    /// memória, senão o slave deixaria de ter os mesmos dados do master.
+    pub async fn apply(&self, mutation: Mutation) -> Result<(), MemoryError> {
+        match mutation {
+            Mutation::Set {
+                key,
+                value,
+                expires_at,
+            } => {
+                let expires_at = expires_at.map(clock::deadline_from_unix_millis);
+                let entry = CacheEntry::new(value, expires_at, clock::now_millis());
+                self.write_reserved(key.clone(), entry, None);
+                if let Some(timestamp) = expires_at {
+                    self.ttl_control.set(timestamp, key).await;
+                }
+            }
+            Mutation::Delete(key) => {
+                self.delete(&key);
+            }
+            Mutation::Expire { key, expires_at } => {
+                let expires_at = expires_at.map(clock::deadline_from_unix_millis);
+                self.expire(&key, expires_at).await;
+            }
+            Mutation::Clear => self.clear(),
+        }
+        Ok(())
+    }
+
+    /// Lista as `keys` validas aceitas pelo filtro, como as de um hash slot.
+    pub fn keys_where(&self, filter: impl Fn(&str) -> bool) -> Vec<String> {
+        let now_timestamp = clock::now_millis();
+        self.memory_map
+            .iter()
+            .filter(|item| !item.value().is_expired(now_timestamp) && filter(item.key()))
+            .map(|item| item.key().clone())
+            .collect()
+    }
+
+    /// Remove a `key` devolvendo a alteração que a recria em outro nó.
+    ///
+    /// A remoção é enviada aos observadores como `Mutation::Delete`. Retorna
+    /// `None` quando a `key` não existe ou ja expirou.
+    pub fn take(&self, key: &str) -> Option<Mutation> {
+        let now_timestamp = clock::now_millis();
+        let _clear_guard = self.write_guard();
+        match self.memory_map.entry(key.to_string()) {
+            Entry::Occupied(occupied) => {
+                self.emit(|| Mutation::Delete(occupied.key().clone()));
+                let (key, entry) = occupied.remove_entry();
+                self.release(&key, &entry);
+                (!entry.is_expired(now_timestamp)).then(|| entry.mutation(&key))
+            }
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    /// Aplica uma `Mutation::Set` recebida na migração de um slot somente se
+    /// a `key` ainda não existir, preservando escritas feitas aqui durante a migração.
+    ///
+    /// Retorna `true` quando a `key` foi escrita.
+    pub async fn import(&self, mutation: Mutation) -> Result<bool, MemoryError> {
+        let Mutation::Set {
+            key,
+            value,
+            expires_at,
+        } = mutation
+        else {
+            self.apply(mutation).await?;
+            return Ok(true);
+        };
+        let expires_at = expires_at.map(clock::deadline_from_unix_millis);
+        let outcome = self
+            .write(key, value, expires_at, Some(&WriteCondition::Nx))
+            .await?;
+        Ok(matches!(outcome, WriteOutcome::Written { .. }))
+    }
+
+    /// Remove a `key` somente se a entrada ainda estiver expirada no momento da remoção.
+    ///
+    /// A remoção é enviada aos observadores sob o lock do shard, na ordem
+    /// correta em relação as escritas seguintes na mesma `key`.
+    fn delete_expired(&self, key: &str, now_timestamp: i64) -> bool {
+        if self.expiry_from_master() {
+            return false;
+        }
+        let _clear_guard = self.write_guard();
+        match self.memory_map.entry(key.to_string()) {
+            Entry::Occupied(occupied) if occupied.get().is_expired(now_timestamp) => {
+                self.emit(|| Mutation::Delete(occupied.key().clone()));
+                let (key, entry) = occupied.remove_entry();
+                self.release(&key, &entry);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Insere a entrada no mapa garantindo espaço dentro do limite de memória.
+    ///
+    /// O espaço é reservado antes de travar o shard da `key`, assim uma remoção
+    /// por limite de memória nunca espera pelo lock que a propria escrita segura.
+    /// A condição é verificada antes da reserva, uma escrita que ja seria
+    /// rejeitada não remove `keys` nem falha por falta de memória, e verificada
+    /// de novo com o lock do shard.
+    fn write_entry(
+        &self,
+        key: String,
+        entry: CacheEntry,
+        condition: Option<&WriteCondition>,
+    ) -> Result<WriteOutcome, MemoryError> {
+        let now_timestamp = clock::now_millis();
+        let growth = {
+            let existing = self.memory_map.get(&key);
+            let current = existing.as_deref().filter(|e| !e.is_expired(now_timestamp));
+            if condition.is_some_and(|c| !c.matches(current)) {
+                return Ok(WriteOutcome::Rejected);
+            }
+            let current_size = existing.map_or(0, |e| e.size(&key));
+            entry.size(&key).saturating_sub(current_size)
+        };
+
+        self.reserve(growth, &[&key])?;
+        Ok(self.write_reserved(key, entry, condition))
+    }
+
+    /// Escreve a entrada no mapa assumindo que o espaço ja foi reservado.
+    fn write_reserved(
+        &self,
+        key: String,
+        mut entry: CacheEntry,
+        condition: Option<&WriteCondition>,
+    ) -> WriteOutcome {
+        let now_timestamp = clock::now_millis();
+        let _clear_guard = self.write_guard();
+        match self.memory_map.entry(key) {
+            Entry::Occupied(mut occupied) => {
+                let current = Some(occupied.get()).filter(|e| !e.is_expired(now_timestamp));
+                if condition.is_some_and(|c| !c.matches(current)) {
+                    return WriteOutcome::Rejected;
+                }
+                let previous_value = current.map(|e| e.value.clone());
+
+                entry.version = self.next_version();
+                let version = entry.version;
+                self.emit(|| entry.mutation(occupied.key()));
+                self.account(occupied.key(), &entry);
+                let previous = occupied.insert(entry);
+                self.release(occupied.key(), &previous);
+
+                WriteOutcome::Written {
+                    previous: previous_value,
+                    version,
+                }
+            }
+            Entry::Vacant(vacant) => {
+                if condition.is_some_and(|c| !c.matches(None)) {
+                    return WriteOutcome::Rejected;
+                }
+
+                entry.version = self.next_version();
+                let version = entry.version;
+                self.emit(|| entry.mutation(vacant.key()));
+                self.account(vacant.key(), &entry);
+                vacant.insert(entry);
+
+                WriteOutcome::Written {
+                    previous: None,
+                    version,
+                }
+            }
+        }
+    }
+
+    /// Substitui o valor numerico da `key` com o lock do shard, tornando a
+    /// leitura, o calculo e a escrita uma unica operação atomica.
+    ///
+    /// `compute` recebe o valor atual, `None` quando a `key` não existe ou
+    /// expirou, e devolve o novo valor junto ao resultado da operação. O espaço
+    /// verificado antes do lock é o de `MAX_NUMBER_LEN`, quando o novo valor
+    /// passa disso, como um float grande, a verificação é refeita com o tamanho
+    /// dele e o calculo repetido.
+    fn update_number<T>(
+        &self,
+        key: &str,
+        compute: impl Fn(Option<&CacheValue>) -> Result<(CacheValue, T), MemoryError>,
+    ) -> Result<T, MemoryError> {
+        let mut number_len = MAX_NUMBER_LEN;
+        loop {
+            let size = key.len() as u64 + number_len + ENTRY_OVERHEAD;
+            self.reserve(self.growth(key, size), &[key])?;
+
+            let now_timestamp = clock::now_millis();
+            let _clear_guard = self.write_guard();
+            let slot = self.memory_map.entry(key.to_string());
+            let current = match &slot {
+                Entry::Occupied(occupied) => {
+                    Some(occupied.get()).filter(|e| !e.is_expired(now_timestamp))
+                }
+                Entry::Vacant(_) => None,
+            };
+            let expires_at = current.and_then(|e| e.expires_at);
+            let (value, result) = compute(current.map(|e| &e.value))?;
+            if value.as_bytes().len() as u64 > number_len {
+                number_len = value.as_bytes().len() as u64;
+                continue;
+            }
+
+            let mut entry = CacheEntry::new(value, expires_at, now_timestamp);
+            entry.version = self.next_version();
+            match slot {
+                Entry::Occupied(mut occupied) => {
+                    self.emit(|| entry.mutation(occupied.key()));
+                    self.account(occupied.key(), &entry);
+                    let previous = occupied.insert(entry);
+                    self.release(occupied.key(), &previous);
+                }
+                Entry::Vacant(vacant) => {
+                    self.emit(|| entry.mutation(vacant.key()));
+                    self.account(vacant.key(), &entry);
+                    vacant.insert(entry);
+                }
+            }
+            return Ok(result);
+        }
+    }
+
+    /// Segura a barreira de `clear` para uma escrita.
+    ///
+    /// Deve envolver somente a alteração do mapa, nunca a reserva de memória,
+    /// ja que a remoção por limite de memória também segura a barreira.
+    fn write_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.clear_barrier.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Entrega a alteração ao observador, montando-a somente quando existe um.
+    fn emit(&self, mutation: impl FnOnce() -> Mutation) {
+        if let Some(observer) = self.observer.get() {
+            observer.on_mutation(mutation());
+        }
+    }
+
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Soma nos contadores uma entrada inserida no mapa.
+    ///
+    /// Ao sobrescrever uma `key` a entrada anterior é descontada por `release`,
+    /// assim length e os demais contadores nunca contam a mesma `key` duas vezes.
+    fn account(&self, key: &str, entry: &CacheEntry) {
+        self.used_memory
+            .fetch_add(entry.size(key), Ordering::AcqRel);
+        self.value_bytes
+            .fetch_add(entry.value.as_bytes().len() as u64, Ordering::AcqRel);
+        if entry.expires_at.is_some() {
+            self.volatile_keys.fetch_add(1, Ordering::AcqRel);
+        }
+        self.length.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Desconta dos contadores uma entrada removida do mapa.
+    fn release(&self, key: &str, entry: &CacheEntry) {
+        self.used_memory
+            .fetch_sub(entry.size(key), Ordering::AcqRel);
+        self.value_bytes
+            .fetch_sub(entry.value.as_bytes().len() as u64, Ordering::AcqRel);
+        if entry.expires_at.is_some() {
+            self.volatile_keys.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.length.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Quanto a `key` cresce ao passar a ocupar `size` bytes.
+    ///
+    /// Sobrescrever uma `key` so precisa reservar o que passar do tamanho atual dela.
+    fn growth(&self, key: &str, size: u64) -> u64 {
+        let current = self.memory_map.get(key).map_or(0, |entry| entry.size(key));
+        size.saturating_sub(current)
+    }
+
+    /// Garante que `size` bytes caibam no limite de memória.
+    ///
+    /// Quando o limite seria ultrapassado, remove `keys` conforme a politica ate
+    /// ficar abaixo de `EVICTION_TARGET_PERCENT` do limite, sem nunca remover as
+    /// `keys` em `protected`, que são as que estão sendo escritas. Com
+    /// `NoEviction`, ou quando a politica não encontra `keys` suficientes, a
+    /// escrita é rejeitada.
+    fn reserve(&self, size: u64, protected: &[&str]) -> Result<(), MemoryError> {
+        if self.max_memory == 0 || self.used_memory() + size <= self.max_memory {
+            return Ok(());
+        }
+
+        if self.eviction_policy != EvictionPolicy::NoEviction {
+            let _eviction_guard = self.eviction_lock.lock().unwrap_or_else(|e| e.into_inner());
+            if self.used_memory() + size > self.max_memory {
+                let target = (self.max_memory * EVICTION_TARGET_PERCENT / 100).saturating_sub(size);
+                self.evict(target, protected);
+            }
+        }
+
+        if self.used_memory() + size > self.max_memory {
+            return Err(MemoryError::OutOfMemory(format!(
+                "used {} of {} bytes with {} policy",
+                self.used_memory(),
+                self.max_memory,
+                self.eviction_policy
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Remove `keys` ate que o uso de memória chegue em `target` bytes.
+    ///
+    /// Cada remoção escolhe, pela politica, a melhor entre as entradas de uma
+    /// amostra sorteada, como no Redis, assim o custo não cresce com o tamanho
+    /// do mapa. Desiste depois de `EVICTION_MAX_MISSES` amostras seguidas sem
+    /// nenhuma `key` removivel.
+    fn evict(&self, target: u64, protected: &[&str]) {
+        let mut misses = 0;
+        while self.used_memory() > target && misses < EVICTION_MAX_MISSES {
+            let victim = self
+                .sample(EVICTION_SAMPLES)
+                .into_iter()
+                .filter(|(key, _)| !protected.contains(&key.as_str()))
+                .min_by_key(|(_, score)| *score);
+            match victim {
+                Some((key, _)) => {
+                    misses = 0;
+                    self.delete(&key);
+                }
+                None => misses += 1,
+            }
+        }
+    }
+
+    /// Sorteia ate `count` entradas do mapa, devolvendo as que a politica pode
+    /// remover junto a sua pontuação.
+    ///
+    /// Cada sorteio escolhe um shard e uma posição da tabela dele, e so o
+    /// shard sorteado fica travado para leitura. Mapas pequenos, com muitas
+    /// posições vazias, são percorridos inteiros.
+    fn sample(&self, count: usize) -> Vec<(String, i64)> {
+        if self.len() <= EVICTION_FULL_SCAN_LEN {
+            return self
+                .memory_map
+                .iter()
+                .filter_map(|item| {
+                    let score = self.eviction_policy.score(item.value())?;
+                    Some((item.key().clone(), score))
+                })
+                .collect();
+        }
+
+        let shards = self.memory_map.shards();
+        let mut sample = Vec::with_capacity(count);
+        let mut sampled = 0;
+        for _ in 0..count * EVICTION_DRAWS_PER_SAMPLE {
+            if sampled == count {
+                break;
+            }
+            let draw = self.draw();
+            let shard = shards[draw as usize % shards.len()].read();
+            let index = (draw >> 32) as usize % shard.buckets();
+            // SAFETY: `index` é menor que `buckets()`, a posição so é lida quando
+            // ocupada e a referencia não sobrevive ao guard de leitura do shard.
+            let Some((key, entry)) = (unsafe {
+                shard
+                    .is_bucket_full(index)
+                    .then(|| shard.bucket(index).as_ref())
+            }) else {
+                continue;
+            };
+            sampled += 1;
+            if let Some(score) = self.eviction_policy.score(entry.get()) {
+                sample.push((key.clone(), score));
+            }
+        }
+        sample
+    }
+
+    /// Numero pseudo aleatorio para os sorteios da remoção.
+    fn draw(&self) -> u64 {
+        let draw = self.eviction_draws.fetch_add(1, Ordering::Relaxed);
+        self.eviction_hasher.hash_one(draw)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
+    use std::{thread, time::Duration};
 
     use super::*;
 
@@ -75,7 +903,9 @@ mod tests {
         let key = "test_key";
         let value = CacheValue::new("value");
 
-        store.set(key.to_string(), value.clone());
+        store
+            .set(key.to_string(), value.clone())
+            .expect("Should insert value");
         let hit = store.get(key).unwrap();
 
         // Same value as CacheValue
@@ -96,7 +926,9 @@ mod tests {
                 let store = store.clone();
                 let task = s.spawn(move || {
                     let value = CacheValue::new(format!("value-{i}"));
-                    store.set(format!("key-{}", i), value);
+                    store
+                        .set(format!("key-{}", i), value)
+                        .expect("Should insert value");
                 });
                 tasks.push(task);
             }
@@ -133,7 +965,9 @@ mod tests {
                 let store = store.clone();
                 let task = s.spawn(move || {
                     let value = CacheValue::new(format!("value-{i}"));
-                    store.set(format!("key-{}", i), value);
+                    store
+                        .set(format!("key-{}", i), value)
+                        .expect("Should insert value");
                 });
                 tasks.push(task);
             }
@@ -164,7 +998,9 @@ mod tests {
                 let store = store.clone();
                 let task = s.spawn(move || {
                     let value = CacheValue::new(format!("value-{i}"));
-                    store.set(format!("key-{}", i), value);
+                    store
+                        .set(format!("key-{}", i), value)
+                        .expect("Should insert value");
                 });
                 tasks.push(task);
             }
@@ -178,4 +1014,777 @@ mod tests {
 
         assert_eq!(0, store.len())
     }
+
+    #[tokio::test]
+    async fn test_expired_key_misses_before_cleanup() {
+        let store = Store::new();
+        let past = clock::now_millis() - 2_000;
+        let future = clock::now_millis() + 10_000;
+
+        store
+            .set_with_deadline("expired".into(), CacheValue::new("value"), past)
+            .await
+            .expect("Should insert value");
+        store
+            .set_with_deadline("alive".into(), CacheValue::new("value"), future)
+            .await
+            .expect("Should insert value");
+
+        assert_eq!(store.get("expired"), None, "Expired key should miss");
+        assert_eq!(store.len(), 1, "Expired key should be removed on read");
+        assert_eq!(store.get("alive"), Some(CacheValue::new("value")));
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_key_before_purge() {
+        let store = Store::new();
+        let past = clock::now_millis() - 2_000;
+
+        store
+            .set_with_deadline("expired".into(), CacheValue::new("value"), past)
+            .await
+            .expect("Should insert value");
+        store.set("alive".into(), CacheValue::new("value")).unwrap();
+
+        assert!(!store.delete("expired"), "Expired key counts as missing");
+        assert_eq!(store.len(), 1, "Expired key is removed anyway");
+        assert_eq!(store.delete_many(&["expired".into(), "alive".into()]), 1);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let store = Store::new();
+        let past = clock::now_millis() - 2_000;
+        let future = clock::deadline_from_ttl(Duration::from_secs(10));
+
+        store
+            .set_with_deadline("expired".into(), CacheValue::new("value"), past)
+            .await
+            .expect("Should insert value");
+        store
+            .set_with_deadline("alive".into(), CacheValue::new("value"), future)
+            .await
+            .expect("Should insert value");
+
+        assert_eq!(store.purge_expired().await, 1, "Should purge one key");
+        assert_eq!(store.len(), 1, "Only the alive key should remain");
+        assert_eq!(store.get("alive"), Some(CacheValue::new("value")));
+    }
+
+    #[tokio::test]
+    async fn test_purge_skips_overwritten_key() {
+        let store = Store::new();
+        let past = clock::now_millis() - 2_000;
+
+        store
+            .set_with_deadline("key".into(), CacheValue::new("old"), past)
+            .await
+            .expect("Should insert value");
+        store
+            .set("key".into(), CacheValue::new("new"))
+            .expect("Should insert value");
+
+        store.purge_expired().await;
+        assert_eq!(
+            store.get("key"),
+            Some(CacheValue::new("new")),
+            "Overwritten key without TTL should not be purged"
+        );
+    }
+
+    #[test]
+    fn test_used_memory() {
+        let store = Store::new();
+
+        store
+            .set("key".into(), CacheValue::new("value"))
+            .expect("Should insert value");
+        let used = store.used_memory();
+        assert!(used > 0, "Should account the inserted bytes");
+
+        store
+            .set("key".into(), CacheValue::new("bigger value"))
+            .expect("Should insert value");
+        assert_eq!(
+            store.used_memory(),
+            used + 7,
+            "Overwrite should only account the difference"
+        );
+
+        store.delete("key");
+        assert_eq!(store.used_memory(), 0, "Should release bytes on delete");
+    }
+
+    #[test]
+    fn test_noeviction_rejects_writes() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 2, EvictionPolicy::NoEviction);
+
+        for i in 0..2 {
+            store
+                .set(format!("key-{}", i), CacheValue::new(format!("value-{i}")))
+                .expect("Should insert value");
+        }
+
+        let result = store.set("key-2".into(), CacheValue::new("value-2"));
+        assert!(
+            matches!(result, Err(MemoryError::OutOfMemory(_))),
+            "Should reject writes over the limit"
+        );
+        assert_eq!(store.get("key-2"), None);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 3, EvictionPolicy::AllKeysLru);
+
+        for i in 0..3 {
+            store
+                .set(format!("key-{}", i), CacheValue::new(format!("value-{i}")))
+                .expect("Should insert value");
+            thread::sleep(Duration::from_millis(2));
+        }
+        // key-0 passa a ser a mais recente
+        store.get("key-0");
+
+        store
+            .set("key-3".into(), CacheValue::new("value-3"))
+            .expect("Should evict and insert value");
+
+        assert!(store.used_memory() <= entry_size * 3);
+        assert_eq!(store.get("key-1"), None, "Least recently used should go");
+        assert!(store.get("key-0").is_some(), "Recently used should stay");
+        assert!(store.get("key-3").is_some(), "New key should be inserted");
+    }
+
+    #[test]
+    fn test_overwrite_reserves_only_growth() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 2, EvictionPolicy::AllKeysLru);
+
+        for i in 0..2 {
+            store
+                .set(format!("key-{}", i), CacheValue::new(format!("value-{i}")))
+                .expect("Should insert value");
+        }
+        store
+            .set("key-0".into(), CacheValue::new("value-9"))
+            .expect("Same size overwrite should fit");
+        assert_eq!(store.len(), 2, "Nothing should be evicted");
+
+        store
+            .set("key-1".into(), CacheValue::new("value-10"))
+            .expect("Should evict the other key");
+        assert_eq!(store.get("key-0"), None);
+        assert_eq!(
+            store.get("key-1"),
+            Some(CacheValue::new("value-10")),
+            "The written key is never evicted"
+        );
+    }
+
+    #[test]
+    fn test_sampled_eviction() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0000"), None, 0).size("key-0000");
+        let limit = entry_size * 2000;
+
+        for policy in [EvictionPolicy::Random, EvictionPolicy::AllKeysLru] {
+            let store = Store::with_limit(limit, policy);
+            for i in 0..4000 {
+                store
+                    .set(
+                        format!("key-{:04}", i),
+                        CacheValue::new(format!("value-{:04}", i)),
+                    )
+                    .expect("Should evict and insert value");
+            }
+
+            assert!(store.used_memory() <= limit);
+            assert!(store.len() > EVICTION_FULL_SCAN_LEN && store.len() < 4000);
+            assert!(store.get("key-3999").is_some(), "Last write should stay");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_write_does_not_evict() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 2, EvictionPolicy::AllKeysLru);
+        for i in 0..2 {
+            store
+                .set(format!("key-{}", i), CacheValue::new(format!("value-{i}")))
+                .expect("Should insert value");
+        }
+
+        let outcome = store
+            .write(
+                "key-2".into(),
+                CacheValue::new("value-2"),
+                None,
+                Some(&WriteCondition::Xx),
+            )
+            .await
+            .expect("Rejected writes should not need memory");
+        assert_eq!(outcome, WriteOutcome::Rejected);
+        assert_eq!(store.len(), 2, "Nothing should be evicted");
+    }
+
+    #[tokio::test]
+    async fn test_volatile_ttl_eviction() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 2, EvictionPolicy::VolatileTtl);
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+
+        store
+            .set("key-0".into(), CacheValue::new("value-0"))
+            .expect("Should insert value");
+        store
+            .set_with_deadline("key-1".into(), CacheValue::new("value-1"), deadline)
+            .await
+            .expect("Should insert value");
+
+        store
+            .set("key-2".into(), CacheValue::new("value-2"))
+            .expect("Should evict and insert value");
+        assert_eq!(store.get("key-1"), None, "Volatile key should be evicted");
+
+        let result = store.set("key-3".into(), CacheValue::new("value-3"));
+        assert!(
+            matches!(result, Err(MemoryError::OutOfMemory(_))),
+            "Should reject writes without volatile keys left"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_keeps_exact_stats() {
+        let store = Store::new();
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+
+        for _ in 0..10 {
+            store
+                .set("key".into(), CacheValue::new("value"))
+                .expect("Should insert value");
+        }
+        store
+            .set_with_deadline("volatile".into(), CacheValue::new("ab"), deadline)
+            .await
+            .expect("Should insert value");
+        store
+            .set_with_deadline("volatile".into(), CacheValue::new("abc"), deadline)
+            .await
+            .expect("Should insert value");
+
+        store.get("key");
+        store.get("missing");
+
+        let stats = store.stats();
+        assert_eq!(stats.keys, 2, "Overwrites should not increase length");
+        assert_eq!(stats.value_bytes, 8, "Should sum only current values");
+        assert_eq!(stats.volatile_keys, 1, "Should count the key with TTL once");
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        store
+            .set("volatile".into(), CacheValue::new("abc"))
+            .expect("Should insert value");
+        assert_eq!(store.stats().volatile_keys, 0, "TTL removed on overwrite");
+
+        store.clear();
+        let stats = store.stats();
+        assert_eq!(
+            (stats.keys, stats.value_bytes, stats.used_memory),
+            (0, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes() {
+        let store = Store::new();
+        let value = |v: &str| CacheValue::new(v);
+
+        let nx = Some(&WriteCondition::Nx);
+        let first = store.write("key".into(), value("a"), None, nx).await;
+        assert!(matches!(
+            first,
+            Ok(WriteOutcome::Written { previous: None, .. })
+        ));
+        let second = store.write("key".into(), value("b"), None, nx).await;
+        assert!(matches!(second, Ok(WriteOutcome::Rejected)));
+
+        let xx = Some(&WriteCondition::Xx);
+        let missing = store.write("other".into(), value("a"), None, xx).await;
+        assert!(matches!(missing, Ok(WriteOutcome::Rejected)));
+
+        let (_, version) = store.get_versioned("key").expect("Should find key");
+        let cas = WriteCondition::Version(version);
+        let swapped = store
+            .write("key".into(), value("c"), None, Some(&cas))
+            .await;
+        assert!(matches!(
+            swapped,
+            Ok(WriteOutcome::Written { previous: Some(ref p), .. }) if *p == value("a")
+        ));
+        let stale = store
+            .write("key".into(), value("d"), None, Some(&cas))
+            .await;
+        assert!(matches!(stale, Ok(WriteOutcome::Rejected)), "Stale version");
+
+        let by_value = WriteCondition::Value(value("c"));
+        let swapped = store
+            .write("key".into(), value("e"), None, Some(&by_value))
+            .await;
+        assert!(matches!(swapped, Ok(WriteOutcome::Written { .. })));
+        assert_eq!(store.get("key"), Some(value("e")));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_key_is_absent_for_conditions() {
+        let store = Store::new();
+        let past = clock::now_millis() - 2_000;
+
+        store
+            .set_with_deadline("key".into(), CacheValue::new("old"), past)
+            .await
+            .expect("Should insert value");
+
+        let outcome = store
+            .write(
+                "key".into(),
+                CacheValue::new("new"),
+                None,
+                Some(&WriteCondition::Nx),
+            )
+            .await;
+        assert!(matches!(
+            outcome,
+            Ok(WriteOutcome::Written { previous: None, .. })
+        ));
+        assert_eq!(store.stats().volatile_keys, 0);
+    }
+
+    #[test]
+    fn test_concurrent_set_if_absent() {
+        let store = Arc::new(Store::new());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let written = runtime.block_on(async {
+            let mut tasks = vec![];
+            for i in 0..100 {
+                let store = store.clone();
+                tasks.push(tokio::spawn(async move {
+                    store
+                        .write(
+                            "lock".into(),
+                            CacheValue::new(format!("owner-{i}")),
+                            None,
+                            Some(&WriteCondition::Nx),
+                        )
+                        .await
+                }));
+            }
+
+            let mut written = 0;
+            for task in tasks {
+                if let Ok(Ok(WriteOutcome::Written { .. })) = task.await {
+                    written += 1;
+                }
+            }
+            written
+        });
+
+        assert_eq!(written, 1, "Only one writer should acquire the lock");
+    }
+
+    #[tokio::test]
+    async fn test_incr_by() {
+        let store = Store::new();
+
+        assert_eq!(
+            store.incr_by("counter", 1).unwrap(),
+            1,
+            "Missing key starts at 0"
+        );
+        assert_eq!(store.incr_by("counter", 10).unwrap(), 11);
+        assert_eq!(store.incr_by("counter", -20).unwrap(), -9);
+        assert_eq!(store.get("counter"), Some(CacheValue::new("-9")));
+
+        store
+            .set("text".into(), CacheValue::new("abc"))
+            .expect("Should insert value");
+        assert!(matches!(
+            store.incr_by("text", 1),
+            Err(MemoryError::NotNumeric(_))
+        ));
+        assert_eq!(store.get("text"), Some(CacheValue::new("abc")));
+
+        store
+            .set("max".into(), CacheValue::new(i64::MAX.to_string()))
+            .expect("Should insert value");
+        assert!(matches!(
+            store.incr_by("max", 1),
+            Err(MemoryError::Overflow(_))
+        ));
+
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        store
+            .set_with_deadline("volatile".into(), CacheValue::new("5"), deadline)
+            .await
+            .expect("Should insert value");
+        store.incr_by("volatile", 1).unwrap();
+        assert_eq!(store.stats().volatile_keys, 1, "Should keep the TTL");
+    }
+
+    #[test]
+    fn test_incr_by_float() {
+        let store = Store::new();
+
+        assert_eq!(store.incr_by_float("price", 10.5).unwrap(), 10.5);
+        assert_eq!(store.incr_by_float("price", -0.5).unwrap(), 10.0);
+        assert_eq!(store.get("price"), Some(CacheValue::new("10")));
+        assert_eq!(
+            store.incr_by("price", 1).unwrap(),
+            11,
+            "Integral float is an integer"
+        );
+    }
+
+    #[test]
+    fn test_incr_by_float_respects_the_limit() {
+        let limit = "price".len() as u64 + MAX_NUMBER_LEN + ENTRY_OVERHEAD + 100;
+        let store = Store::with_limit(limit, EvictionPolicy::NoEviction);
+
+        assert_eq!(store.incr_by_float("price", 1.5).unwrap(), 1.5);
+        assert!(
+            matches!(
+                store.incr_by_float("price", 1e300),
+                Err(MemoryError::OutOfMemory(_))
+            ),
+            "A 300 digit number does not fit"
+        );
+        assert_eq!(store.get("price"), Some(CacheValue::new("1.5")));
+        assert!(store.stats().used_memory <= limit);
+    }
+
+    #[test]
+    fn test_concurrent_incr() {
+        let store = Arc::new(Store::new());
+
+        thread::scope(|s| {
+            for _ in 0..10 {
+                let store = store.clone();
+                s.spawn(move || {
+                    for _ in 0..100 {
+                        store.incr_by("counter", 1).expect("Should increment");
+                    }
+                });
+            }
+        });
+
+        assert_eq!(store.get("counter"), Some(CacheValue::new("1000")));
+    }
+
+    #[test]
+    fn test_batch_operations() {
+        let store = Store::new();
+        let items = (0..3)
+            .map(|i| (format!("key-{}", i), CacheValue::new(format!("value-{i}"))))
+            .collect();
+
+        store.set_many(items).expect("Should insert values");
+        assert_eq!(store.len(), 3);
+
+        let keys: Vec<String> = ["key-2", "missing", "key-0"]
+            .iter()
+            .map(|k| k.to_string())
+            .collect();
+        assert_eq!(
+            store.get_many(&keys),
+            vec![
+                Some(CacheValue::new("value-2")),
+                None,
+                Some(CacheValue::new("value-0"))
+            ],
+            "Should keep the request order with explicit misses"
+        );
+
+        assert_eq!(store.delete_many(&keys), 2, "Should count existing keys");
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_set_many_is_all_or_nothing() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 2, EvictionPolicy::NoEviction);
+
+        let items = (0..3)
+            .map(|i| (format!("key-{}", i), CacheValue::new(format!("value-{i}"))))
+            .collect();
+
+        assert!(matches!(
+            store.set_many(items),
+            Err(MemoryError::OutOfMemory(_))
+        ));
+        assert_eq!(store.len(), 0, "No key should be written");
+    }
+
+    #[tokio::test]
+    async fn test_expire_and_ttl() {
+        let store = Store::new();
+        store.set("key".into(), CacheValue::new("value")).unwrap();
+
+        assert!(store.exists("key"));
+        assert_eq!(store.ttl("key"), Some(None));
+        assert_eq!(store.ttl("missing"), None);
+        assert!(!store.expire("missing", Some(0)).await);
+
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        assert!(store.expire("key", Some(deadline)).await);
+        let ttl = store.ttl("key").unwrap().unwrap();
+        assert!(ttl > 59_000 && ttl <= 60_000, "Unexpected ttl: {}", ttl);
+        assert_eq!(store.stats().volatile_keys, 1);
+
+        assert!(store.expire("key", None).await);
+        assert_eq!(store.ttl("key"), Some(None));
+        assert_eq!(store.stats().volatile_keys, 0);
+
+        let past = clock::now_millis() - 1;
+        assert!(store.expire("key", Some(past)).await);
+        assert!(!store.exists("key"));
+        assert_eq!(store.get("key"), None);
+    }
+
+    /// Guarda as alterações recebidas para conferencia nos testes.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Mutation>>);
+
+    impl MutationObserver for Recorder {
+        fn on_mutation(&self, mutation: Mutation) {
+            self.0.lock().unwrap().push(mutation);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clear_is_ordered_with_writes() {
+        let store = Arc::new(Store::new());
+        let recorder = Arc::new(Recorder::default());
+        store.observe(recorder.clone());
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let store = store.clone();
+                s.spawn(move || {
+                    for i in 0..500 {
+                        store
+                            .set(format!("key-{}-{}", t, i), CacheValue::new("value"))
+                            .unwrap();
+                    }
+                });
+            }
+            for _ in 0..20 {
+                store.clear();
+            }
+        });
+
+        let replica = Store::new();
+        let mutations = recorder.0.lock().unwrap().clone();
+        for mutation in mutations {
+            replica.apply(mutation).await.unwrap();
+        }
+        assert_eq!(replica.len(), store.len());
+        let mut keys = store.keys_where(|_| true);
+        let mut replica_keys = replica.keys_where(|_| true);
+        keys.sort();
+        replica_keys.sort();
+        assert_eq!(keys, replica_keys, "Replaying the log should match");
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_observed_and_applied() {
+        let store = Store::new();
+        let recorder = Arc::new(Recorder::default());
+        assert!(store.observe(recorder.clone()));
+        assert!(
+            !store.observe(recorder.clone()),
+            "Only one observer is allowed"
+        );
+
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        store.set("a".into(), CacheValue::new("1")).unwrap();
+        store
+            .set_with_deadline("b".into(), CacheValue::new("2"), deadline)
+            .await
+            .unwrap();
+        store.incr_by("a", 10).unwrap();
+        store.expire("a", Some(deadline)).await;
+        store.delete("b");
+        store.delete("missing");
+        store
+            .write(
+                "a".into(),
+                CacheValue::new("x"),
+                None,
+                Some(&WriteCondition::Nx),
+            )
+            .await
+            .unwrap();
+
+        let mutations = recorder.0.lock().unwrap().clone();
+        assert_eq!(mutations.len(), 5, "Rejected writes should not be observed");
+        assert!(
+            matches!(&mutations[3], Mutation::Expire { key, expires_at: Some(_) } if key == "a")
+        );
+        assert_eq!(mutations[4], Mutation::Delete("b".into()));
+
+        let replica = Store::new();
+        for mutation in mutations {
+            replica.apply(mutation).await.unwrap();
+        }
+        assert_eq!(replica.get("a"), Some(CacheValue::new("11")));
+        assert_eq!(replica.get("b"), None);
+        let ttl = replica.ttl("a").unwrap().unwrap();
+        assert!(ttl > 59_000, "Deadline should be kept, got {}", ttl);
+
+        store.clear();
+        let clear = recorder.0.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(clear, Mutation::Clear);
+        replica.apply(clear).await.unwrap();
+        assert_eq!(replica.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_shards() {
+        let store = Store::new();
+        for i in 0..100 {
+            store
+                .set(format!("key-{}", i), CacheValue::new("value"))
+                .unwrap();
+        }
+        store
+            .set_with_deadline("expired".into(), CacheValue::new("value"), 0)
+            .await
+            .unwrap();
+
+        let replica = Store::new();
+        for shard in 0..store.shard_count() {
+            for mutation in store.snapshot_shard(shard) {
+                replica.apply(mutation).await.unwrap();
+            }
+        }
+        assert_eq!(replica.len(), 100, "Expired keys are not copied");
+        assert_eq!(replica.get("key-42"), Some(CacheValue::new("value")));
+    }
+
+    #[tokio::test]
+    async fn test_apply_ignores_memory_limit() {
+        let entry_size = CacheEntry::new(CacheValue::new("value-0"), None, 0).size("key-0");
+        let store = Store::with_limit(entry_size * 2, EvictionPolicy::NoEviction);
+
+        for i in 0..4 {
+            let mutation = Mutation::Set {
+                key: format!("key-{}", i),
+                value: CacheValue::new(format!("value-{i}")),
+                expires_at: None,
+            };
+            store
+                .apply(mutation)
+                .await
+                .expect("Replicated writes always apply");
+        }
+        assert_eq!(store.len(), 4, "Nothing should be evicted or dropped");
+        assert!(store.used_memory() > entry_size * 2);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_from_master() {
+        let store = Store::new();
+        let recorder = Arc::new(Recorder::default());
+        store.observe(recorder.clone());
+        store.set_expiry_from_master(true);
+
+        store.set("key".into(), CacheValue::new("1")).unwrap();
+        let past = clock::now_millis() - 1;
+        store.expire("key", Some(past)).await;
+
+        // Expirada fica escondida, mas so o master remove
+        assert_eq!(store.get("key"), None);
+        assert!(!store.exists("key"));
+        assert_eq!(store.purge_expired().await, 0);
+        assert_eq!(store.len(), 1, "Slave should not delete on its own");
+
+        // O master ainda pode estender o prazo de uma key expirada localmente
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        assert!(store.expire("key", Some(deadline)).await);
+        assert_eq!(store.get("key"), Some(CacheValue::new("1")));
+
+        store.expire("key", Some(past)).await;
+        store.set_expiry_from_master(false);
+        assert_eq!(store.get("key"), None);
+        assert_eq!(store.len(), 0, "Master deletes on read");
+        let delete = recorder.0.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(delete, Mutation::Delete("key".into()));
+    }
+
+    #[tokio::test]
+    async fn test_slave_drains_ttl_control() {
+        let store = Store::new();
+        store.set_expiry_from_master(true);
+        let past = clock::now_millis() - 1_000;
+        for key in ["deleted", "waiting"] {
+            store
+                .set_with_deadline(key.into(), CacheValue::new("1"), past)
+                .await
+                .unwrap();
+        }
+        assert_eq!(store.ttl_control.len(), 2);
+
+        // Remoção enviada pelo master
+        store
+            .apply(Mutation::Delete("deleted".into()))
+            .await
+            .unwrap();
+        assert_eq!(store.purge_expired().await, 0);
+        assert_eq!(
+            store.ttl_control.len(),
+            1,
+            "Only the key waiting for the master stays tracked"
+        );
+
+        // Promovido, o nó remove a key que continuou no controle
+        store.set_expiry_from_master(false);
+        assert_eq!(store.purge_expired().await, 1);
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.ttl_control.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_take_and_import() {
+        let source = Store::new();
+        let recorder = Arc::new(Recorder::default());
+        source.observe(recorder.clone());
+
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        source
+            .set_with_deadline("a".into(), CacheValue::new("1"), deadline)
+            .await
+            .unwrap();
+        source.set("b".into(), CacheValue::new("2")).unwrap();
+        assert_eq!(source.keys_where(|key| key == "a"), vec!["a".to_string()]);
+
+        let taken = source.take("a").unwrap();
+        assert_eq!(source.take("a"), None);
+        assert_eq!(source.len(), 1);
+        let delete = recorder.0.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(delete, Mutation::Delete("a".into()));
+
+        let target = Store::new();
+        target.set("b".into(), CacheValue::new("newer")).unwrap();
+        assert!(target.import(taken).await.unwrap());
+        let b = source.take("b").unwrap();
+        assert!(!target.import(b).await.unwrap(), "Existing keys are kept");
+
+        assert_eq!(target.get("a"), Some(CacheValue::new("1")));
+        assert!(target.ttl("a").unwrap().unwrap() > 59_000);
+        assert_eq!(target.get("b"), Some(CacheValue::new("newer")));
+    }
 }
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Fotografia dos contadores do `Store`, usada por dashboards de capacidade.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoreStats {
+    /// Quantidade de `keys` no cache.
+    pub keys: u64,
+    /// Soma do tamanho, em bytes, de todos os valores armazenados.
+    pub value_bytes: u64,
+    /// Memória aproximada ocupada pelas entradas, incluindo `keys` e metadados.
+    pub used_memory: u64,
+    /// Limite de memória em bytes, `0` quando não existe limite.
+    pub max_memory: u64,
+    /// Politica de remoção configurada.
+    pub eviction_policy: String,
+    /// Quantidade de `keys` com tempo de vida.
+    pub volatile_keys: u64,
+    /// Leituras que encontraram a `key`.
+    pub hits: u64,
+    /// Leituras que não encontraram a `key`.
+    pub misses: u64,
+}
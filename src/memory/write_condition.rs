@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use super::{cache_entry::CacheEntry, cache_value::CacheValue};
+
+/// Condição avaliada de forma atomica antes de uma escrita no `Store`.
+///
+/// Uma `key` expirada é tratada como inexistente em todas as condições.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteCondition {
+    /// Escreve somente se a `key` não existir.
+    Nx,
+    /// Escreve somente se a `key` ja existir.
+    Xx,
+    /// Escreve somente se o valor atual for igual ao informado.
+    Value(CacheValue),
+    /// Escreve somente se a versão atual for igual a informada.
+    Version(u64),
+}
+
+impl WriteCondition {
+    /// Verifica a condição contra a entrada atual da `key`, `None` quando ela não existe.
+    pub fn matches(&self, current: Option<&CacheEntry>) -> bool {
+        match (self, current) {
+            (WriteCondition::Nx, current) => current.is_none(),
+            (WriteCondition::Xx, current) => current.is_some(),
+            (WriteCondition::Value(expected), Some(entry)) => entry.value == *expected,
+            (WriteCondition::Version(expected), Some(entry)) => entry.version == *expected,
+            (_, None) => false,
+        }
+    }
+}
+
+/// Resultado de uma escrita condicional.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOutcome {
+    /// O valor foi escrito, junto ao valor anterior da `key` e a nova versão.
+    Written {
+        previous: Option<CacheValue>,
+        version: u64,
+    },
+    /// A condição não foi atendida e nada foi escrito.
+    Rejected,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let mut entry = CacheEntry::new(CacheValue::new("value"), None, 0);
+        entry.version = 7;
+
+        assert!(WriteCondition::Nx.matches(None));
+        assert!(!WriteCondition::Nx.matches(Some(&entry)));
+        assert!(WriteCondition::Xx.matches(Some(&entry)));
+        assert!(!WriteCondition::Xx.matches(None));
+        assert!(WriteCondition::Value(CacheValue::new("value")).matches(Some(&entry)));
+        assert!(!WriteCondition::Value(CacheValue::new("other")).matches(Some(&entry)));
+        assert!(WriteCondition::Version(7).matches(Some(&entry)));
+        assert!(!WriteCondition::Version(6).matches(Some(&entry)));
+        assert!(!WriteCondition::Version(7).matches(None));
+    }
+}
@@ -0,0 +1,520 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio_tungstenite::connect_async;
+
+use crate::memory::{Mutation, clock};
+
+use super::{
+    HEARTBEAT_INTERVAL, MAX_MISSED_HEARTBEATS, Replica, ReplicationError, ReplicationMessage,
+    failover, read_message, send,
+};
+
+/// Intervalo entre as tentativas de conexão com o master.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Mantem o slave conectado ao servidor de replicação do master.
+///
+/// Reconecta a cada `RECONNECT_INTERVAL` quando a conexão cai ou o master
+/// ainda não esta disponivel. Quando um master ja alcançado fica em silencio
+/// por mais que o `failover_timeout` da replica, inicia a eleição de um novo,
+/// repetida enquanto não houver maioria. Termina quando o nó é promovido a master.
+pub async fn start_client(replica: Arc<Replica>) {
+    let failover_timeout = replica.failover_timeout.as_millis() as i64;
+    while replica.node().is_slave() {
+        let master = *replica.node().master_ipaddr();
+        match sync_with_master(&replica, master).await {
+            Ok(()) => eprintln!("Conexão de replicação com o master {} encerrada", master),
+            Err(e) => eprintln!("Falha na replicação com o master {}: {}", master, e),
+        }
+
+        let silence = replica.master_link.silent_for();
+        if silence.is_some_and(|silence| silence >= failover_timeout) {
+            eprintln!("Master {} sem resposta, iniciando eleição", master);
+            failover(&replica).await;
+            continue;
+        }
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+    println!("Nó promovido a master, replicação como cliente encerrada");
+}
+
+/// Aplica no `Store` local as alterações recebidas do master ate a conexão cair.
+///
+/// Ao conectar se registra no master e envia a posição do `MasterLink`, assim o master decide entre
+/// reenviar somente o que foi perdido ou fazer uma sincronização completa.
+/// Na sincronização completa os dados locais são descartados e substituidos
+/// pelo snapshot, durante a carga as leituras podem ver parte dos dados.
+///
+/// A conexão é encerrada quando o master passa `MAX_MISSED_HEARTBEATS`
+/// intervalos de heartbeat sem enviar nenhuma mensagem.
+async fn sync_with_master(replica: &Replica, master: SocketAddr) -> Result<(), ReplicationError> {
+    let (mut ws_stream, _) = connect_async(format!("ws://{}", master)).await?;
+    println!("Conectado ao master {}", master);
+
+    let node = replica.node();
+    send(
+        &mut ws_stream,
+        &ReplicationMessage::Register {
+            node_id: node.id.clone(),
+            address: *node.address(),
+            version: node.version.clone(),
+        },
+    )
+    .await?;
+
+    let link = &replica.master_link;
+    let (replication_id, offset) = link.position();
+    send(
+        &mut ws_stream,
+        &ReplicationMessage::Sync {
+            replication_id,
+            offset,
+        },
+    )
+    .await?;
+
+    let silence = HEARTBEAT_INTERVAL * MAX_MISSED_HEARTBEATS;
+    let mut full_sync = None;
+    loop {
+        let Ok(message) = tokio::time::timeout(silence, read_message(&mut ws_stream)).await else {
+            return Err(ReplicationError::Timeout(format!(
+                "no message from master in {:?}",
+                silence
+            )));
+        };
+        let Some(message) = message? else {
+            return Ok(());
+        };
+        link.touch();
+
+        match message {
+            ReplicationMessage::FullSync {
+                replication_id,
+                offset,
+            } => {
+                println!(
+                    "Sincronização completa com o master a partir do offset {}",
+                    offset
+                );
+                link.reset();
+                replica.store.clear();
+                full_sync = Some((replication_id, offset));
+            }
+            ReplicationMessage::Snapshot(mutations) => {
+                for mutation in mutations {
+                    apply(replica, mutation).await;
+                }
+            }
+            ReplicationMessage::SnapshotEnd => {
+                if let Some((replication_id, offset)) = full_sync.take() {
+                    link.synced(replication_id, offset);
+                    send(&mut ws_stream, &ReplicationMessage::Ack { offset }).await?;
+                }
+            }
+            ReplicationMessage::PartialSync {
+                replication_id,
+                offset,
+            } => {
+                println!(
+                    "Sincronização parcial com o master a partir do offset {}",
+                    offset
+                );
+                link.synced(replication_id, offset);
+                send(&mut ws_stream, &ReplicationMessage::Ack { offset }).await?;
+            }
+            ReplicationMessage::Mutation { offset, mutation } => {
+                apply(replica, mutation).await;
+                link.applied(offset);
+            }
+            ReplicationMessage::Ping { offset, timestamp } => {
+                link.pinged(offset, clock::deadline_from_unix_millis(timestamp));
+                let offset = link.position().1;
+                send(&mut ws_stream, &ReplicationMessage::Ack { offset }).await?;
+            }
+            ReplicationMessage::Topology(peers) => link.set_peers(peers),
+            ReplicationMessage::Rejected(reason) => {
+                return Err(ReplicationError::Register(reason));
+            }
+            ReplicationMessage::Register { .. }
+            | ReplicationMessage::Sync { .. }
+            | ReplicationMessage::Ack { .. }
+            | ReplicationMessage::Election
+            | ReplicationMessage::Demote(_)
+            | ReplicationMessage::Import(_)
+            | ReplicationMessage::Importing(_)
+            | ReplicationMessage::Imported(_)
+            | ReplicationMessage::ImportEnd
+            | ReplicationMessage::Gossip(_)
+            | ReplicationMessage::Candidate(_) => {
+                return Err(ReplicationError::ParseError(format!(
+                    "unexpected {:?} from master",
+                    message
+                )));
+            }
+        }
+    }
+}
+
+async fn apply(replica: &Replica, mutation: Mutation) {
+    if let Err(e) = replica.store.apply(mutation).await {
+        eprintln!("Falha ao aplicar alteração do master: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use crate::{
+        memory::{CacheValue, clock},
+        replication::{Node, NodeMode, Replica, serve},
+        test_support::{listen, wait_for, wait_until},
+    };
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::connect_async;
+
+    use crate::replication::{HEARTBEAT_INTERVAL, MAX_MISSED_HEARTBEATS, ReplicationMessage, send};
+
+    use super::start_client;
+
+    fn build_replica(mode: &str, ipaddr: SocketAddr) -> Arc<Replica> {
+        let mode = NodeMode::try_from(mode.to_string()).unwrap();
+        Arc::new(Replica::new(Node::new(mode, ipaddr)))
+    }
+
+    #[tokio::test]
+    async fn test_slave_applies_master_mutations() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let slave = build_replica("slave", master_ipaddr);
+
+        tokio::spawn(serve(master.clone(), listener));
+
+        let slave_clone = slave.clone();
+        tokio::spawn(async move { start_client(slave_clone).await });
+        wait_until(|| slave.master_link.position().0.is_some()).await;
+
+        let store = &master.store;
+        store.set("a".into(), CacheValue::new("1")).unwrap();
+        store.incr_by("a", 1).unwrap();
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        store
+            .set_with_deadline("b".into(), CacheValue::new("2"), deadline)
+            .await
+            .unwrap();
+        store.set("c".into(), CacheValue::new("3")).unwrap();
+        store.delete("c");
+
+        wait_until(|| slave.store.len() == 2).await;
+        assert_eq!(slave.store.get("a"), Some(CacheValue::new("2")));
+        assert_eq!(slave.store.get("c"), None);
+        let ttl = slave.store.ttl("b").unwrap().unwrap();
+        assert!(ttl > 59_000, "Deadline should be replicated, got {}", ttl);
+
+        store.clear();
+        wait_until(|| slave.store.len() == 0).await;
+    }
+
+    #[tokio::test]
+    async fn test_slave_receives_existing_data() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let slave = build_replica("slave", master_ipaddr);
+
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        for index in 0..2500 {
+            let key = format!("key:{}", index);
+            match index % 2 {
+                0 => master.store.set(key, CacheValue::new("even")).unwrap(),
+                _ => master
+                    .store
+                    .set_with_deadline(key, CacheValue::new("odd"), deadline)
+                    .await
+                    .unwrap(),
+            }
+        }
+        // Dados antigos do slave são descartados na sincronização
+        slave
+            .store
+            .set("stale".into(), CacheValue::new("x"))
+            .unwrap();
+
+        tokio::spawn(serve(master.clone(), listener));
+        let slave_clone = slave.clone();
+        tokio::spawn(async move { start_client(slave_clone).await });
+
+        wait_until(|| slave.store.len() == 2500).await;
+        assert_eq!(slave.store.get("stale"), None);
+        assert_eq!(slave.store.get("key:10"), Some(CacheValue::new("even")));
+        assert_eq!(slave.store.ttl("key:10"), Some(None));
+        let ttl = slave.store.ttl("key:11").unwrap().unwrap();
+        assert!(ttl > 59_000, "Deadline should be kept, got {}", ttl);
+        assert_eq!(slave.store.stats().volatile_keys, 1250);
+
+        master
+            .store
+            .set("after".into(), CacheValue::new("sync"))
+            .unwrap();
+        wait_until(|| slave.store.get("after").is_some()).await;
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_with_partial_sync() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let slave = build_replica("slave", master_ipaddr);
+
+        tokio::spawn(serve(master.clone(), listener));
+
+        master.store.set("a".into(), CacheValue::new("1")).unwrap();
+        let slave_clone = slave.clone();
+        let client = tokio::spawn(async move { start_client(slave_clone).await });
+        wait_until(|| slave.master_link.position().0.is_some()).await;
+        assert_eq!(
+            slave.master_link.position(),
+            (Some(master.log.id().to_string()), 1)
+        );
+
+        // Uma sincronização completa descartaria essa key
+        client.abort();
+        let _ = client.await;
+        slave
+            .store
+            .set("local".into(), CacheValue::new("x"))
+            .unwrap();
+
+        master.store.set("b".into(), CacheValue::new("2")).unwrap();
+        master.store.delete("a");
+
+        let slave_clone = slave.clone();
+        tokio::spawn(async move { start_client(slave_clone).await });
+        wait_until(|| slave.master_link.position().1 == 3).await;
+
+        assert_eq!(slave.store.get("a"), None);
+        assert_eq!(slave.store.get("b"), Some(CacheValue::new("2")));
+        assert_eq!(slave.store.get("local"), Some(CacheValue::new("x")));
+    }
+
+    #[tokio::test]
+    async fn test_slave_registers_with_master() {
+        let (listener, master_ipaddr) = listen().await;
+        let slave_ipaddr: SocketAddr = "127.0.0.1:5556".parse().unwrap();
+        let master = build_replica("master", master_ipaddr);
+        let mode = NodeMode::try_from("slave".to_string()).unwrap();
+        let slave = Arc::new(Replica::new(
+            Node::new(mode, master_ipaddr).with_address(slave_ipaddr),
+        ));
+
+        tokio::spawn(serve(master.clone(), listener));
+
+        let slave_clone = slave.clone();
+        let client = tokio::spawn(async move { start_client(slave_clone).await });
+        master.store.set("a".into(), CacheValue::new("1")).unwrap();
+        wait_until(|| slave.master_link.position().1 == 1).await;
+
+        // O master conhece o offset do slave a partir do proximo `Ack`
+        wait_for(|| async {
+            let replicas = master.replicas().await;
+            replicas.first().is_some_and(|replica| replica.offset == 1)
+        })
+        .await;
+        let replicas = master.replicas().await;
+        assert_eq!(replicas.len(), 1);
+        assert_eq!(replicas[0].id, slave.node().id);
+        assert_eq!(replicas[0].address, slave_ipaddr);
+        assert_eq!(replicas[0].version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            (replicas[0].offset, replicas[0].lag, replicas[0].lag_ms),
+            (1, 0, 0)
+        );
+
+        client.abort();
+        let _ = client.await;
+        wait_for(|| async { master.replicas().await.is_empty() }).await;
+    }
+
+    #[tokio::test]
+    async fn test_silent_slave_is_dropped() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+
+        tokio::spawn(serve(master.clone(), listener));
+
+        // Slave que se registra mas nunca responde aos heartbeats
+        let (mut ws_stream, _) = connect_async(format!("ws://{}", master_ipaddr))
+            .await
+            .unwrap();
+        let register = ReplicationMessage::Register {
+            node_id: "silent".into(),
+            address: "127.0.0.1:5556".parse().unwrap(),
+            version: "0.0.0".into(),
+        };
+        send(&mut ws_stream, &register).await.unwrap();
+        let sync = ReplicationMessage::Sync {
+            replication_id: None,
+            offset: 0,
+        };
+        send(&mut ws_stream, &sync).await.unwrap();
+
+        wait_for(|| async { master.replicas().await.len() == 1 }).await;
+
+        let timeout = HEARTBEAT_INTERVAL * (MAX_MISSED_HEARTBEATS + 2);
+        let started = tokio::time::Instant::now();
+        while master.replicas().await.len() == 1 {
+            assert!(
+                started.elapsed() < timeout,
+                "Silent slave should be dropped"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(started.elapsed() >= HEARTBEAT_INTERVAL * (MAX_MISSED_HEARTBEATS - 1));
+    }
+
+    #[tokio::test]
+    async fn test_failover_promotes_a_slave() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let master_server = tokio::spawn(serve(master.clone(), listener));
+        master.store.set("a".into(), CacheValue::new("1")).unwrap();
+
+        let mut slaves = Vec::new();
+        for node_id in ["node-a", "node-b"] {
+            let (listener, address) = listen().await;
+            let mode = NodeMode::try_from("slave".to_string()).unwrap();
+            let node = Node::new(mode, master_ipaddr)
+                .with_id(node_id)
+                .with_address(address);
+            let slave =
+                Arc::new(Replica::new(node).with_failover_timeout(Duration::from_millis(500)));
+
+            tokio::spawn(serve(slave.clone(), listener));
+            let slave_clone = slave.clone();
+            tokio::spawn(async move { start_client(slave_clone).await });
+            slaves.push(slave);
+        }
+        let (slave_a, slave_b) = (&slaves[0], &slaves[1]);
+
+        // Os slaves conhecem um ao outro pela `Topology` do proximo heartbeat
+        wait_until(|| {
+            slaves
+                .iter()
+                .all(|slave| slave.master_link.peers().len() == 2 && slave.store.len() == 1)
+        })
+        .await;
+
+        master_server.abort();
+        let _ = master_server.await;
+
+        wait_until(|| {
+            slave_a.node().is_master() && slave_b.node().master_ipaddr() == slave_a.node().address()
+        })
+        .await;
+        assert!(slave_b.node().is_slave(), "Only one slave is promoted");
+        assert!(!slave_a.is_read_only(), "Promoted slave accepts writes");
+
+        slave_a.store.set("b".into(), CacheValue::new("2")).unwrap();
+        wait_until(|| slave_b.store.get("b").is_some()).await;
+        assert_eq!(slave_b.store.get("a"), Some(CacheValue::new("1")));
+
+        // O antigo master volta no mesmo endereço e passa a seguir o eleito
+        let listener = TcpListener::bind(master_ipaddr).await.unwrap();
+        let returning = build_replica("master", master_ipaddr);
+        returning
+            .store
+            .set("stale".into(), CacheValue::new("0"))
+            .unwrap();
+        tokio::spawn(serve(returning.clone(), listener));
+        wait_until(|| {
+            returning.node().master_ipaddr() == slave_a.node().address()
+                && returning.store.get("b").is_some()
+        })
+        .await;
+        assert!(returning.is_read_only(), "The old master is demoted");
+        assert_eq!(returning.store.get("stale"), None);
+        assert!(slave_a.node().is_master(), "The winner stays master");
+    }
+
+    #[tokio::test]
+    async fn test_lone_slave_is_not_promoted() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let master_server = tokio::spawn(serve(master.clone(), listener));
+
+        let (listener, address) = listen().await;
+        let mode = NodeMode::try_from("slave".to_string()).unwrap();
+        let node = Node::new(mode, master_ipaddr)
+            .with_id("node-a")
+            .with_address(address);
+        let slave = Arc::new(Replica::new(node).with_failover_timeout(Duration::from_millis(300)));
+        tokio::spawn(serve(slave.clone(), listener));
+        let slave_clone = slave.clone();
+        tokio::spawn(async move { start_client(slave_clone).await });
+        wait_until(|| slave.master_link.peers().len() == 1).await;
+
+        master_server.abort();
+        let _ = master_server.await;
+        tokio::time::sleep(Duration::from_millis(1_500)).await;
+        assert!(slave.node().is_slave(), "No other slave to vote");
+        assert!(slave.is_read_only());
+    }
+
+    #[tokio::test]
+    async fn test_master_drives_expiry() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let slave = build_replica("slave", master_ipaddr);
+
+        tokio::spawn(serve(master.clone(), listener));
+        let slave_clone = slave.clone();
+        tokio::spawn(async move { start_client(slave_clone).await });
+
+        let deadline = clock::deadline_from_ttl(Duration::from_millis(300));
+        master
+            .store
+            .set_with_deadline("key".into(), CacheValue::new("1"), deadline)
+            .await
+            .unwrap();
+        wait_until(|| slave.store.len() == 1).await;
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(slave.store.get("key"), None, "Expired key is hidden");
+        slave.store.purge_expired().await;
+        assert_eq!(slave.store.len(), 1, "Only the master deletes it");
+
+        assert_eq!(master.store.purge_expired().await, 1);
+        wait_until(|| slave.store.len() == 0).await;
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_acks() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = build_replica("master", master_ipaddr);
+        let slave = build_replica("slave", master_ipaddr);
+
+        tokio::spawn(serve(master.clone(), listener));
+        let slave_clone = slave.clone();
+        tokio::spawn(async move { start_client(slave_clone).await });
+        wait_until(|| slave.master_link.position().0.is_some()).await;
+
+        master.store.set("a".into(), CacheValue::new("1")).unwrap();
+        let offset = master.log.offset();
+        let started = tokio::time::Instant::now();
+        let acknowledged = master
+            .wait_for_acks(offset, 1, Duration::from_secs(5))
+            .await;
+        assert_eq!(acknowledged, 1);
+        assert!(
+            started.elapsed() < HEARTBEAT_INTERVAL,
+            "Ack should be requested without waiting for the heartbeat"
+        );
+        assert_eq!(slave.store.get("a"), Some(CacheValue::new("1")));
+
+        let started = tokio::time::Instant::now();
+        let acknowledged = master
+            .wait_for_acks(offset, 2, Duration::from_millis(300))
+            .await;
+        assert_eq!(acknowledged, 1, "Only one slave is attached");
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,1093 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap, HashSet},
+    net::SocketAddr,
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Node, ReplicationError, Services};
+
+/// Quantidade de hash slots em que o espaço de `keys` é dividido.
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// Tempo padrão sem noticias de um nó ate ele ser suspeito de falha.
+pub const NODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Quantos `node_timeout` um membro sem slots fica sem noticias ate ser
+/// esquecido, que tambem é o tempo em que a fofoca não o traz de volta.
+const FORGET_TIMEOUTS: u32 = 12;
+
+/// Hash slot da `key`, o CRC16 dela modulo `CLUSTER_SLOTS`, como no Redis Cluster.
+///
+/// Quando a `key` possui um trecho não vazio entre `{` e `}` somente ele entra
+/// no hash, assim `keys` relacionadas podem ser mantidas no mesmo slot.
+pub fn key_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+    let hashed = match bytes.iter().position(|byte| *byte == b'{') {
+        Some(open) => match bytes[open + 1..].iter().position(|byte| *byte == b'}') {
+            Some(len) if len > 0 => &bytes[open + 1..open + 1 + len],
+            _ => bytes,
+        },
+        None => bytes,
+    };
+    crc16(hashed) % CLUSTER_SLOTS
+}
+
+/// CRC16 XMODEM, o mesmo usado pelo Redis Cluster.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x1021,
+        })
+    })
+}
+
+/// Intervalo de slots, com inicio e fim inclusos.
+///
+/// Lido do texto como `"0-8191"` ou `"42"` para um unico slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl SlotRange {
+    pub fn new(start: u16, end: u16) -> Result<Self, ReplicationError> {
+        if start > end || end >= CLUSTER_SLOTS {
+            return Err(ReplicationError::Cluster(format!(
+                "invalid slot range {}-{}",
+                start, end
+            )));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn contains(&self, slot: u16) -> bool {
+        self.slots().contains(&slot)
+    }
+
+    pub fn slots(&self) -> RangeInclusive<u16> {
+        self.start..=self.end
+    }
+
+    /// Valida um intervalo recebido de um cliente ou de outro nó.
+    fn validate(self) -> Result<Self, ReplicationError> {
+        Self::new(self.start, self.end)
+    }
+}
+
+impl FromStr for SlotRange {
+    type Err = ReplicationError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |slot: &str| {
+            slot.trim()
+                .parse::<u16>()
+                .map_err(|e| ReplicationError::ParseError(format!("invalid slot {}: {}", slot, e)))
+        };
+        match value.split_once('-') {
+            Some((start, end)) => Self::new(parse(start)?, parse(end)?),
+            None => {
+                let slot = parse(value)?;
+                Self::new(slot, slot)
+            }
+        }
+    }
+}
+
+/// Master que atende um conjunto de slots, identificado pelo seu endereço de
+/// replicação. Os redirecionamentos levam os clientes aos seus `services`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterNode {
+    pub id: String,
+    pub address: SocketAddr,
+    #[serde(default)]
+    pub services: Services,
+}
+
+impl From<&Node> for ClusterNode {
+    fn from(node: &Node) -> Self {
+        Self {
+            id: node.id.clone(),
+            address: *node.address(),
+            services: *node.services(),
+        }
+    }
+}
+
+/// Intervalo de slots atendido por um master, listado pelo comando `ClusterSlots`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlotAssignment {
+    #[serde(flatten)]
+    pub slots: SlotRange,
+    pub node: ClusterNode,
+}
+
+/// Estado de um nó do cluster anunciado na fofoca.
+///
+/// Somente o proprio nó altera o seu estado, aumentando o `heartbeat` a
+/// cada rodada, e entre duas versões vence a de maior `heartbeat`. Os
+/// `suspects` são os nós dos quais ele não tem noticias ha mais que o
+/// tempo limite.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterMember {
+    pub id: String,
+    pub address: SocketAddr,
+    #[serde(default)]
+    pub services: Services,
+    pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master: Option<SocketAddr>,
+    pub heartbeat: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suspects: Vec<String>,
+}
+
+/// Slots atribuidos a um master na `epoch` informada.
+///
+/// Cada atribuição recebe uma `epoch` maior que todas as conhecidas pelo nó
+/// que a fez, e para cada slot vence a atribuição de maior `epoch`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlotClaim {
+    #[serde(flatten)]
+    pub slots: SlotRange,
+    pub node: ClusterNode,
+    pub epoch: u64,
+}
+
+/// Visão do cluster trocada entre os nós pela fofoca.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterDigest {
+    pub members: Vec<ClusterMember>,
+    pub slots: Vec<SlotClaim>,
+}
+
+/// Situação de um nó na visão local.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeState {
+    Ok,
+    /// Algum nó não tem noticias dele.
+    Suspect,
+    /// A maioria dos masters não tem noticias dele.
+    Fail,
+}
+
+/// Nó do cluster listado pelo comando `ClusterNodes`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterNodeInfo {
+    pub id: String,
+    pub address: SocketAddr,
+    pub services: Services,
+    pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master: Option<SocketAddr>,
+    /// Indica o nó que respondeu o comando.
+    pub myself: bool,
+    pub state: NodeState,
+    /// Nós que suspeitam da falha deste.
+    pub suspected_by: Vec<String>,
+    pub slots: Vec<SlotRange>,
+    pub heartbeat: u64,
+}
+
+/// Onde as `keys` de uma requisição devem ser atendidas.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    /// O nó local atende o slot das `keys`.
+    Local,
+    /// O slot pertence a outro master.
+    Moved(u16, ClusterNode),
+    /// O slot esta sendo migrado e as `keys` que faltam ja podem estar no destino.
+    Ask(u16, ClusterNode),
+    /// Nenhum master atende o slot.
+    Unassigned(u16),
+    /// As `keys` estão em slots diferentes.
+    CrossSlot,
+}
+
+/// Visão do nó sobre os membros do cluster e a divisão dos slots entre os masters.
+pub struct Cluster {
+    /// Tempo sem um novo `heartbeat` de um nó ate ele ser suspeito de falha.
+    node_timeout: Duration,
+    table: RwLock<SlotTable>,
+}
+
+struct SlotTable {
+    /// Dono de cada slot, indexado pelo slot.
+    owners: Vec<Option<SlotOwner>>,
+    /// Maior `epoch` de atribuição conhecida.
+    epoch: u64,
+    /// Slots locais sendo enviados a outro master, com o destino.
+    migrating: HashMap<u16, Arc<ClusterNode>>,
+    /// Slots sendo recebidos de outro master.
+    importing: HashSet<u16>,
+    /// Membros conhecidos, incluindo o nó local, indexados pelo id.
+    members: HashMap<String, Member>,
+    /// Membros esquecidos, ignorados na fofoca ate o instante informado.
+    forgotten: HashMap<String, Instant>,
+}
+
+#[derive(Clone)]
+struct SlotOwner {
+    node: Arc<ClusterNode>,
+    epoch: u64,
+}
+
+impl SlotOwner {
+    /// Ordem das atribuições de um slot, a maior `epoch` vence e no empate o menor id.
+    fn precedence(&self) -> (u64, Reverse<&str>) {
+        (self.epoch, Reverse(self.node.id.as_str()))
+    }
+}
+
+struct Member {
+    state: ClusterMember,
+    /// Quando o `heartbeat` do membro avançou pela ultima vez na visão local.
+    seen_at: Instant,
+}
+
+impl Cluster {
+    pub fn new() -> Self {
+        Self {
+            node_timeout: NODE_TIMEOUT,
+            table: RwLock::new(SlotTable {
+                owners: vec![None; CLUSTER_SLOTS as usize],
+                epoch: 0,
+                migrating: HashMap::new(),
+                importing: HashSet::new(),
+                members: HashMap::new(),
+                forgotten: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn with_node_timeout(mut self, node_timeout: Duration) -> Self {
+        self.node_timeout = node_timeout;
+        self
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, SlotTable> {
+        self.table.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, SlotTable> {
+        self.table.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Atribui os slots ao master informado em uma nova `epoch`, encerrando
+    /// migrações em andamento neles.
+    ///
+    /// Um master ainda desconhecido passa a ser membro, assim a fofoca chega nele.
+    pub fn assign(&self, slots: SlotRange, node: ClusterNode) -> Result<(), ReplicationError> {
+        let slots = slots.validate()?;
+        let mut table = self.write();
+        table.learn(&node);
+        table.epoch += 1;
+        let owner = SlotOwner {
+            node: Arc::new(node),
+            epoch: table.epoch,
+        };
+        for slot in slots.slots() {
+            table.owners[slot as usize] = Some(owner.clone());
+            table.migrating.remove(&slot);
+            table.importing.remove(&slot);
+        }
+        Ok(())
+    }
+
+    /// Intervalos continuos de slots com o mesmo dono, em ordem.
+    pub fn assignments(&self) -> Vec<SlotAssignment> {
+        let table = self.read();
+        let mut assignments: Vec<SlotAssignment> = Vec::new();
+        for (slot, owner) in (0..CLUSTER_SLOTS).zip(table.owners.iter()) {
+            let Some(owner) = owner else {
+                continue;
+            };
+            match assignments.last_mut() {
+                Some(last) if last.slots.end + 1 == slot && last.node == *owner.node => {
+                    last.slots.end = slot;
+                }
+                _ => assignments.push(SlotAssignment {
+                    slots: SlotRange::new(slot, slot).unwrap(),
+                    node: (*owner.node).clone(),
+                }),
+            }
+        }
+        assignments
+    }
+
+    /// Passa os slots do master em `previous` para `node`, usado quando um slave é promovido.
+    pub fn take_over(&self, previous: SocketAddr, node: ClusterNode) {
+        let mut table = self.write();
+        table.epoch += 1;
+        let promoted = SlotOwner {
+            node: Arc::new(node),
+            epoch: table.epoch,
+        };
+        for owner in table.owners.iter_mut() {
+            if owner
+                .as_ref()
+                .is_some_and(|owner| owner.node.address == previous)
+            {
+                *owner = Some(promoted.clone());
+            }
+        }
+    }
+
+    /// Decide onde as `keys` devem ser atendidas por um nó que serve os slots
+    /// do master em `local`, o proprio nó ou o master de um slave.
+    ///
+    /// Em um slot sendo migrado as `keys` continuam locais enquanto todas
+    /// existirem aqui, `exists` responde isso. Os slots sendo recebidos so
+    /// são atendidos com `asking`, o pedido redirecionado por um `Ask`.
+    pub fn route<F>(&self, keys: &[&str], local: SocketAddr, asking: bool, exists: F) -> Route
+    where
+        F: Fn(&str) -> bool,
+    {
+        let Some((first, others)) = keys.split_first() else {
+            return Route::Local;
+        };
+        let slot = key_slot(first);
+        if others.iter().any(|key| key_slot(key) != slot) {
+            return Route::CrossSlot;
+        }
+
+        let table = self.read();
+        match table.owners[slot as usize]
+            .as_ref()
+            .map(|owner| &owner.node)
+        {
+            Some(owner) if owner.address == local => match table.migrating.get(&slot) {
+                Some(target) if !keys.iter().all(|key| exists(key)) => {
+                    Route::Ask(slot, table.resolve(target))
+                }
+                _ => Route::Local,
+            },
+            _ if asking && table.importing.contains(&slot) => Route::Local,
+            Some(owner) => Route::Moved(slot, table.resolve(owner)),
+            None => Route::Unassigned(slot),
+        }
+    }
+
+    /// Marca os slots do master em `local` como migrando para `target`.
+    ///
+    /// Falha se algum dos slots não pertencer a ele ou ja estiver migrando
+    /// para outro nó. Uma migração interrompida pode ser refeita para o mesmo destino.
+    pub fn start_migration(
+        &self,
+        slots: SlotRange,
+        local: SocketAddr,
+        target: ClusterNode,
+    ) -> Result<(), ReplicationError> {
+        let slots = slots.validate()?;
+        let target = Arc::new(target);
+        let mut table = self.write();
+        for slot in slots.slots() {
+            let owned = table.owners[slot as usize]
+                .as_ref()
+                .is_some_and(|owner| owner.node.address == local);
+            let elsewhere = table
+                .migrating
+                .get(&slot)
+                .is_some_and(|current| current.id != target.id);
+            if !owned || elsewhere {
+                return Err(ReplicationError::Cluster(format!(
+                    "slot {} is not owned by this node or is migrating to another node",
+                    slot
+                )));
+            }
+        }
+        for slot in slots.slots() {
+            table.migrating.insert(slot, target.clone());
+        }
+        Ok(())
+    }
+
+    pub fn cancel_migration(&self, slots: SlotRange) {
+        let mut table = self.write();
+        for slot in slots.slots() {
+            table.migrating.remove(&slot);
+        }
+    }
+
+    /// Marca os slots como sendo recebidos de outro master.
+    pub fn start_import(&self, slots: SlotRange) -> Result<(), ReplicationError> {
+        let slots = slots.validate()?;
+        let mut table = self.write();
+        table.importing.extend(slots.slots());
+        Ok(())
+    }
+
+    pub fn cancel_import(&self, slots: SlotRange) {
+        let mut table = self.write();
+        for slot in slots.slots() {
+            table.importing.remove(&slot);
+        }
+    }
+
+    /// Inicia uma rodada da fofoca, atualizando o estado anunciado pelo nó local.
+    ///
+    /// O `heartbeat` local aumenta e os membros sem um novo `heartbeat` ha mais
+    /// que `node_timeout` passam a ser suspeitos.
+    ///
+    /// Um nó reiniciado volta com outro id no mesmo endereço. O id antigo é
+    /// esquecido assim que fica suspeito e o endereço volta a responder, e os
+    /// demais membros sem slots são esquecidos depois de `FORGET_TIMEOUTS`.
+    pub fn tick(&self, local: &Node) {
+        let mut table = self.write();
+        let now = Instant::now();
+        table.forget(local, now, self.node_timeout);
+        let mut suspects: Vec<String> = table
+            .members
+            .values()
+            .filter(|member| member.state.id != local.id)
+            .filter(|member| now.duration_since(member.seen_at) > self.node_timeout)
+            .map(|member| member.state.id.clone())
+            .collect();
+        suspects.sort();
+
+        let heartbeat = table
+            .members
+            .get(&local.id)
+            .map_or(0, |member| member.state.heartbeat);
+        let state = ClusterMember {
+            id: local.id.clone(),
+            address: *local.address(),
+            services: *local.services(),
+            role: local.mode.to_string(),
+            master: local.is_slave().then(|| *local.master_ipaddr()),
+            heartbeat: heartbeat + 1,
+            suspects,
+        };
+        table.members.insert(
+            local.id.clone(),
+            Member {
+                state,
+                seen_at: now,
+            },
+        );
+    }
+
+    /// Visão local enviada aos outros nós.
+    pub fn digest(&self) -> ClusterDigest {
+        let table = self.read();
+        let mut members: Vec<ClusterMember> = table
+            .members
+            .values()
+            .map(|member| member.state.clone())
+            .collect();
+        members.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut slots: Vec<SlotClaim> = Vec::new();
+        for (slot, owner) in (0..CLUSTER_SLOTS).zip(table.owners.iter()) {
+            let Some(owner) = owner else {
+                continue;
+            };
+            match slots.last_mut() {
+                Some(last)
+                    if last.slots.end + 1 == slot
+                        && last.epoch == owner.epoch
+                        && last.node == *owner.node =>
+                {
+                    last.slots.end = slot;
+                }
+                _ => slots.push(SlotClaim {
+                    slots: SlotRange::new(slot, slot).unwrap(),
+                    node: (*owner.node).clone(),
+                    epoch: owner.epoch,
+                }),
+            }
+        }
+        ClusterDigest { members, slots }
+    }
+
+    /// Junta a visão recebida de outro nó à local.
+    ///
+    /// De cada membro fica o estado de maior `heartbeat`, menos o do nó local
+    /// que so ele altera, e de cada slot a atribuição de maior `epoch`.
+    pub fn merge(&self, digest: ClusterDigest, local_id: &str) {
+        let mut table = self.write();
+        let now = Instant::now();
+        for state in digest.members {
+            if state.id == local_id || table.forgotten.contains_key(&state.id) {
+                continue;
+            }
+            match table.members.get_mut(&state.id) {
+                Some(member) if member.state.heartbeat >= state.heartbeat => {}
+                Some(member) => {
+                    member.state = state;
+                    member.seen_at = now;
+                }
+                None => {
+                    let member = Member {
+                        state,
+                        seen_at: now,
+                    };
+                    table.members.insert(member.state.id.clone(), member);
+                }
+            }
+        }
+
+        for claim in digest.slots {
+            let Ok(slots) = claim.slots.validate() else {
+                continue;
+            };
+            table.epoch = table.epoch.max(claim.epoch);
+            let claimed = SlotOwner {
+                node: Arc::new(claim.node),
+                epoch: claim.epoch,
+            };
+            for slot in slots.slots() {
+                let owner = &mut table.owners[slot as usize];
+                if owner
+                    .as_ref()
+                    .is_none_or(|owner| claimed.precedence() > owner.precedence())
+                {
+                    *owner = Some(claimed.clone());
+                }
+            }
+        }
+    }
+
+    /// Endereços de replicação anunciados pelos nós com quem o nó local troca
+    /// a fofoca, incluindo o master de um slave mesmo antes dele ser conhecido.
+    pub fn peers(&self, local: &Node) -> Vec<SocketAddr> {
+        let table = self.read();
+        let mut peers: BTreeSet<SocketAddr> = table
+            .members
+            .values()
+            .map(|member| member.state.address)
+            .collect();
+        if local.is_slave() {
+            peers.insert(*local.master_ipaddr());
+        }
+        peers.remove(local.address());
+        peers.into_iter().collect()
+    }
+
+    /// Membros conhecidos com os slots de cada um e as suspeitas de falha.
+    ///
+    /// Um nó falhou quando a maioria dos masters suspeita dele.
+    pub fn nodes(&self, local_id: &str) -> Vec<ClusterNodeInfo> {
+        let table = self.read();
+        let masters: Vec<&ClusterMember> = table
+            .members
+            .values()
+            .map(|member| &member.state)
+            .filter(|state| state.role == "master")
+            .collect();
+
+        let mut nodes: Vec<ClusterNodeInfo> = table
+            .members
+            .values()
+            .map(|member| {
+                let state = &member.state;
+                let mut suspected_by: Vec<String> = table
+                    .members
+                    .values()
+                    .filter(|other| other.state.suspects.contains(&state.id))
+                    .map(|other| other.state.id.clone())
+                    .collect();
+                suspected_by.sort();
+                let votes = masters
+                    .iter()
+                    .filter(|master| master.suspects.contains(&state.id))
+                    .count();
+                let state_flag = match suspected_by.is_empty() {
+                    true => NodeState::Ok,
+                    false if votes > masters.len() / 2 => NodeState::Fail,
+                    false => NodeState::Suspect,
+                };
+                let slots = table.owned_ranges(state.address).into_iter().collect();
+
+                ClusterNodeInfo {
+                    id: state.id.clone(),
+                    address: state.address,
+                    services: state.services,
+                    role: state.role.clone(),
+                    master: state.master,
+                    myself: state.id == local_id,
+                    state: state_flag,
+                    suspected_by,
+                    slots,
+                    heartbeat: state.heartbeat,
+                }
+            })
+            .collect();
+        nodes.sort_by_key(|node| node.address);
+        nodes
+    }
+}
+
+impl SlotTable {
+    /// Copia do nó com os endereços de serviço anunciados por ele na fofoca,
+    /// que podem faltar em uma atribuição feita por um cliente.
+    fn resolve(&self, node: &ClusterNode) -> ClusterNode {
+        let mut node = node.clone();
+        if let Some(member) = self.members.get(&node.id)
+            && member.state.services != Services::default()
+        {
+            node.services = member.state.services;
+        }
+        node
+    }
+
+    /// Registra um master conhecido somente por uma atribuição de slots.
+    fn learn(&mut self, node: &ClusterNode) {
+        if self.members.contains_key(&node.id) {
+            return;
+        }
+        self.forgotten.remove(&node.id);
+        let state = ClusterMember {
+            id: node.id.clone(),
+            address: node.address,
+            services: node.services,
+            role: "master".into(),
+            master: None,
+            heartbeat: 0,
+            suspects: Vec::new(),
+        };
+        self.members.insert(
+            node.id.clone(),
+            Member {
+                state,
+                seen_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Esquece os membros antigos, ver `Cluster::tick`.
+    fn forget(&mut self, local: &Node, now: Instant, node_timeout: Duration) {
+        let forget_timeout = node_timeout * FORGET_TIMEOUTS;
+        self.forgotten.retain(|_, until| *until > now);
+
+        let live: HashSet<SocketAddr> = self
+            .members
+            .values()
+            .filter(|member| now.duration_since(member.seen_at) <= node_timeout)
+            .map(|member| member.state.address)
+            .chain([*local.address()])
+            .collect();
+        let forgotten: Vec<String> = self
+            .members
+            .values()
+            .filter(|member| member.state.id != local.id)
+            .filter(|member| {
+                let silence = now.duration_since(member.seen_at);
+                let replaced = silence > node_timeout && live.contains(&member.state.address);
+                let abandoned =
+                    silence > forget_timeout && self.owned_ranges(member.state.address).is_empty();
+                replaced || abandoned
+            })
+            .map(|member| member.state.id.clone())
+            .collect();
+        for id in forgotten {
+            self.members.remove(&id);
+            self.forgotten.insert(id, now + forget_timeout);
+        }
+    }
+
+    /// Intervalos continuos dos slots atendidos no endereço.
+    fn owned_ranges(&self, address: SocketAddr) -> Vec<SlotRange> {
+        let mut ranges: Vec<SlotRange> = Vec::new();
+        for (slot, owner) in (0..CLUSTER_SLOTS).zip(self.owners.iter()) {
+            if owner
+                .as_ref()
+                .is_none_or(|owner| owner.node.address != address)
+            {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end + 1 == slot => last.end = slot,
+                _ => ranges.push(SlotRange::new(slot, slot).unwrap()),
+            }
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use crate::replication::{Node, NodeMode, Services};
+
+    use super::{Cluster, ClusterNode, FORGET_TIMEOUTS, NodeState, Route, SlotRange, key_slot};
+
+    fn build_node(id: &str, port: u16) -> ClusterNode {
+        ClusterNode {
+            id: id.into(),
+            address: SocketAddr::from(([127, 0, 0, 1], port)),
+            services: Services::default(),
+        }
+    }
+
+    #[test]
+    fn test_key_slot() {
+        // Valores do Redis Cluster
+        assert_eq!(key_slot("foo"), 12182);
+        assert_eq!(key_slot("bar"), 5061);
+        assert_eq!(key_slot("123456789"), 0x31C3);
+        assert_eq!(key_slot("{user1000}.following"), key_slot("user1000"));
+        assert_eq!(key_slot("{user1000}.followers"), key_slot("user1000"));
+        assert_eq!(key_slot("foo{}{bar}"), key_slot("foo{}{bar}"));
+        assert_ne!(
+            key_slot("foo{}{bar}"),
+            key_slot("bar"),
+            "Empty tag hashes the whole key"
+        );
+    }
+
+    #[test]
+    fn test_slot_range() {
+        assert_eq!(
+            "0-8191".parse::<SlotRange>().unwrap(),
+            SlotRange {
+                start: 0,
+                end: 8191
+            }
+        );
+        assert_eq!(
+            "42".parse::<SlotRange>().unwrap(),
+            SlotRange { start: 42, end: 42 }
+        );
+        assert!("10-5".parse::<SlotRange>().is_err());
+        assert!("0-16384".parse::<SlotRange>().is_err());
+        assert!("a-b".parse::<SlotRange>().is_err());
+    }
+
+    #[test]
+    fn test_assignments() {
+        let cluster = Cluster::new();
+        let (a, b) = (build_node("a", 5555), build_node("b", 5556));
+        cluster
+            .assign(SlotRange::new(0, 8191).unwrap(), a.clone())
+            .unwrap();
+        cluster
+            .assign(SlotRange::new(8192, 16383).unwrap(), b.clone())
+            .unwrap();
+        cluster
+            .assign(SlotRange::new(100, 199).unwrap(), b.clone())
+            .unwrap();
+
+        let ranges: Vec<(u16, u16, String)> = cluster
+            .assignments()
+            .into_iter()
+            .map(|assignment| {
+                (
+                    assignment.slots.start,
+                    assignment.slots.end,
+                    assignment.node.id,
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 99, "a".into()),
+                (100, 199, "b".into()),
+                (200, 8191, "a".into()),
+                (8192, 16383, "b".into()),
+            ]
+        );
+
+        let c = build_node("c", 5557);
+        cluster.take_over(a.address, c.clone());
+        let assignments = cluster.assignments();
+        assert_eq!(assignments[0].node, c);
+        assert_eq!(assignments[1].node, b);
+        assert_eq!(assignments[2].node, c);
+    }
+
+    #[test]
+    fn test_route() {
+        let cluster = Cluster::new();
+        let (a, b) = (build_node("a", 5555), build_node("b", 5556));
+        let foo = key_slot("foo");
+
+        assert_eq!(
+            cluster.route(&["foo"], a.address, false, |_| true),
+            Route::Unassigned(foo)
+        );
+        cluster
+            .assign(SlotRange::new(0, 16383).unwrap(), a.clone())
+            .unwrap();
+        assert_eq!(cluster.route(&[], b.address, false, |_| true), Route::Local);
+        assert_eq!(
+            cluster.route(&["foo"], a.address, false, |_| true),
+            Route::Local
+        );
+        assert_eq!(
+            cluster.route(&["foo"], b.address, false, |_| true),
+            Route::Moved(foo, a.clone())
+        );
+        assert_eq!(
+            cluster.route(&["foo", "bar"], a.address, false, |_| true),
+            Route::CrossSlot
+        );
+        assert_eq!(
+            cluster.route(&["{foo}.a", "{foo}.b"], a.address, false, |_| true),
+            Route::Local
+        );
+
+        // Migração de `a` para `b`
+        let slots = SlotRange::new(foo, foo).unwrap();
+        cluster
+            .start_migration(slots, a.address, b.clone())
+            .unwrap();
+        assert!(
+            cluster.start_migration(slots, a.address, b.clone()).is_ok(),
+            "Retry to the same target"
+        );
+        assert!(
+            cluster
+                .start_migration(slots, a.address, build_node("c", 5557))
+                .is_err()
+        );
+        assert_eq!(
+            cluster.route(&["foo"], a.address, false, |_| true),
+            Route::Local
+        );
+        assert_eq!(
+            cluster.route(&["foo"], a.address, false, |_| false),
+            Route::Ask(foo, b.clone())
+        );
+
+        let importing = Cluster::new();
+        importing
+            .assign(SlotRange::new(0, 16383).unwrap(), a.clone())
+            .unwrap();
+        importing.start_import(slots).unwrap();
+        assert_eq!(
+            importing.route(&["foo"], b.address, true, |_| false),
+            Route::Local
+        );
+        assert_eq!(
+            importing.route(&["foo"], b.address, false, |_| false),
+            Route::Moved(foo, a.clone())
+        );
+
+        cluster.assign(slots, b.clone()).unwrap();
+        assert_eq!(
+            cluster.route(&["foo"], a.address, false, |_| true),
+            Route::Moved(foo, b.clone())
+        );
+        cluster
+            .assign(SlotRange::new(0, 0).unwrap(), a.clone())
+            .unwrap();
+        assert!(
+            cluster.start_migration(slots, a.address, b).is_err(),
+            "Slot not owned by a"
+        );
+    }
+
+    fn build_member(port: u16) -> (Node, Cluster) {
+        let node = Node::new(NodeMode::Master, SocketAddr::from(([127, 0, 0, 1], port)));
+        let cluster = Cluster::new().with_node_timeout(Duration::from_millis(50));
+        cluster.tick(&node);
+        (node, cluster)
+    }
+
+    /// Troca as visões dos dois nós, como em uma rodada da fofoca.
+    fn exchange(a: (&Node, &Cluster), b: (&Node, &Cluster)) {
+        a.1.tick(a.0);
+        b.1.merge(a.1.digest(), &b.0.id);
+        a.1.merge(b.1.digest(), &a.0.id);
+    }
+
+    #[test]
+    fn test_merge_converges() {
+        let (node_a, a) = build_member(5555);
+        let (node_b, b) = build_member(5556);
+        let (owner_a, owner_b) = (ClusterNode::from(&node_a), ClusterNode::from(&node_b));
+        a.assign(SlotRange::new(0, 8191).unwrap(), owner_a.clone())
+            .unwrap();
+        b.assign(SlotRange::new(8192, 16383).unwrap(), owner_b.clone())
+            .unwrap();
+
+        exchange((&node_a, &a), (&node_b, &b));
+        assert_eq!(a.assignments(), b.assignments());
+        assert_eq!(a.assignments().len(), 2);
+        assert_eq!(a.nodes(&node_a.id).len(), 2);
+
+        // A atribuição mais nova vence, mesmo se a antiga chegar depois
+        let stale = a.digest();
+        b.assign(SlotRange::new(0, 99).unwrap(), owner_b.clone())
+            .unwrap();
+        b.merge(stale, &node_b.id);
+        exchange((&node_a, &a), (&node_b, &b));
+        let assignments = a.assignments();
+        assert_eq!(assignments, b.assignments());
+        assert_eq!(assignments[0].slots, SlotRange::new(0, 99).unwrap());
+        assert_eq!(assignments[0].node, owner_b);
+
+        // O estado de um nó so é alterado por ele
+        let before = b.nodes(&node_b.id);
+        b.merge(b.digest(), &node_a.id);
+        assert_eq!(b.nodes(&node_b.id), before);
+    }
+
+    #[test]
+    fn test_redirect_to_services() {
+        let (node_a, a) = build_member(5555);
+        let services = Services {
+            socket: Some(SocketAddr::from(([127, 0, 0, 1], 50001))),
+            ..Default::default()
+        };
+        let node_b = Node::new(NodeMode::Master, SocketAddr::from(([127, 0, 0, 1], 5556)))
+            .with_services(services);
+        let b = Cluster::new();
+        b.tick(&node_b);
+
+        // Atribuição feita por um cliente, sem os endereços de serviço de b
+        let owner_b = ClusterNode {
+            services: Services::default(),
+            ..ClusterNode::from(&node_b)
+        };
+        a.assign(SlotRange::new(0, 16383).unwrap(), owner_b.clone())
+            .unwrap();
+        let slot = key_slot("foo");
+        assert_eq!(
+            a.route(&["foo"], *node_a.address(), false, |_| false),
+            Route::Moved(slot, owner_b.clone())
+        );
+
+        exchange((&node_b, &b), (&node_a, &a));
+        assert_eq!(
+            a.route(&["foo"], *node_a.address(), false, |_| false),
+            Route::Moved(slot, ClusterNode::from(&node_b))
+        );
+    }
+
+    #[test]
+    fn test_gossip_between_hosts() {
+        // Mesma porta em maquinas diferentes, cada nó com o seu IP anunciado
+        let address_a = SocketAddr::from(([10, 0, 0, 1], 5555));
+        let address_b = SocketAddr::from(([10, 0, 0, 2], 5555));
+        let node_a = Node::new(NodeMode::Master, address_a);
+        let node_b = Node::new(NodeMode::Master, address_b);
+        let (a, b) = (Cluster::new(), Cluster::new());
+        a.assign(SlotRange::new(0, 8191).unwrap(), ClusterNode::from(&node_a))
+            .unwrap();
+        b.assign(
+            SlotRange::new(8192, 16383).unwrap(),
+            ClusterNode::from(&node_b),
+        )
+        .unwrap();
+        b.tick(&node_b);
+
+        exchange((&node_a, &a), (&node_b, &b));
+        exchange((&node_b, &b), (&node_a, &a));
+        assert_eq!(a.peers(&node_a), vec![address_b]);
+        assert_eq!(b.peers(&node_b), vec![address_a]);
+        assert_eq!(a.assignments(), b.assignments());
+        for (node, cluster) in [(&node_a, &a), (&node_b, &b)] {
+            let nodes = cluster.nodes(&node.id);
+            let addresses: Vec<SocketAddr> = nodes.iter().map(|node| node.address).collect();
+            assert_eq!(addresses, vec![address_a, address_b]);
+        }
+
+        let slot = key_slot("foo");
+        let owner = match slot < 8192 {
+            true => (&node_b, &b, &node_a),
+            false => (&node_a, &a, &node_b),
+        };
+        assert_eq!(
+            owner
+                .1
+                .route(&["foo"], *owner.0.address(), false, |_| false),
+            Route::Moved(slot, ClusterNode::from(owner.2))
+        );
+    }
+
+    #[test]
+    fn test_forget_restarted_node() {
+        let (node_a, a) = build_member(5555);
+        let (node_b, b) = build_member(5556);
+        let slots = SlotRange::new(0, 99).unwrap();
+        a.assign(slots, ClusterNode::from(&node_b)).unwrap();
+        exchange((&node_a, &a), (&node_b, &b));
+        let before_restart = b.digest();
+
+        // `b` volta com outro id no mesmo endereço
+        std::thread::sleep(Duration::from_millis(100));
+        let (restarted, b) = build_member(5556);
+        exchange((&restarted, &b), (&node_a, &a));
+        a.tick(&node_a);
+        let nodes = a.nodes(&node_a.id);
+        assert_eq!(nodes.len(), 2, "Old id is forgotten");
+        assert_eq!(nodes[1].id, restarted.id);
+        assert_eq!(nodes[1].slots, vec![slots], "Slots follow the address");
+
+        // A visão antiga de outro nó não traz o id de volta
+        a.merge(before_restart, &node_a.id);
+        assert_eq!(a.nodes(&node_a.id).len(), 2);
+    }
+
+    #[test]
+    fn test_forget_silent_members() {
+        let (node_a, a) = build_member(5555);
+        let (node_b, b) = build_member(5556);
+        let (node_c, c) = build_member(5557);
+        a.assign(SlotRange::new(0, 99).unwrap(), ClusterNode::from(&node_c))
+            .unwrap();
+        exchange((&node_a, &a), (&node_b, &b));
+        exchange((&node_a, &a), (&node_c, &c));
+        assert_eq!(a.nodes(&node_a.id).len(), 3);
+
+        // Depois de `FORGET_TIMEOUTS` somente o nó com slots continua listado
+        std::thread::sleep(Duration::from_millis(50) * FORGET_TIMEOUTS + Duration::from_millis(50));
+        a.tick(&node_a);
+        let nodes = a.nodes(&node_a.id);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].id, node_c.id);
+        assert_eq!(nodes[1].state, NodeState::Suspect);
+    }
+
+    #[test]
+    fn test_failure_suspicions() {
+        let (node_a, a) = build_member(5555);
+        let (node_b, b) = build_member(5556);
+        let (node_c, c) = build_member(5557);
+        exchange((&node_a, &a), (&node_b, &b));
+        exchange((&node_c, &c), (&node_b, &b));
+        exchange((&node_a, &a), (&node_b, &b));
+        assert!(
+            a.nodes(&node_a.id)
+                .iter()
+                .all(|node| node.state == NodeState::Ok)
+        );
+
+        // `c` para de responder
+        std::thread::sleep(Duration::from_millis(100));
+        exchange((&node_a, &a), (&node_b, &b));
+        let state_of = |cluster: &Cluster, id: &str| {
+            let nodes = cluster.nodes(&node_a.id);
+            nodes.into_iter().find(|node| node.id == id).unwrap()
+        };
+        let suspected = state_of(&b, &node_c.id);
+        assert_eq!(suspected.state, NodeState::Suspect);
+        assert_eq!(suspected.suspected_by, vec![node_a.id.clone()]);
+
+        // As suspeitas entre `a` e `b` somem depois de uma rodada
+        exchange((&node_b, &b), (&node_a, &a));
+        exchange((&node_a, &a), (&node_b, &b));
+        let failed = state_of(&a, &node_c.id);
+        assert_eq!(failed.state, NodeState::Fail, "Two of three masters");
+        assert_eq!(failed.suspected_by.len(), 2);
+        assert_eq!(state_of(&a, &node_b.id).state, NodeState::Ok);
+
+        // `c` volta e as suspeitas somem nas rodadas seguintes
+        exchange((&node_c, &c), (&node_a, &a));
+        exchange((&node_a, &a), (&node_b, &b));
+        exchange((&node_b, &b), (&node_a, &a));
+        assert_eq!(state_of(&a, &node_c.id).state, NodeState::Ok);
+    }
+}
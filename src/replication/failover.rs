@@ -0,0 +1,267 @@
+use std::{
+    cmp::Reverse,
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio_tungstenite::connect_async;
+
+use super::{
+    Candidate, HEARTBEAT_INTERVAL, Replica, ReplicationError, ReplicationMessage, read_message,
+    send,
+};
+
+/// Tempo maximo para um slave responder o pedido de `Election`.
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Espera maxima, sorteada, antes de repetir uma eleição sem maioria.
+const ELECTION_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Situação do nó local na eleição.
+pub fn candidate(replica: &Replica) -> Candidate {
+    let node = replica.node();
+    Candidate {
+        address: *node.address(),
+        offset: replica.offset(),
+        master: node.is_master(),
+        master_ipaddr: *node.master_ipaddr(),
+        master_silence: replica.master_link.silent_for(),
+        node_id: node.id,
+    }
+}
+
+/// Verifica se o candidato concorda que o master `lost` caiu.
+///
+/// Concorda o nó ja promovido, o que passou a replicar outro master e o que
+/// tambem esta sem noticias de `lost` ha pelo menos `failover_timeout`
+/// milissegundos. Quem ainda recebe mensagens do master recusa o voto.
+pub fn votes_for_failover(candidate: &Candidate, lost: SocketAddr, failover_timeout: i64) -> bool {
+    candidate.master
+        || candidate.master_ipaddr != lost
+        || candidate
+            .master_silence
+            .is_none_or(|silence| silence >= failover_timeout)
+}
+
+/// Indica se os `voters`, contando o proprio nó, são maioria entre os
+/// `slaves` conhecidos.
+///
+/// Exige ao menos um voto alem do proprio, ja que um slave sozinho não
+/// distingue a queda do master de uma falha apenas na sua conexão com ele.
+pub fn has_quorum(voters: usize, slaves: usize) -> bool {
+    voters >= 2 && voters * 2 > slaves
+}
+
+/// Escolhe o novo master entre os candidatos.
+///
+/// Um nó que ja foi promovido vence direto, caso contrario vence o maior
+/// offset e, no empate, o menor id, assim todos os slaves que enxergam os
+/// mesmos candidatos chegam ao mesmo resultado.
+pub fn choose_winner(candidates: &[Candidate]) -> Option<&Candidate> {
+    candidates.iter().max_by_key(|candidate| {
+        (
+            candidate.master,
+            candidate.offset,
+            Reverse(&candidate.node_id),
+        )
+    })
+}
+
+/// Elege um novo master entre os slaves conhecidos depois de perder o master atual.
+///
+/// Consulta os demais slaves informados pela ultima `Topology`, ignorando os
+/// que não respondem. So segue adiante com `has_quorum`, quando a maioria
+/// desses slaves, contando o proprio nó, responde e concorda que o master
+/// caiu, assim um slave isolado dos demais ou sem outros slaves nunca se
+/// promove. Quando o vencedor é o proprio nó ele é promovido e passa a avisar
+/// o master perdido com `Demote`, caso contrario passa a replicar o vencedor.
+///
+/// Retorna `false` quando não houve maioria, depois de esperar um tempo
+/// sorteado para que os slaves não repitam a eleição juntos.
+pub async fn failover(replica: &Arc<Replica>) -> bool {
+    let local = candidate(replica);
+    let failover_timeout = replica.failover_timeout.as_millis() as i64;
+    let peers = replica.master_link.peers();
+    let mut voters = vec![local.clone()];
+    for peer in &peers {
+        if peer.id == local.node_id {
+            continue;
+        }
+        match query_candidate(peer.address).await {
+            Ok(candidate)
+                if votes_for_failover(&candidate, local.master_ipaddr, failover_timeout) =>
+            {
+                voters.push(candidate)
+            }
+            Ok(_) => eprintln!("Slave {} ainda alcança o master", peer.address),
+            Err(e) => eprintln!("Slave {} fora da eleição: {}", peer.address, e),
+        }
+    }
+
+    let slaves = 1 + peers.iter().filter(|peer| peer.id != local.node_id).count();
+    if !has_quorum(voters.len(), slaves) {
+        eprintln!(
+            "Eleição sem maioria, {} de {} slaves, tentando novamente",
+            voters.len(),
+            slaves
+        );
+        let jitter =
+            RandomState::new().hash_one(&local.node_id) % ELECTION_BACKOFF.as_millis() as u64;
+        tokio::time::sleep(Duration::from_millis(jitter)).await;
+        return false;
+    }
+
+    let Some(winner) = choose_winner(&voters) else {
+        return false;
+    };
+    if winner.node_id == local.node_id {
+        match replica.promote() {
+            Ok(()) => {
+                println!("Nó promovido a master no offset {}", winner.offset);
+                tokio::spawn(demote_lost_master(replica.clone(), local.master_ipaddr));
+            }
+            Err(e) => eprintln!("Falha ao promover o nó: {}", e),
+        }
+    } else {
+        println!(
+            "Novo master eleito: {} em {}",
+            winner.node_id, winner.address
+        );
+        replica.follow(winner.address);
+        // Da ao vencedor o mesmo prazo antes de uma nova eleição
+        replica.master_link.touch();
+    }
+    true
+}
+
+/// Avisa o master perdido a cada `HEARTBEAT_INTERVAL` que este nó venceu a
+/// eleição, ate ele voltar e se registrar como slave ou este nó deixar de ser
+/// master.
+async fn demote_lost_master(replica: Arc<Replica>, lost: SocketAddr) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let node = replica.node();
+        if !node.is_master() || replica.replica_node(lost).await.is_some() {
+            break;
+        }
+        let demote = async {
+            let (mut ws_stream, _) = connect_async(format!("ws://{}", lost)).await?;
+            send(&mut ws_stream, &ReplicationMessage::Demote(*node.address())).await
+        };
+        if tokio::time::timeout(ELECTION_TIMEOUT, demote)
+            .await
+            .is_ok_and(|sent| sent.is_ok())
+        {
+            println!("Antigo master {} avisado do failover", lost);
+        }
+    }
+}
+
+/// Atende o `Demote` do nó eleito no lugar deste master, passando a replica-lo.
+///
+/// So um master sem slaves conectados obedece, ja que a eleição exige a
+/// maioria dos slaves sem noticias dele. Retorna se o nó passou a seguir `winner`.
+pub async fn step_down(replica: &Arc<Replica>, winner: SocketAddr) -> bool {
+    let node = replica.node();
+    if !node.is_master() || *node.address() == winner || !replica.replicas().await.is_empty() {
+        return false;
+    }
+    println!(
+        "Master {} eleito no lugar deste nó, passando a replica-lo",
+        winner
+    );
+    replica.replicate_from(winner);
+    true
+}
+
+/// Pede a situação de um slave na eleição.
+async fn query_candidate(address: SocketAddr) -> Result<Candidate, ReplicationError> {
+    let query = async {
+        let (mut ws_stream, _) = connect_async(format!("ws://{}", address)).await?;
+        send(&mut ws_stream, &ReplicationMessage::Election).await?;
+        match read_message(&mut ws_stream).await? {
+            Some(ReplicationMessage::Candidate(candidate)) => Ok(candidate),
+            other => Err(ReplicationError::ParseError(format!(
+                "expected Candidate, got {:?}",
+                other
+            ))),
+        }
+    };
+    tokio::time::timeout(ELECTION_TIMEOUT, query)
+        .await
+        .map_err(|_| ReplicationError::Timeout(format!("no Candidate from {}", address)))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Candidate, choose_winner, has_quorum, votes_for_failover};
+
+    fn build_candidate(node_id: &str, offset: u64, master: bool) -> Candidate {
+        Candidate {
+            node_id: node_id.into(),
+            address: "127.0.0.1:5556".parse().unwrap(),
+            offset,
+            master,
+            master_ipaddr: "127.0.0.1:5555".parse().unwrap(),
+            master_silence: Some(6_000),
+        }
+    }
+
+    #[test]
+    fn test_choose_winner() {
+        assert_eq!(choose_winner(&[]), None);
+
+        let candidates = [
+            build_candidate("b", 10, false),
+            build_candidate("c", 12, false),
+            build_candidate("a", 12, false),
+        ];
+        let winner = choose_winner(&candidates).unwrap();
+        assert_eq!(winner.node_id, "a", "Highest offset, then lowest id");
+
+        let candidates = [
+            build_candidate("a", 12, false),
+            build_candidate("d", 3, true),
+        ];
+        let winner = choose_winner(&candidates).unwrap();
+        assert_eq!(winner.node_id, "d", "A promoted node wins directly");
+    }
+
+    #[test]
+    fn test_votes_for_failover() {
+        let lost = "127.0.0.1:5555".parse().unwrap();
+        let silent = build_candidate("a", 10, false);
+        assert!(votes_for_failover(&silent, lost, 5_000));
+
+        let mut connected = build_candidate("b", 10, false);
+        connected.master_silence = Some(200);
+        assert!(
+            !votes_for_failover(&connected, lost, 5_000),
+            "A slave that still sees the master refuses"
+        );
+
+        connected.master_ipaddr = "127.0.0.1:5556".parse().unwrap();
+        assert!(
+            votes_for_failover(&connected, lost, 5_000),
+            "A slave that already follows another master agrees"
+        );
+        assert!(votes_for_failover(
+            &build_candidate("c", 3, true),
+            lost,
+            5_000
+        ));
+    }
+
+    #[test]
+    fn test_has_quorum() {
+        assert!(!has_quorum(1, 1), "A lone slave never promotes itself");
+        assert!(!has_quorum(1, 2));
+        assert!(has_quorum(2, 2));
+        assert!(has_quorum(2, 3));
+        assert!(!has_quorum(2, 4));
+        assert!(has_quorum(3, 4));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::Sink;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Message},
+};
+
+use super::{ClusterDigest, Replica, ReplicationError, ReplicationMessage, read_message, send};
+
+/// Intervalo entre as rodadas da fofoca do cluster.
+pub const GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tempo maximo para um nó responder a fofoca.
+const GOSSIP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Troca periodicamente a visão do cluster com os outros nós.
+///
+/// A cada rodada o nó troca a visão com um dos nós conhecidos, em rodizio,
+/// assim todos convergem para a mesma visão. Termina imediatamente fora do
+/// modo cluster.
+pub async fn start_gossip(replica: Arc<Replica>) {
+    let Some(cluster) = &replica.cluster else {
+        return;
+    };
+    let mut interval = tokio::time::interval(GOSSIP_INTERVAL);
+    let mut round = 0;
+    loop {
+        interval.tick().await;
+        let peers = cluster.peers(&replica.node());
+        if peers.is_empty() {
+            continue;
+        }
+        let peer = peers[round % peers.len()];
+        round += 1;
+        if let Err(e) = gossip_with(&replica, peer).await {
+            eprintln!("Fofoca com o nó {} falhou: {}", peer, e);
+        }
+    }
+}
+
+/// Envia a visão local ao nó em `address` e junta a resposta dele.
+///
+/// Antes do envio o nó atualiza o proprio estado, com um novo `heartbeat`.
+pub async fn gossip_with(replica: &Replica, address: SocketAddr) -> Result<(), ReplicationError> {
+    let Some(cluster) = &replica.cluster else {
+        return Err(ReplicationError::Cluster("cluster mode is disabled".into()));
+    };
+    cluster.tick(&replica.node());
+    let exchange = async {
+        let (mut ws_stream, _) = connect_async(format!("ws://{}", address)).await?;
+        let digest = ReplicationMessage::Gossip(cluster.digest());
+        send(&mut ws_stream, &digest).await?;
+        match read_message(&mut ws_stream).await? {
+            Some(ReplicationMessage::Gossip(digest)) => Ok(digest),
+            Some(ReplicationMessage::Rejected(reason)) => Err(ReplicationError::Cluster(reason)),
+            other => Err(ReplicationError::ParseError(format!(
+                "expected Gossip, got {:?}",
+                other
+            ))),
+        }
+    };
+    let digest = tokio::time::timeout(GOSSIP_TIMEOUT, exchange)
+        .await
+        .map_err(|_| ReplicationError::Timeout(format!("no Gossip from {}", address)))??;
+    cluster.merge(digest, &replica.node().id);
+    Ok(())
+}
+
+/// Junta a visão recebida de outro nó e responde com a visão local.
+pub async fn answer_gossip<W>(
+    writer: &mut W,
+    replica: &Replica,
+    digest: ClusterDigest,
+) -> Result<(), ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let Some(cluster) = &replica.cluster else {
+        let reason = "cluster mode is disabled".to_string();
+        send(writer, &ReplicationMessage::Rejected(reason.clone())).await?;
+        return Err(ReplicationError::Cluster(reason));
+    };
+    cluster.merge(digest, &replica.node().id);
+    send(writer, &ReplicationMessage::Gossip(cluster.digest())).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        replication::{Cluster, ClusterNode, Node, NodeMode, Replica, SlotRange, serve},
+        test_support::{listen, wait_until},
+    };
+
+    use super::{gossip_with, start_gossip};
+
+    async fn start_node(slots: SlotRange) -> Arc<Replica> {
+        let (listener, ipaddr) = listen().await;
+        let node = Node::new(NodeMode::Master, ipaddr);
+        let cluster = Cluster::new();
+        cluster.assign(slots, ClusterNode::from(&node)).unwrap();
+        let replica = Arc::new(Replica::new(node).with_cluster(cluster));
+
+        tokio::spawn(serve(replica.clone(), listener));
+        replica
+    }
+
+    #[tokio::test]
+    async fn test_gossip_converges() {
+        let a = start_node(SlotRange::new(0, 5000).unwrap()).await;
+        let b = start_node(SlotRange::new(5001, 10000).unwrap()).await;
+        let c = start_node(SlotRange::new(10001, 16383).unwrap()).await;
+
+        // `a` e `c` so conhecem `b`
+        gossip_with(&a, *b.node().address()).await.unwrap();
+        gossip_with(&c, *b.node().address()).await.unwrap();
+        for replica in [&a, &b, &c] {
+            tokio::spawn(start_gossip(replica.clone()));
+        }
+
+        let converged = || {
+            let views: Vec<_> = [&a, &b, &c]
+                .iter()
+                .map(|replica| replica.cluster.as_ref().unwrap().assignments())
+                .collect();
+            let members = [&a, &b, &c].iter().all(|replica| {
+                let cluster = replica.cluster.as_ref().unwrap();
+                cluster.nodes(&replica.node().id).len() == 3
+            });
+            members && views[0].len() == 3 && views.iter().all(|view| *view == views[0])
+        };
+        wait_until(converged).await;
+
+        let nodes = a.cluster.as_ref().unwrap().nodes(&a.node().id);
+        let myself: Vec<_> = nodes.iter().filter(|node| node.myself).collect();
+        assert_eq!(myself.len(), 1);
+        assert_eq!(myself[0].id, a.node().id);
+        let node_c = nodes.iter().find(|node| node.id == c.node().id).unwrap();
+        assert_eq!(node_c.slots, vec![SlotRange::new(10001, 16383).unwrap()]);
+        assert!(nodes.iter().all(|node| node.role == "master"));
+    }
+
+    #[tokio::test]
+    async fn test_gossip_requires_cluster_mode() {
+        let (listener, ipaddr) = listen().await;
+        let standalone = Arc::new(Replica::new(Node::new(NodeMode::Master, ipaddr)));
+        tokio::spawn(serve(standalone.clone(), listener));
+
+        let node = Node::new(NodeMode::Master, "127.0.0.1:5556".parse().unwrap());
+        let replica = Replica::new(node).with_cluster(Cluster::new());
+        assert!(gossip_with(&replica, ipaddr).await.is_err());
+        assert!(gossip_with(&standalone, ipaddr).await.is_err());
+    }
+}
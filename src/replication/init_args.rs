@@ -1,7 +1,9 @@
-use std::sync::OnceLock;
+use std::{net::IpAddr, sync::OnceLock};
 
 use clap::Parser;
 
+use super::FAILOVER_TIMEOUT;
+
 pub static INIT_ARGS: OnceLock<InitArgs> = OnceLock::new();
 
 /// Estrutura de argumentos para inicialização do nó.
@@ -17,4 +19,35 @@ pub struct InitArgs {
     /// Porta do servidor mestre para se conectar, sendo slave.
     #[arg(long, default_value = "5555")]
     pub port: u64,
+    /// IP em que os serviços do nó escutam, como `0.0.0.0` para todas as interfaces.
+    #[arg(long, env = "CR_BIND_IP", default_value = "127.0.0.1")]
+    pub bind_ip: IpAddr,
+    /// IP pelo qual os outros nós e os clientes alcançam o nó, anunciado na
+    /// replicação, na fofoca e nos redirecionamentos do cluster. Quando não
+    /// informado é o `bind_ip`.
+    #[arg(long, env = "CR_ADVERTISED_IP")]
+    pub advertised_ip: Option<IpAddr>,
+    /// Limite de memória do cache em bytes, `0` desabilita o limite.
+    #[arg(long, env = "CR_MAX_MEMORY", default_value = "0")]
+    pub max_memory: u64,
+    /// Politica de remoção ao atingir o limite de memória entre
+    /// noeviction, allkeys-lru, allkeys-lfu, volatile-ttl e random.
+    #[arg(long, env = "CR_EVICTION_POLICY", default_value = "noeviction")]
+    pub eviction_policy: String,
+    /// Porta do listener compativel com o protocolo do Redis (RESP),
+    /// desabilitado quando não informada.
+    #[arg(long, env = "CR_RESP_PORT")]
+    pub resp_port: Option<u16>,
+    /// Porta do gateway HTTP, desabilitado quando não informada.
+    #[arg(long, env = "CR_HTTP_PORT")]
+    pub http_port: Option<u16>,
+    /// Tempo em milissegundos sem noticias do master ate os slaves elegerem um novo.
+    #[arg(long, env = "CR_FAILOVER_TIMEOUT", default_value_t = FAILOVER_TIMEOUT.as_millis() as u64)]
+    pub failover_timeout: u64,
+    /// Habilita o modo cluster, em que cada master atende somente os seus hash slots.
+    #[arg(long, env = "CR_CLUSTER_ENABLED")]
+    pub cluster_enabled: bool,
+    /// Slots atendidos pelo nó ao iniciar como master no cluster, como `0-8191`.
+    #[arg(long, env = "CR_CLUSTER_SLOTS")]
+    pub cluster_slots: Option<String>,
 }
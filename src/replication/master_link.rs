@@ -0,0 +1,122 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+use crate::memory::clock;
+
+use super::ReplicaInfo;
+
+/// Posição de um slave na replicação do master.
+///
+/// Guarda o id do log de replicação do master e o offset da ultima alteração
+/// aplicada, enviados ao reconectar para pedir somente o que foi perdido.
+///
+/// Também guarda os demais slaves do master e o momento da ultima mensagem
+/// recebida dele, usados para decidir quando e entre quem fazer a eleição.
+pub struct MasterLink {
+    /// Id do log do master, `None` enquanto nenhuma sincronização foi concluida.
+    replication_id: Mutex<Option<String>>,
+    /// Offset da ultima alteração do master aplicada no `Store` local.
+    offset: AtomicU64,
+    /// Slaves conectados ao master, informados por ele a cada heartbeat.
+    peers: Mutex<Vec<ReplicaInfo>>,
+    /// Momento da ultima mensagem do master, `0` enquanto nenhuma foi recebida.
+    last_contact: AtomicI64,
+    /// Offset do master no ultimo `Ping` junto ao momento em que foi enviado,
+    /// no relógio local.
+    pinged: Mutex<(u64, i64)>,
+}
+
+impl MasterLink {
+    pub fn new() -> Self {
+        Self {
+            replication_id: Mutex::new(None),
+            offset: AtomicU64::new(0),
+            peers: Mutex::new(Vec::new()),
+            last_contact: AtomicI64::new(0),
+            pinged: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Id do log do master e offset aplicado, usados para continuar a replicação.
+    pub fn position(&self) -> (Option<String>, u64) {
+        let replication_id = self
+            .replication_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        (replication_id.clone(), self.offset.load(Ordering::Acquire))
+    }
+
+    /// Descarta a posição atual no inicio de uma sincronização completa.
+    pub fn reset(&self) {
+        *self
+            .replication_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// Registra a conclusão de uma sincronização com o log `replication_id` ate `offset`.
+    pub fn synced(&self, replication_id: String, offset: u64) {
+        let mut current = self
+            .replication_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *current = Some(replication_id);
+        self.offset.store(offset, Ordering::Release);
+    }
+
+    /// Registra a aplicação da alteração com o offset informado.
+    pub fn applied(&self, offset: u64) {
+        self.offset.store(offset, Ordering::Release);
+    }
+
+    /// Registra o offset do master informado no `Ping` enviado em `timestamp`.
+    pub fn pinged(&self, offset: u64, timestamp: i64) {
+        *self.pinged.lock().unwrap_or_else(|e| e.into_inner()) = (offset, timestamp);
+    }
+
+    /// Atraso em milissegundos em relação ao master pelo ultimo `Ping`.
+    ///
+    /// É zero quando o slave ja aplicou o offset que o master tinha no `Ping`,
+    /// caso contrario é o tempo desde o envio desse `Ping`, ja que os dados do
+    /// slave são no maximo dessa idade.
+    pub fn lag(&self) -> i64 {
+        let (offset, timestamp) = *self.pinged.lock().unwrap_or_else(|e| e.into_inner());
+        if self.offset.load(Ordering::Acquire) >= offset {
+            return 0;
+        }
+        (clock::now_millis() - timestamp).max(0)
+    }
+
+    /// Slaves do master conhecidos pelo ultimo heartbeat, incluindo o proprio nó.
+    pub fn peers(&self) -> Vec<ReplicaInfo> {
+        self.peers.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_peers(&self, peers: Vec<ReplicaInfo>) {
+        *self.peers.lock().unwrap_or_else(|e| e.into_inner()) = peers;
+    }
+
+    /// Esquece os slaves e o ultimo contato do master anterior ao trocar de master.
+    ///
+    /// Ate alcançar o novo master o slave não participa de eleições.
+    pub fn forget(&self) {
+        self.set_peers(Vec::new());
+        self.last_contact.store(0, Ordering::Release);
+    }
+
+    /// Registra uma mensagem recebida do master.
+    pub fn touch(&self) {
+        self.last_contact
+            .store(clock::now_millis(), Ordering::Release);
+    }
+
+    /// Milissegundos desde a ultima mensagem do master, `None` se ele nunca foi alcançado.
+    pub fn silent_for(&self) -> Option<i64> {
+        match self.last_contact.load(Ordering::Acquire) {
+            0 => None,
+            last_contact => Some(clock::now_millis() - last_contact),
+        }
+    }
+}
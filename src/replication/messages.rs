@@ -0,0 +1,164 @@
+use std::{net::SocketAddr, time::Duration};
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::memory::Mutation;
+
+use super::{ClusterDigest, ClusterNode, ReplicaInfo, ReplicationError, SlotRange};
+
+/// Quantidade maxima de `keys` enviadas em cada mensagem `Snapshot`.
+pub const SNAPSHOT_BATCH: usize = 1000;
+
+/// Intervalo entre os `Ping` enviados pelo master a cada slave.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Quantidade de heartbeats sem resposta que encerram a conexão de replicação.
+pub const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// Mensagens trocadas na conexão de replicação entre master e slave.
+///
+/// Cada mensagem é enviada em um frame de texto JSON, identificada pelo campo
+/// `message` e com o conteudo em `data`.
+///
+/// Ao conectar o slave se apresenta com `Register`, informando seu id, o
+/// endereço em que atende a replicação e sua versão. O master responde
+/// `Rejected` e encerra a conexão quando não consegue registra-lo.
+///
+/// Em seguida o slave envia `Sync` com a posição em que parou. Quando o
+/// backlog do master cobre essa posição ele responde `PartialSync` e envia
+/// somente as alterações perdidas, caso contrario responde `FullSync`, envia
+/// o snapshot em varias mensagens `Snapshot` e termina com `SnapshotEnd`.
+/// Depois disso as alterações seguem em `Mutation`, na ordem dos offsets.
+///
+/// A cada `HEARTBEAT_INTERVAL` o master envia `Ping` e o slave responde `Ack`
+/// com o offset que ja aplicou, o mesmo `Ack` é enviado ao fim da
+/// sincronização. Se qualquer um dos lados passar `MAX_MISSED_HEARTBEATS`
+/// intervalos sem noticias do outro a conexão é encerrada. Junto ao `Ping`
+/// o master envia `Topology` com os slaves conectados.
+///
+/// Na eleição de um novo master cada slave conecta nos demais e envia
+/// `Election`, que é respondido com `Candidate` e encerra a conexão. O
+/// vencedor envia `Demote` ao master perdido ate que ele volte e passe a
+/// replica-lo.
+///
+/// Na migração de slots do cluster o master de origem conecta no destino e
+/// envia `Import`, que é respondido com `Importing` ou `Rejected`. As `keys`
+/// seguem em mensagens `Snapshot`, cada uma confirmada com `Imported`, e a
+/// migração termina com `ImportEnd`, quando o destino assume os slots.
+///
+/// Na fofoca do cluster um nó envia `Gossip` com a sua visão e o outro
+/// responde `Gossip` com a dele, ja juntando as duas, e encerra a conexão.
+///
+/// ```json
+/// {"message": "Register", "data": {"node_id": "9b1d...", "address": "127.0.0.1:5556", "version": "0.1.0"}}
+/// {"message": "Rejected", "data": "address 127.0.0.1:5556 already registered"}
+/// {"message": "Sync", "data": {"replication_id": "5f0c...", "offset": 40}}
+/// {"message": "FullSync", "data": {"replication_id": "5f0c...", "offset": 42}}
+/// {"message": "Snapshot", "data": [{"mutation": "Set", "data": {"key": "user:1", "value": "aGk="}}]}
+/// {"message": "SnapshotEnd"}
+/// {"message": "PartialSync", "data": {"replication_id": "5f0c...", "offset": 40}}
+/// {"message": "Mutation", "data": {"offset": 43, "mutation": {"mutation": "Delete", "data": "user:1"}}}
+/// {"message": "Ping", "data": {"offset": 43, "timestamp": 1767225600000}}
+/// {"message": "Ack", "data": {"offset": 43}}
+/// {"message": "Topology", "data": [{"id": "9b1d...", "address": "127.0.0.1:5556", "version": "0.1.0", "offset": 43, "lag": 0, "lag_ms": 0}]}
+/// {"message": "Election"}
+/// {"message": "Candidate", "data": {"node_id": "9b1d...", "address": "127.0.0.1:5556", "offset": 43, "master": false, "master_ipaddr": "127.0.0.1:5555", "master_silence": 5200}}
+/// {"message": "Import", "data": {"start": 0, "end": 99}}
+/// {"message": "Importing", "data": {"id": "7c2e...", "address": "127.0.0.1:5557", "services": {"socket": "127.0.0.1:50002"}}}
+/// {"message": "Imported", "data": 1000}
+/// {"message": "ImportEnd"}
+/// {"message": "Gossip", "data": {"members": [{"id": "9b1d...", "address": "127.0.0.1:5555", "services": {"socket": "127.0.0.1:50000"}, "role": "master", "heartbeat": 12}], "slots": [{"start": 0, "end": 16383, "node": {"id": "9b1d...", "address": "127.0.0.1:5555", "services": {"socket": "127.0.0.1:50000"}}, "epoch": 1}]}}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "message", content = "data")]
+pub enum ReplicationMessage {
+    /// Apresentação do slave, a primeira mensagem da conexão.
+    Register {
+        node_id: String,
+        address: SocketAddr,
+        version: String,
+    },
+    /// O master recusou o registro do slave pelo motivo informado.
+    Rejected(String),
+    /// Pedido do slave para continuar do offset aplicado no log `replication_id`.
+    Sync {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replication_id: Option<String>,
+        offset: u64,
+    },
+    /// Inicio de uma sincronização completa, o slave descarta os dados que possui.
+    FullSync { replication_id: String, offset: u64 },
+    /// Parte do snapshot do master, com as `keys` e seus prazos de expiração.
+    Snapshot(Vec<Mutation>),
+    /// Fim do snapshot, a partir daqui o slave esta no offset informado em `FullSync`.
+    SnapshotEnd,
+    /// A replicação continua do offset do slave, sem descartar seus dados.
+    PartialSync { replication_id: String, offset: u64 },
+    /// Alteração aplicada no master que o slave deve aplicar no seu `Store`.
+    Mutation { offset: u64, mutation: Mutation },
+    /// Heartbeat do master, com o offset atual do log e o timestamp Unix em
+    /// milissegundos do envio, usados pelo slave para medir o proprio atraso.
+    Ping { offset: u64, timestamp: i64 },
+    /// Resposta do slave com o offset da ultima alteração aplicada.
+    Ack { offset: u64 },
+    /// Slaves conectados ao master, enviados junto aos heartbeats.
+    Topology(Vec<ReplicaInfo>),
+    /// Pedido de um slave pela situação do nó durante a eleição.
+    Election,
+    /// Situação do nó na eleição, com o offset ja aplicado e se ele ja é master.
+    Candidate(Candidate),
+    /// Aviso do master eleito ao master que ele substituiu, para que este
+    /// passe a replica-lo quando voltar.
+    Demote(SocketAddr),
+    /// Pedido de outro master para transferir os slots do intervalo a este nó.
+    Import(SlotRange),
+    /// O nó aceitou receber os slots, identificando-se como o novo dono.
+    Importing(ClusterNode),
+    /// Quantidade de `keys` aplicadas pelo destino do ultimo `Snapshot` ou, apos
+    /// o `ImportEnd`, em toda a migração.
+    Imported(u64),
+    /// Fim das `keys` do intervalo, o destino passa a ser o dono dos slots.
+    ImportEnd,
+    /// Visão do cluster de quem envia, membros e slots.
+    Gossip(ClusterDigest),
+}
+
+/// Nó que participa da eleição de um novo master.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub node_id: String,
+    pub address: SocketAddr,
+    pub offset: u64,
+    pub master: bool,
+    /// Master que o nó replica.
+    pub master_ipaddr: SocketAddr,
+    /// Milissegundos desde a ultima mensagem do master, `None` se ele nunca foi alcançado.
+    pub master_silence: Option<i64>,
+}
+
+/// Lê a proxima mensagem de replicação, `None` quando a conexão termina.
+pub async fn read_message<R>(reader: &mut R) -> Result<Option<ReplicationMessage>, ReplicationError>
+where
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    while let Some(message) = reader.next().await {
+        match message? {
+            Message::Text(text) => return Ok(Some(serde_json::from_str(&text)?)),
+            Message::Close(_) => return Ok(None),
+            _ => continue,
+        }
+    }
+    Ok(None)
+}
+
+/// Envia a mensagem de replicação em um frame de texto JSON.
+pub async fn send<W>(writer: &mut W, message: &ReplicationMessage) -> Result<(), ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let text = serde_json::to_string(message)?;
+    writer.send(Message::text(text)).await?;
+    Ok(())
+}
@@ -0,0 +1,428 @@
+use std::net::SocketAddr;
+
+use futures_util::{Sink, Stream};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Message},
+};
+
+use crate::memory::Mutation;
+
+use super::{
+    Cluster, ClusterNode, Replica, ReplicationError, ReplicationMessage, SNAPSHOT_BATCH, SlotRange,
+    key_slot, read_message, send,
+};
+
+/// Transfere os slots do intervalo, com suas `keys`, para o master em `target`.
+///
+/// A migração acontece com o nó atendendo clientes. As `keys` são removidas
+/// daqui lote a lote e enviadas ao destino, e enquanto isso as requisições
+/// por `keys` que ja sairam são redirecionadas com `Ask`. No fim os dois nós
+/// passam a apontar o destino como dono dos slots.
+///
+/// Se a migração falhar depois que o destino confirmou alguma `key`, os
+/// slots continuam em migração, assim o `Ask` ainda leva ate as `keys` que
+/// ja estão no destino, e a migração deve ser refeita para o mesmo destino.
+///
+/// Retorna quantas `keys` foram transferidas.
+pub async fn migrate_slots(
+    replica: &Replica,
+    slots: SlotRange,
+    target: SocketAddr,
+) -> Result<u64, ReplicationError> {
+    let cluster = enabled_cluster(replica)?;
+    let node = replica.node();
+    if node.is_slave() {
+        return Err(ReplicationError::Cluster(
+            "only masters can migrate slots".into(),
+        ));
+    }
+    if target == *node.address() {
+        return Err(ReplicationError::Cluster(
+            "slots can't be migrated to the same node".into(),
+        ));
+    }
+
+    let (mut ws_stream, _) = connect_async(format!("ws://{}", target)).await?;
+    send(&mut ws_stream, &ReplicationMessage::Import(slots)).await?;
+    let target_node = match read_message(&mut ws_stream).await? {
+        Some(ReplicationMessage::Importing(target_node)) => target_node,
+        Some(ReplicationMessage::Rejected(reason)) => {
+            return Err(ReplicationError::Cluster(reason));
+        }
+        other => {
+            return Err(ReplicationError::ParseError(format!(
+                "expected Importing, got {:?}",
+                other
+            )));
+        }
+    };
+
+    cluster.start_migration(slots, *node.address(), target_node.clone())?;
+    let mut moved = 0;
+    match send_keys(&mut ws_stream, replica, slots, &mut moved).await {
+        Ok(()) => {
+            cluster.assign(slots, target_node)?;
+            Ok(moved)
+        }
+        Err(e) if moved == 0 => {
+            cluster.cancel_migration(slots);
+            Err(e)
+        }
+        Err(e) => Err(ReplicationError::Cluster(format!(
+            "migration of slots {}-{} stopped after {} keys, retry it to the same target: {}",
+            slots.start, slots.end, moved, e
+        ))),
+    }
+}
+
+/// Envia as `keys` dos slots ate não restar nenhuma e encerra com `ImportEnd`.
+///
+/// A lista é refeita depois de cada passada para pegar as `keys` escritas
+/// enquanto a anterior era enviada. Um lote que o destino não confirma
+/// volta para o `Store` local, os confirmados são somados em `moved`.
+async fn send_keys<S>(
+    ws_stream: &mut S,
+    replica: &Replica,
+    slots: SlotRange,
+    moved: &mut u64,
+) -> Result<(), ReplicationError>
+where
+    S: Sink<Message, Error = tungstenite::Error>
+        + Stream<Item = Result<Message, tungstenite::Error>>
+        + Unpin,
+{
+    loop {
+        let keys = replica
+            .store
+            .keys_where(|key| slots.contains(key_slot(key)));
+        if keys.is_empty() {
+            break;
+        }
+
+        for batch in keys.chunks(SNAPSHOT_BATCH) {
+            let mutations: Vec<Mutation> = batch
+                .iter()
+                .filter_map(|key| replica.store.take(key))
+                .collect();
+            let count = mutations.len() as u64;
+            let message = ReplicationMessage::Snapshot(mutations.clone());
+            if let Err(e) = send_batch(ws_stream, &message).await {
+                for mutation in mutations {
+                    if let Err(e) = replica.store.import(mutation).await {
+                        eprintln!("Falha ao restaurar uma key da migração: {}", e);
+                    }
+                }
+                return Err(e);
+            }
+            *moved += count;
+        }
+    }
+
+    send(ws_stream, &ReplicationMessage::ImportEnd).await?;
+    match read_message(ws_stream).await? {
+        Some(ReplicationMessage::Imported(_)) => Ok(()),
+        other => Err(ReplicationError::ParseError(format!(
+            "expected Imported, got {:?}",
+            other
+        ))),
+    }
+}
+
+/// Envia um lote de `keys` e espera a confirmação do destino.
+async fn send_batch<S>(
+    ws_stream: &mut S,
+    message: &ReplicationMessage,
+) -> Result<(), ReplicationError>
+where
+    S: Sink<Message, Error = tungstenite::Error>
+        + Stream<Item = Result<Message, tungstenite::Error>>
+        + Unpin,
+{
+    send(ws_stream, message).await?;
+    match read_message(ws_stream).await? {
+        Some(ReplicationMessage::Imported(_)) => Ok(()),
+        other => Err(ReplicationError::ParseError(format!(
+            "expected Imported, got {:?}",
+            other
+        ))),
+    }
+}
+
+/// Recebe os slots migrados por outro master, pedidos com `Import`.
+///
+/// As `keys` recebidas não substituem as escritas feitas aqui durante a
+/// migração. Ao receber `ImportEnd` o nó passa a ser o dono dos slots. Se a
+/// conexão cair antes disso sem nenhuma `key` recebida os slots deixam de
+/// ser recebidos, senão continuam aceitando o `Ask` ate a migração ser refeita.
+pub async fn import_slots<W, R>(
+    writer: &mut W,
+    reader: &mut R,
+    replica: &Replica,
+    slots: SlotRange,
+) -> Result<(), ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let node = replica.node();
+    let accepted = enabled_cluster(replica).and_then(|cluster| match node.is_master() {
+        true => cluster.start_import(slots).map(|()| cluster),
+        false => Err(ReplicationError::Cluster(
+            "only masters can import slots".into(),
+        )),
+    });
+    let cluster = match accepted {
+        Ok(cluster) => cluster,
+        Err(e) => {
+            send(writer, &ReplicationMessage::Rejected(e.to_string())).await?;
+            return Err(e);
+        }
+    };
+    let local = ClusterNode::from(&node);
+    send(writer, &ReplicationMessage::Importing(local.clone())).await?;
+
+    let (mut received, mut imported) = (0, 0);
+    let result = loop {
+        match read_message(reader).await {
+            Ok(Some(ReplicationMessage::Snapshot(mutations))) => {
+                received += mutations.len();
+                let mut applied = 0;
+                for mutation in mutations {
+                    match replica.store.import(mutation).await {
+                        Ok(true) => applied += 1,
+                        Ok(false) => {}
+                        Err(e) => eprintln!("Falha ao importar uma key: {}", e),
+                    }
+                }
+                imported += applied;
+                if let Err(e) = send(writer, &ReplicationMessage::Imported(applied)).await {
+                    break Err(e);
+                }
+            }
+            Ok(Some(ReplicationMessage::ImportEnd)) => {
+                if let Err(e) = cluster.assign(slots, local) {
+                    break Err(e);
+                }
+                println!(
+                    "Slots {}-{} importados com {} keys",
+                    slots.start, slots.end, imported
+                );
+                return send(writer, &ReplicationMessage::Imported(imported)).await;
+            }
+            Ok(None) => {
+                break Err(ReplicationError::Cluster(
+                    "connection closed during slot import".into(),
+                ));
+            }
+            Ok(Some(other)) => {
+                break Err(ReplicationError::ParseError(format!(
+                    "expected Snapshot or ImportEnd, got {:?}",
+                    other
+                )));
+            }
+            Err(e) => break Err(e),
+        }
+    };
+
+    if received == 0 {
+        cluster.cancel_import(slots);
+    }
+    result
+}
+
+fn enabled_cluster(replica: &Replica) -> Result<&Cluster, ReplicationError> {
+    replica
+        .cluster
+        .as_ref()
+        .ok_or_else(|| ReplicationError::Cluster("cluster mode is disabled".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+
+    use tokio_tungstenite::accept_async;
+
+    use crate::{
+        memory::CacheValue,
+        replication::{
+            Cluster, ClusterNode, Node, NodeMode, Replica, ReplicationMessage, Route, Services,
+            SlotRange, key_slot, read_message, send, serve,
+        },
+        test_support::listen,
+    };
+
+    use super::migrate_slots;
+
+    /// Endereço da origem, que não precisa atender conexões.
+    const SOURCE: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5555);
+
+    fn build_master(ipaddr: SocketAddr, cluster: Option<Cluster>) -> Arc<Replica> {
+        let replica = Replica::new(Node::new(NodeMode::Master, ipaddr));
+        Arc::new(match cluster {
+            Some(cluster) => replica.with_cluster(cluster),
+            None => replica,
+        })
+    }
+
+    /// Master que atende a replicação, destino das migrações.
+    async fn start_master(cluster: Option<Cluster>) -> Arc<Replica> {
+        let (listener, ipaddr) = listen().await;
+        let replica = build_master(ipaddr, cluster);
+        tokio::spawn(serve(replica.clone(), listener));
+        replica
+    }
+
+    #[tokio::test]
+    async fn test_migrate_slots() {
+        let source = build_master(SOURCE, Some(Cluster::new()));
+        let target = start_master(Some(Cluster::new())).await;
+        let source_node = ClusterNode::from(&source.node());
+        let target_node = ClusterNode::from(&target.node());
+        let all = SlotRange::new(0, 16383).unwrap();
+        for replica in [&source, &target] {
+            let cluster = replica.cluster.as_ref().unwrap();
+            cluster.assign(all, source_node.clone()).unwrap();
+        }
+
+        let slots = SlotRange::new(0, 8191).unwrap();
+        let keys: Vec<String> = (0..2500).map(|i| format!("key:{}", i)).collect();
+        for key in &keys {
+            source
+                .store
+                .set(key.clone(), CacheValue::new(key.as_str()))
+                .unwrap();
+        }
+        let (moving, staying): (Vec<&String>, Vec<&String>) =
+            keys.iter().partition(|key| slots.contains(key_slot(key)));
+        // Escrita feita no destino durante a migração vence a copia antiga
+        target
+            .store
+            .set(moving[0].clone(), CacheValue::new("newer"))
+            .unwrap();
+
+        let moved = migrate_slots(&source, slots, *target.node().address())
+            .await
+            .unwrap();
+        assert_eq!(moved, moving.len() as u64);
+        assert_eq!(source.store.len(), staying.len() as u64);
+        assert_eq!(target.store.len(), moving.len() as u64);
+        assert_eq!(target.store.get(moving[0]), Some(CacheValue::new("newer")));
+        assert_eq!(
+            target.store.get(moving[1]),
+            Some(CacheValue::new(moving[1].as_str()))
+        );
+        assert!(
+            source.log.offset() > keys.len() as u64,
+            "Deletes are replicated"
+        );
+
+        for replica in [&source, &target] {
+            let assignments = replica.cluster.as_ref().unwrap().assignments();
+            assert_eq!(assignments[0].slots, slots);
+            assert_eq!(assignments[0].node, target_node);
+            assert_eq!(assignments[1].node, source_node);
+        }
+        assert_eq!(
+            source.route(&[moving[1]], false),
+            Route::Moved(key_slot(moving[1]), target_node.clone())
+        );
+        assert_eq!(target.route(&[moving[1]], false), Route::Local);
+        assert_eq!(source.route(&[staying[0]], false), Route::Local);
+
+        // Slots que não pertencem mais a origem
+        assert!(
+            migrate_slots(&source, slots, *target.node().address())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_to_node_without_cluster() {
+        let source = build_master(SOURCE, Some(Cluster::new()));
+        let target = start_master(None).await;
+        let all = SlotRange::new(0, 16383).unwrap();
+        let cluster = source.cluster.as_ref().unwrap();
+        cluster
+            .assign(all, ClusterNode::from(&source.node()))
+            .unwrap();
+
+        source
+            .store
+            .set("foo".into(), CacheValue::new("1"))
+            .unwrap();
+        let result = migrate_slots(&source, all, *target.node().address()).await;
+        assert!(result.is_err(), "Target must reject the import");
+        assert_eq!(source.store.get("foo"), Some(CacheValue::new("1")));
+        assert_eq!(source.route(&["foo"], false), Route::Local);
+    }
+
+    /// Destino que confirma somente o primeiro lote de `keys` e derruba a conexão.
+    async fn failing_target() -> ClusterNode {
+        let (listener, address) = listen().await;
+        let node = ClusterNode {
+            id: "target".into(),
+            address,
+            services: Services::default(),
+        };
+        let importing = node.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws_stream = accept_async(stream).await.unwrap();
+            let _ = read_message(&mut ws_stream).await;
+            let message = ReplicationMessage::Importing(importing);
+            send(&mut ws_stream, &message).await.unwrap();
+            if let Ok(Some(ReplicationMessage::Snapshot(mutations))) =
+                read_message(&mut ws_stream).await
+            {
+                let message = ReplicationMessage::Imported(mutations.len() as u64);
+                send(&mut ws_stream, &message).await.unwrap();
+            }
+        });
+        node
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_migration_keeps_asking() {
+        let source = build_master(SOURCE, Some(Cluster::new()));
+        let source_node = ClusterNode::from(&source.node());
+        let all = SlotRange::new(0, 16383).unwrap();
+        let cluster = source.cluster.as_ref().unwrap();
+        cluster.assign(all, source_node.clone()).unwrap();
+        let target = failing_target().await;
+
+        let keys: Vec<String> = (0..2500).map(|i| format!("key:{}", i)).collect();
+        for key in &keys {
+            source
+                .store
+                .set(key.clone(), CacheValue::new(key.as_str()))
+                .unwrap();
+        }
+        let result = migrate_slots(&source, all, target.address).await;
+        assert!(result.is_err());
+        assert_eq!(
+            source.store.len(),
+            keys.len() as u64 - 1000,
+            "Unconfirmed batch is restored"
+        );
+
+        // As `keys` confirmadas continuam alcançaveis pelo `Ask`
+        let moved = keys.iter().find(|key| !source.store.exists(key)).unwrap();
+        assert_eq!(
+            source.route(&[moved], false),
+            Route::Ask(key_slot(moved), target.clone())
+        );
+        assert_eq!(cluster.assignments()[0].node, source_node);
+        assert!(
+            cluster
+                .start_migration(all, source_node.address, target)
+                .is_ok(),
+            "Migration can be retried"
+        );
+    }
+}
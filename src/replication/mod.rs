@@ -1,30 +1,111 @@
+mod client;
+mod cluster;
+mod failover;
+mod gossip;
 mod init_args;
+mod master_link;
+mod messages;
+mod migration;
 mod node;
 mod replica;
+mod replication_log;
 mod server;
 
+pub use client::*;
+pub use cluster::*;
+pub use failover::*;
+pub use gossip::*;
 pub use init_args::*;
+pub use master_link::*;
+pub use messages::*;
+pub use migration::*;
 pub use node::*;
 pub use replica::*;
+pub use replication_log::*;
 pub use server::*;
 
 use tokio::task::JoinHandle;
 
-use std::{env, fmt::Display, net::SocketAddr, sync::Arc};
+use std::{
+    env,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 pub fn create_node() -> Result<Node, ReplicationError> {
     let args = INIT_ARGS.get().unwrap();
     let mode = NodeMode::try_from(args.mode.clone())?;
-    match mode {
-        NodeMode::Master => {
-            let ipaddr = "127.0.0.1:50000".parse()?;
-            return Ok(Node::new(mode, ipaddr));
-        }
+    let ip = advertised_ip(args)?;
+    let address = replication_address(ip)?;
+    let node = match mode {
+        NodeMode::Master => Node::new(mode, address),
         NodeMode::Slave => {
             let ipaddr = format!("{}:{}", args.master_ip, args.port).parse()?;
-            return Ok(Node::new(mode, ipaddr));
+            Node::new(mode, ipaddr).with_address(address)
         }
+    };
+    Ok(node.with_services(service_addresses(args, ip)))
+}
+
+/// Cria a visão do cluster quando o modo cluster esta habilitado, ja com
+/// os slots iniciais de um nó master.
+pub fn create_cluster(node: &Node) -> Result<Option<Cluster>, ReplicationError> {
+    let args = INIT_ARGS.get().unwrap();
+    if !args.cluster_enabled {
+        return Ok(None);
+    }
+
+    // Um nó é suspeito depois do mesmo silencio que inicia uma eleição
+    let cluster = Cluster::new().with_node_timeout(Duration::from_millis(args.failover_timeout));
+    if let (Some(slots), true) = (&args.cluster_slots, node.is_master()) {
+        cluster.assign(slots.parse()?, ClusterNode::from(node))?;
+    }
+    Ok(Some(cluster))
+}
+
+/// IP anunciado pelo nó, o `advertised_ip` ou, sem ele, o `bind_ip`.
+///
+/// Um `bind_ip` como `0.0.0.0` não alcança o nó de outra maquina, nesse caso
+/// o `advertised_ip` é obrigatorio.
+pub fn advertised_ip(args: &InitArgs) -> Result<IpAddr, ReplicationError> {
+    let ip = args.advertised_ip.unwrap_or(args.bind_ip);
+    if ip.is_unspecified() {
+        return Err(ReplicationError::AddrParseError(format!(
+            "{} can't be advertised, set CR_ADVERTISED_IP",
+            ip
+        )));
     }
+    Ok(ip)
+}
+
+/// Endereço anunciado do servidor de replicação, na porta de `CR_REPLICATION_PORT`.
+pub fn replication_address(ip: IpAddr) -> Result<SocketAddr, ReplicationError> {
+    let replication_port = env::var("CR_REPLICATION_PORT").unwrap_or_else(|_| "5555".to_string());
+    let port = replication_port.parse().map_err(|_| {
+        ReplicationError::AddrParseError(format!("invalid replication port {}", replication_port))
+    })?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Endereços anunciados dos serviços para os clientes.
+///
+/// O serviço de socket sempre é iniciado, na porta de `CR_SERVICE_PORT`, os
+/// demais somente quando suas portas são informadas.
+pub fn service_addresses(args: &InitArgs, ip: IpAddr) -> Services {
+    let service_port = env::var("CR_SERVICE_PORT").unwrap_or_else(|_| "50000".to_string());
+    let advertised = |port: u16| SocketAddr::new(ip, port);
+    Services {
+        socket: service_port.parse().ok().map(advertised),
+        resp: args.resp_port.map(advertised),
+        http: args.http_port.map(advertised),
+    }
+}
+
+/// Endereço em que o nó escuta para atender o endereço anunciado, no `bind_ip`.
+pub fn bind_address(advertised: SocketAddr) -> SocketAddr {
+    SocketAddr::new(INIT_ARGS.get().unwrap().bind_ip, advertised.port())
 }
 
 pub async fn start_replication_tasks(
@@ -32,26 +113,29 @@ pub async fn start_replication_tasks(
 ) -> Result<Vec<JoinHandle<()>>, ReplicationError> {
     let mut tasks = Vec::new();
 
-    let replication_port = env::var("CR_REPLICATION_PORT").unwrap_or_else(|_| "5555".to_string());
-
-    // Replicação sempre sera ligado ao localhost mesmo sendo um slave
-    // Depois melhorar o entendimento e a coesão entre as classes.
-    let localhost_with: SocketAddr = format!("127.0.0.1:{}", replication_port).parse()?;
+    // O servidor de replicação roda mesmo sendo um slave, para as eleições e a fofoca
+    let bind_with = bind_address(*replica.node().address());
     let replica_task = replica.clone();
     let rp_server_task = tokio::spawn(async move {
-        if start_server(replica_task, localhost_with).await.is_err() {
+        if start_server(replica_task, bind_with).await.is_err() {
             eprintln!("Failed to start replication server");
         }
     });
 
     tasks.push(rp_server_task);
-    if replica.node.is_master() {
+    // A fofoca do cluster roda em masters e slaves
+    if replica.cluster.is_some() {
+        tasks.push(tokio::spawn(start_gossip(replica.clone())));
+    }
+    if replica.node().is_master() {
         println!("A conexão cliente para a replicação sera ignorada quando o nó for master");
         return Ok(tasks);
     }
 
     // Replicação como cliente do slave para o servidor master somente sera
-    // iniciado se a replica tiver um nó slave.
+    // iniciado se a replica tiver um nó slave. A tarefa fica com a replica,
+    // que a encerra ou reinicia quando o papel do nó muda.
+    replica.start_client_task();
 
     Ok(tasks)
 }
@@ -59,10 +143,13 @@ pub async fn start_replication_tasks(
 #[derive(Debug)]
 pub enum ReplicationError {
     AddrParseError(String),
+    Cluster(String),
     Register(String),
     ParseError(String),
+    Timeout(String),
     Tokio(tokio::io::Error),
     Unregister(String),
+    WebSocket(String),
 }
 
 impl From<std::net::AddrParseError> for ReplicationError {
@@ -77,21 +164,36 @@ impl From<tokio::io::Error> for ReplicationError {
     }
 }
 
+impl From<tokio_tungstenite::tungstenite::Error> for ReplicationError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        ReplicationError::WebSocket(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ReplicationError {
+    fn from(err: serde_json::Error) -> Self {
+        ReplicationError::ParseError(err.to_string())
+    }
+}
+
 impl std::error::Error for ReplicationError {}
 
 impl std::fmt::Display for ReplicationError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ReplicationError::AddrParseError(msg) => write!(f, "Address parse error: {}", msg),
+            ReplicationError::Cluster(msg) => write!(f, "Cluster error: {}", msg),
             ReplicationError::Register(msg) => write!(f, "Register error: {}", msg),
             ReplicationError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            ReplicationError::Timeout(msg) => write!(f, "Timeout: {}", msg),
             ReplicationError::Tokio(msg) => write!(f, "Tokio error: {}", msg),
             ReplicationError::Unregister(msg) => write!(f, "Unregister error: {}", msg),
+            ReplicationError::WebSocket(msg) => write!(f, "WebSocket error: {}", msg),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NodeMode {
     Master,
     Slave,
@@ -130,3 +232,40 @@ impl Display for NodeMode {
         write!(f, "{}", mode_str)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, SocketAddr};
+
+    use clap::Parser;
+
+    use super::{InitArgs, advertised_ip, replication_address, service_addresses};
+
+    #[test]
+    fn test_advertised_addresses() {
+        let args = InitArgs::parse_from(["crusty-cache"]);
+        assert_eq!(advertised_ip(&args).unwrap(), IpAddr::from([127, 0, 0, 1]));
+
+        let args = InitArgs::parse_from(["crusty-cache", "--bind-ip", "0.0.0.0"]);
+        assert!(
+            advertised_ip(&args).is_err(),
+            "Unspecified IP can't be dialed"
+        );
+
+        let args = InitArgs::parse_from([
+            "crusty-cache",
+            "--bind-ip",
+            "0.0.0.0",
+            "--advertised-ip",
+            "10.0.0.2",
+            "--resp-port",
+            "6379",
+        ]);
+        let ip = advertised_ip(&args).unwrap();
+        assert_eq!(ip, IpAddr::from([10, 0, 0, 2]));
+        assert_eq!(replication_address(ip).unwrap().ip(), ip);
+        let services = service_addresses(&args, ip);
+        assert_eq!(services.resp, Some(SocketAddr::from(([10, 0, 0, 2], 6379))));
+        assert_eq!(services.http, None);
+    }
+}
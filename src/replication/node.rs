@@ -1,29 +1,84 @@
-use std::{env, net::SocketAddr};
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use super::{NodeMode, ReplicationError};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Node {
+    /// Id unico do nó, gerado na criação.
+    pub id: String,
     pub mode: NodeMode,
+    /// Versão do crusty-cache em execução no nó.
+    pub version: String,
+    /// Endereço em que o nó atende a replicação.
+    address: SocketAddr,
     master_ipaddr: SocketAddr,
+    /// Endereços em que o nó atende os clientes.
+    services: Services,
+}
+
+/// Endereços em que um nó atende os clientes, um por protocolo, usados nos
+/// redirecionamentos do cluster. Os protocolos desabilitados ficam `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Services {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<SocketAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resp: Option<SocketAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<SocketAddr>,
 }
 
 impl Node {
+    /// Cria o nó com um novo id, usando o endereço do master também como o
+    /// endereço do proprio nó ate que `with_address` informe outro.
     pub fn new(mode: NodeMode, ipaddr: SocketAddr) -> Self {
         Self {
+            id: Uuid::new_v4().to_string(),
             mode,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            address: ipaddr,
             master_ipaddr: ipaddr,
+            services: Services::default(),
         }
     }
 
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    pub fn with_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    pub fn with_services(mut self, services: Services) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Troca o modo do nó, que passa a ser o proprio master.
     pub fn promote(&mut self, mode: String) -> Result<(), ReplicationError> {
         self.mode = NodeMode::try_from(mode)?;
-        let default_port = env::var("CR_SERVICE_PORT").unwrap_or_else(|_| "50000".to_string());
-        self.master_ipaddr = format!("127.0.0.1:{}", default_port).parse()?;
+        self.master_ipaddr = self.address;
 
         Ok(())
     }
 
+    /// Torna o nó um slave do master no endereço informado.
+    pub fn follow(&mut self, master: SocketAddr) {
+        self.mode = NodeMode::Slave;
+        self.master_ipaddr = master;
+    }
+
     pub fn is_master(&self) -> bool {
         self.mode.is_master()
     }
@@ -32,9 +87,17 @@ impl Node {
         self.mode.is_slave()
     }
 
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+
     pub fn master_ipaddr(&self) -> &SocketAddr {
         &self.master_ipaddr
     }
+
+    pub fn services(&self) -> &Services {
+        &self.services
+    }
 }
 
 #[cfg(test)]
@@ -104,5 +167,19 @@ mod tests {
             8081,
             "Node port does not match"
         );
+
+        let mut node =
+            Node::new(NodeMode::Slave, ipaddr).with_address("127.0.0.1:5556".parse().unwrap());
+        node.promote("master".into())
+            .expect("Must promote node without errors");
+        assert_eq!(
+            node.master_ipaddr(),
+            node.address(),
+            "Promoted node must be its own master"
+        );
+
+        node.follow(ipaddr);
+        assert!(node.is_slave(), "Must be a slave node");
+        assert_eq!(node.master_ipaddr(), &ipaddr);
     }
 }
@@ -3,60 +3,368 @@ use std::{
     net::SocketAddr,
     sync::{
         Arc,
-        atomic::{AtomicU16, Ordering},
+        atomic::{AtomicI64, AtomicU64, Ordering},
     },
+    time::Duration,
 };
 
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Notify, RwLock, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::memory::{Store, clock};
+
+use super::{
+    Cluster, ClusterNode, HEARTBEAT_INTERVAL, MAX_MISSED_HEARTBEATS, MasterLink, Node,
+    ReplicationError, ReplicationLog, Route, start_client,
+};
 
-use super::Node;
+/// Tempo padrão sem noticias do master ate um slave iniciar a eleição.
+pub const FAILOVER_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Replica {
+    /// Nó local, alterado quando ele é promovido ou passa a seguir outro master.
+    node: std::sync::RwLock<Node>,
+    /// Tempo sem noticias do master ate um slave iniciar a eleição de um novo.
+    pub failover_timeout: Duration,
+    /// Cache principal do nó, compartilhado entre os serviços de socket e replicação.
+    pub store: Arc<Store>,
+    /// Fluxo das alterações do `store` enviado aos slaves.
+    pub log: Arc<ReplicationLog>,
+    /// Posição do nó na replicação do master, usada quando ele é um slave.
+    pub master_link: MasterLink,
+    /// Divisão dos hash slots entre os masters, presente no modo cluster.
+    pub cluster: Option<Cluster>,
+    /// Slaves conectados ao master, indexados pelo endereço de replicação anunciado.
+    replica_nodes: Arc<RwLock<HashMap<SocketAddr, Arc<ReplicaNode>>>>,
+    /// Tarefa do cliente de replicação, presente enquanto o nó replica um master.
+    client_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+    /// Avisa quem espera confirmações a cada `Ack` recebido de um slave.
+    acks: Notify,
+    /// Geração dos pedidos de `Ping` imediato às conexões com os slaves,
+    /// antecipando os `Ack`. Cada conexão acompanha a geração que ja atendeu,
+    /// assim nenhum pedido se perde enquanto ela envia outras mensagens.
+    ack_requests: watch::Sender<u64>,
+}
+
+/// Slave registrado no master pelo handshake da replicação.
+#[derive(Debug)]
+pub struct ReplicaNode {
     pub node: Node,
-    replicas_length: AtomicU16,
-    replica_nodes: Arc<RwLock<HashMap<SocketAddr, Node>>>,
+    /// Offset confirmado pelo slave no ultimo `Ack`.
+    offset: AtomicU64,
+    /// Momento do ultimo `Ack` no relógio do cache.
+    acked_at: AtomicI64,
+}
+
+impl ReplicaNode {
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::Acquire)
+    }
+
+    /// Registra que o slave confirmou a aplicação das alterações ate `offset`.
+    pub fn acknowledge(&self, offset: u64) {
+        self.offset.fetch_max(offset, Ordering::AcqRel);
+        self.acked_at.store(clock::now_millis(), Ordering::Release);
+    }
+
+    /// Situação do slave em relação ao log do master que esta em `master_offset`.
+    fn info(&self, master_offset: u64) -> ReplicaInfo {
+        let offset = self.offset();
+        let lag = master_offset.saturating_sub(offset);
+        let lag_ms = match lag {
+            0 => 0,
+            _ => (clock::now_millis() - self.acked_at.load(Ordering::Acquire)).max(0) as u64,
+        };
+        ReplicaInfo {
+            id: self.node.id.clone(),
+            address: *self.node.address(),
+            version: self.node.version.clone(),
+            offset,
+            lag,
+            lag_ms,
+        }
+    }
+}
+
+/// Situação de um slave conectado, listada pelo comando `Replicas`.
+///
+/// O `lag` é a quantidade de alterações do log do master que o slave ainda não
+/// confirmou e o `lag_ms` ha quanto tempo o slave não confirma estar em dia,
+/// zero quando ele ja confirmou todas.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplicaInfo {
+    pub id: String,
+    pub address: SocketAddr,
+    pub version: String,
+    pub offset: u64,
+    pub lag: u64,
+    pub lag_ms: u64,
+}
+
+/// Estado da replicação do nó, exibido junto as estatisticas do cache.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplicationStats {
+    pub role: String,
+    /// Offset do log no master, ou o ultimo aplicado do master em um slave.
+    pub offset: u64,
+    pub connected_slaves: usize,
+    pub replicas: Vec<ReplicaInfo>,
 }
 
 impl Replica {
+    #[cfg(test)]
     pub fn new(node: Node) -> Self {
+        Self::with_store(node, Store::new())
+    }
+
+    /// Cria a replica usando um `Store` ja configurado, como o limite de memória.
+    pub fn with_store(node: Node, store: Store) -> Self {
+        let log = Arc::new(ReplicationLog::new());
+        store.observe(log.clone());
+        store.set_expiry_from_master(node.is_slave());
+
         Self {
-            node,
-            replicas_length: AtomicU16::new(0),
+            node: std::sync::RwLock::new(node),
+            failover_timeout: FAILOVER_TIMEOUT,
+            store: Arc::new(store),
+            log,
+            master_link: MasterLink::new(),
+            cluster: None,
             replica_nodes: Arc::new(RwLock::new(HashMap::new())),
+            client_task: std::sync::Mutex::new(None),
+            acks: Notify::new(),
+            ack_requests: watch::channel(0).0,
         }
     }
 
-    pub async fn replicas_length(&self) -> u16 {
-        self.replicas_length.load(Ordering::Acquire)
+    pub fn with_failover_timeout(mut self, failover_timeout: Duration) -> Self {
+        self.failover_timeout = failover_timeout;
+        self
+    }
+
+    /// Habilita o modo cluster, ja com o nó local entre os membros.
+    pub fn with_cluster(mut self, cluster: Cluster) -> Self {
+        cluster.tick(&self.node());
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Copia do nó local no momento da chamada.
+    pub fn node(&self) -> Node {
+        self.node.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Indica se o nó rejeita escritas de clientes, o que acontece nos slaves
+    /// ja que todo o conteudo deles vem da replicação.
+    pub fn is_read_only(&self) -> bool {
+        self.node
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_slave()
+    }
+
+    /// Atraso em milissegundos dos dados do nó em relação ao master.
+    ///
+    /// É zero no master. Em um slave é o atraso medido pelo ultimo `Ping`, ver
+    /// `MasterLink::lag`, ou o tempo sem noticias do master quando a conexão
+    /// com ele foi perdida, ja que nada garante que ele não recebeu escritas.
+    /// `None` enquanto nenhuma sincronização foi concluida.
+    pub fn staleness(&self) -> Option<u64> {
+        if !self.is_read_only() {
+            return Some(0);
+        }
+        self.master_link.position().0?;
+        let silence = self.master_link.silent_for()?;
+        let disconnected = (HEARTBEAT_INTERVAL * MAX_MISSED_HEARTBEATS).as_millis() as i64;
+        let staleness = match silence > disconnected {
+            true => silence,
+            false => self.master_link.lag(),
+        };
+        Some(staleness.max(0) as u64)
+    }
+
+    /// Promove o nó a master, passando a aceitar escritas, e encerra o cliente de replicação.
+    ///
+    /// No cluster o nó assume os slots do master que replicava.
+    pub fn promote(&self) -> Result<(), ReplicationError> {
+        let mut node = self.node.write().unwrap_or_else(|e| e.into_inner());
+        let previous = *node.master_ipaddr();
+        node.promote("master".into())?;
+        let promoted = ClusterNode::from(&*node);
+        drop(node);
+        self.store.set_expiry_from_master(false);
+        if let Some(cluster) = &self.cluster {
+            cluster.take_over(previous, promoted);
+        }
+
+        self.stop_client_task();
+        Ok(())
+    }
+
+    /// Decide se as `keys` de uma requisição são atendidas por este nó.
+    ///
+    /// Fora do modo cluster todas são locais. Um slave atende os slots do seu master.
+    pub fn route(&self, keys: &[&str], asking: bool) -> Route {
+        let Some(cluster) = &self.cluster else {
+            return Route::Local;
+        };
+        let node = self.node();
+        let local = match node.is_master() {
+            true => *node.address(),
+            false => *node.master_ipaddr(),
+        };
+        cluster.route(keys, local, asking, |key| self.store.exists(key))
+    }
+
+    /// Passa a replicar o master no endereço informado, sem mexer no cliente de replicação.
+    ///
+    /// A partir daqui as `keys` expiradas so são removidas pelo novo master.
+    pub fn follow(&self, master: SocketAddr) {
+        let mut node = self.node.write().unwrap_or_else(|e| e.into_inner());
+        node.follow(master);
+        self.store.set_expiry_from_master(true);
+    }
+
+    /// Torna o nó um slave do master informado, reiniciando o cliente de replicação.
+    ///
+    /// A posição no master anterior é mantida, assim o novo master decide
+    /// entre uma sincronização parcial ou completa pelo id do seu log.
+    pub fn replicate_from(self: &Arc<Self>, master: SocketAddr) {
+        self.follow(master);
+        self.master_link.forget();
+        self.start_client_task();
+    }
+
+    /// Inicia o cliente de replicação com o master atual do nó, encerrando o anterior.
+    pub fn start_client_task(self: &Arc<Self>) {
+        let replica = self.clone();
+        let task = tokio::spawn(async move { start_client(replica).await });
+        let mut client_task = self.client_task.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(previous) = client_task.replace(task) {
+            previous.abort();
+        }
+    }
+
+    pub fn stop_client_task(&self) {
+        let mut client_task = self.client_task.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(task) = client_task.take() {
+            task.abort();
+        }
     }
 
+    /// Registra o slave pelo seu endereço, retornando `false` quando o
+    /// endereço ja pertence a outro slave conectado.
     pub async fn register_node(&self, node: Node) -> bool {
         let mut rn_guard = self.replica_nodes.write().await;
-        if rn_guard
-            .insert(node.master_ipaddr().clone(), node)
-            .is_some()
-        {
+        let key = *node.address();
+        if rn_guard.contains_key(&key) {
             return false;
         }
-        self.replicas_length.fetch_add(1, Ordering::AcqRel);
+        let replica_node = ReplicaNode {
+            node,
+            offset: AtomicU64::new(0),
+            acked_at: AtomicI64::new(clock::now_millis()),
+        };
+        rn_guard.insert(key, Arc::new(replica_node));
 
         true
     }
 
-    pub async fn unregister_node(&self, key: SocketAddr) -> bool {
-        let mut rn_guard = self.replica_nodes.write().await;
-        if rn_guard.remove(&key).is_some() {
-            self.replicas_length.fetch_sub(1, Ordering::AcqRel);
-            return true;
+    pub async fn replica_node(&self, key: SocketAddr) -> Option<Arc<ReplicaNode>> {
+        self.replica_nodes.read().await.get(&key).cloned()
+    }
+
+    /// Lista os slaves conectados com o atraso de cada um em relação ao log.
+    pub async fn replicas(&self) -> Vec<ReplicaInfo> {
+        let master_offset = self.log.offset();
+        let rn_guard = self.replica_nodes.read().await;
+        let mut replicas: Vec<ReplicaInfo> = rn_guard
+            .values()
+            .map(|replica_node| replica_node.info(master_offset))
+            .collect();
+        replicas.sort_by_key(|replica| replica.address);
+        replicas
+    }
+
+    /// Offset do nó na replicação, o do log no master ou o ultimo aplicado em um slave.
+    pub fn offset(&self) -> u64 {
+        match self.is_read_only() {
+            true => self.master_link.position().1,
+            false => self.log.offset(),
         }
+    }
+
+    /// Registra o `Ack` de um slave e acorda quem espera confirmações.
+    pub fn acknowledge(&self, registration: &ReplicaNode, offset: u64) {
+        registration.acknowledge(offset);
+        self.acks.notify_waiters();
+    }
 
-        false
+    /// Receptor dos pedidos de confirmações imediatas dos slaves, muda a cada pedido.
+    pub fn ack_requests(&self) -> watch::Receiver<u64> {
+        self.ack_requests.subscribe()
+    }
+
+    /// Espera ate `replicas` slaves confirmarem as alterações ate `offset`, ou o `timeout` passar.
+    ///
+    /// Retorna quantos slaves confirmaram, que pode ser menos que o pedido
+    /// quando o tempo acaba ou mais quando outros ja estavam em dia.
+    pub async fn wait_for_acks(&self, offset: u64, replicas: usize, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let acked = self.acks.notified();
+            tokio::pin!(acked);
+            acked.as_mut().enable();
+
+            let count = self.acknowledged(offset).await;
+            if count >= replicas {
+                return count;
+            }
+            self.ack_requests.send_modify(|generation| *generation += 1);
+            if tokio::time::timeout_at(deadline, acked).await.is_err() {
+                return self.acknowledged(offset).await;
+            }
+        }
+    }
+
+    /// Quantidade de slaves que ja confirmaram as alterações ate `offset`.
+    async fn acknowledged(&self, offset: u64) -> usize {
+        let rn_guard = self.replica_nodes.read().await;
+        rn_guard
+            .values()
+            .filter(|replica_node| replica_node.offset() >= offset)
+            .count()
+    }
+
+    pub async fn replication_stats(&self) -> ReplicationStats {
+        let offset = self.offset();
+        let replicas = self.replicas().await;
+        ReplicationStats {
+            role: self.node().mode.to_string(),
+            offset,
+            connected_slaves: replicas.len(),
+            replicas,
+        }
+    }
+
+    pub async fn unregister_node(&self, key: SocketAddr) -> bool {
+        self.replica_nodes.write().await.remove(&key).is_some()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::replication::NodeMode;
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        memory::CacheValue,
+        replication::{NodeMode, serve},
+        test_support::{listen, wait_for, wait_until},
+    };
 
     use super::{Node, Replica, SocketAddr};
 
@@ -77,11 +385,11 @@ mod tests {
         let node_slave = build_node("slave", "127.0.0.1", 8001);
         let result = replica_master.register_node(node_slave).await;
 
-        assert_eq!(result, true, "Should return true when inserting a new node");
+        assert!(result, "Should return true when inserting a new node");
         assert_eq!(
-            replica_master.replicas_length().await,
+            replica_master.replicas().await.len(),
             1,
-            "Replica length should be 1"
+            "Replica count should be 1"
         );
     }
 
@@ -91,22 +399,100 @@ mod tests {
         let replica_master = Replica::new(node_master);
 
         let node_slave = build_node("slave", "127.0.0.1", 8001);
-        let slave_addr = node_slave.master_ipaddr().clone();
+        let slave_addr = *node_slave.address();
         replica_master.register_node(node_slave).await;
 
         assert_eq!(
-            replica_master.replicas_length().await,
+            replica_master.replicas().await.len(),
             1,
-            "Replica length should be 1"
+            "Replica count should be 1"
         );
 
         let result = replica_master.unregister_node(slave_addr).await;
 
-        assert_eq!(result, true, "Should return true when removing a node");
+        assert!(result, "Should return true when removing a node");
         assert_eq!(
-            replica_master.replicas_length().await,
+            replica_master.replicas().await.len(),
             0,
-            "Replica length should be 0"
+            "Replica count should be 0"
         );
     }
+
+    #[tokio::test]
+    async fn test_register_duplicated_address() {
+        let replica_master = Replica::new(build_node("master", "127.0.0.1", 8000));
+
+        let node_slave = build_node("slave", "127.0.0.1", 8001).with_id("first");
+        assert!(replica_master.register_node(node_slave).await);
+
+        let node_slave = build_node("slave", "127.0.0.1", 8001).with_id("second");
+        assert!(
+            !replica_master.register_node(node_slave).await,
+            "Address already belongs to another slave"
+        );
+
+        let replicas = replica_master.replicas().await;
+        assert_eq!(replicas.len(), 1);
+        assert_eq!(replicas[0].id, "first");
+        assert_eq!(replica_master.replicas().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replica_lag() {
+        let replica_master = Replica::new(build_node("master", "127.0.0.1", 8000));
+        let node_slave = build_node("slave", "127.0.0.1", 8001);
+        let slave_addr = *node_slave.address();
+        replica_master.register_node(node_slave).await;
+
+        for key in ["a", "b", "c"] {
+            replica_master
+                .store
+                .set(key.into(), CacheValue::new("1"))
+                .unwrap();
+        }
+        let registration = replica_master.replica_node(slave_addr).await.unwrap();
+        registration.acknowledge(1);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let stats = replica_master.replication_stats().await;
+        assert_eq!((stats.offset, stats.connected_slaves), (3, 1));
+        let info = &stats.replicas[0];
+        assert_eq!((info.offset, info.lag), (1, 2));
+        assert!(info.lag_ms >= 20, "Lag should grow since the last ack");
+
+        registration.acknowledge(3);
+        let info = &replica_master.replicas().await[0];
+        assert_eq!((info.lag, info.lag_ms), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_replicate_from_and_promote() {
+        let (listener, master_ipaddr) = listen().await;
+        let master = Arc::new(Replica::new(build_node(
+            "master",
+            "127.0.0.1",
+            master_ipaddr.port().into(),
+        )));
+        tokio::spawn(serve(master.clone(), listener));
+        master.store.set("a".into(), CacheValue::new("1")).unwrap();
+
+        // Um master em execução passa a replicar outro nó
+        let node = build_node("master", "127.0.0.1", 8001);
+        let replica = Arc::new(Replica::new(node));
+        replica.replicate_from(master_ipaddr);
+        assert!(replica.is_read_only(), "Node must become a slave");
+        assert_eq!(replica.node().master_ipaddr(), &master_ipaddr);
+
+        wait_until(|| replica.store.get("a").is_some()).await;
+        assert_eq!(replica.store.get("a"), Some(CacheValue::new("1")));
+
+        replica.promote().unwrap();
+        assert!(!replica.is_read_only(), "Node must become a master");
+        // A tarefa do cliente para e o master remove o registro
+        wait_for(|| async { master.replicas().await.is_empty() }).await;
+
+        master.store.set("b".into(), CacheValue::new("2")).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(replica.store.get("b"), None);
+    }
 }
@@ -0,0 +1,181 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::memory::{Mutation, MutationObserver};
+
+/// Quantidade de alterações mantidas no backlog para a sincronização parcial.
+pub const BACKLOG_CAPACITY: usize = 16384;
+
+/// Fração do backlog que um slave pode deixar sem ler antes de perder o fluxo.
+///
+/// O fluxo menor que o backlog garante que um slave que perdeu o fluxo,
+/// inclusive enquanto recebia o snapshot, ainda encontra no backlog o que
+/// falta e volta com uma sincronização parcial em vez de outra completa.
+const STREAM_BACKLOG_DIVISOR: usize = 4;
+
+/// Alteração junto ao seu offset no log.
+pub type LogEntry = (u64, Mutation);
+
+/// Log de replicação com as alterações do `Store` do master.
+///
+/// O log possui um id unico gerado na criação e cada alteração recebe um
+/// offset, que cresce de um em um. As ultimas alterações ficam em um backlog
+/// limitado, assim um slave que reconecta com o mesmo id e um offset ainda
+/// coberto pelo backlog recebe somente o que perdeu.
+pub struct ReplicationLog {
+    id: String,
+    capacity: usize,
+    state: Mutex<LogState>,
+    sender: broadcast::Sender<LogEntry>,
+}
+
+struct LogState {
+    /// Offset da ultima alteração registrada.
+    offset: u64,
+    /// Ultimas alterações registradas, da mais antiga para a mais nova.
+    backlog: VecDeque<LogEntry>,
+}
+
+impl ReplicationLog {
+    pub fn new() -> Self {
+        Self::with_capacity(BACKLOG_CAPACITY)
+    }
+
+    /// Cria o log mantendo ate `capacity` alterações no backlog.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel((capacity / STREAM_BACKLOG_DIVISOR).max(1));
+        Self {
+            id: Uuid::new_v4().to_string(),
+            capacity,
+            state: Mutex::new(LogState {
+                offset: 0,
+                backlog: VecDeque::with_capacity(capacity),
+            }),
+            sender,
+        }
+    }
+
+    /// Id da replicação, muda sempre que o log é recriado.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Offset da ultima alteração registrada.
+    pub fn offset(&self) -> u64 {
+        self.state().offset
+    }
+
+    /// Passa a receber as alterações registradas a partir desse momento.
+    ///
+    /// Retorna junto o offset atual, o receptor entrega exatamente as
+    /// alterações com offset maior que ele.
+    pub fn subscribe(&self) -> (u64, broadcast::Receiver<LogEntry>) {
+        let state = self.state();
+        (state.offset, self.sender.subscribe())
+    }
+
+    /// Continua a replicação de um slave que ja aplicou ate `offset` do log `id`.
+    ///
+    /// Devolve as alterações do backlog posteriores ao offset e o receptor das
+    /// seguintes, ou `None` quando o id é outro ou o backlog não cobre mais o
+    /// intervalo, casos em que o slave precisa de uma sincronização completa.
+    pub fn resume(
+        &self,
+        id: &str,
+        offset: u64,
+    ) -> Option<(Vec<LogEntry>, broadcast::Receiver<LogEntry>)> {
+        let state = self.state();
+        if id != self.id || offset > state.offset {
+            return None;
+        }
+
+        let oldest = state
+            .backlog
+            .front()
+            .map_or(state.offset + 1, |(offset, _)| *offset);
+        if offset + 1 < oldest {
+            return None;
+        }
+
+        let missing = state
+            .backlog
+            .iter()
+            .filter(|(entry_offset, _)| *entry_offset > offset)
+            .cloned()
+            .collect();
+        Some((missing, self.sender.subscribe()))
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, LogState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl MutationObserver for ReplicationLog {
+    fn on_mutation(&self, mutation: Mutation) {
+        // O lock garante que a ordem do backlog e do envio siga a ordem dos offsets.
+        let mut state = self.state();
+        state.offset += 1;
+        let entry = (state.offset, mutation);
+
+        if self.capacity > 0 {
+            if state.backlog.len() == self.capacity {
+                state.backlog.pop_front();
+            }
+            state.backlog.push_back(entry.clone());
+        }
+        // Sem slaves conectados não existe receptor e a alteração é descartada.
+        let _ = self.sender.send(entry);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory::{Mutation, MutationObserver};
+
+    use super::ReplicationLog;
+
+    #[tokio::test]
+    async fn test_subscribe_receives_later_offsets() {
+        let log = ReplicationLog::new();
+        log.on_mutation(Mutation::Clear);
+
+        let (offset, mut receiver) = log.subscribe();
+        assert_eq!(offset, 1);
+
+        log.on_mutation(Mutation::Delete("key".into()));
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            (2, Mutation::Delete("key".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_backlog() {
+        let log = ReplicationLog::with_capacity(2);
+        let id = log.id().to_string();
+        assert!(log.resume(&id, 0).is_some(), "Empty log covers offset 0");
+
+        for key in ["a", "b", "c"] {
+            log.on_mutation(Mutation::Delete(key.into()));
+        }
+
+        let (missing, mut receiver) = log.resume(&id, 1).expect("Offset 1 is covered");
+        assert_eq!(
+            missing,
+            vec![
+                (2, Mutation::Delete("b".into())),
+                (3, Mutation::Delete("c".into()))
+            ]
+        );
+        log.on_mutation(Mutation::Clear);
+        assert_eq!(receiver.recv().await.unwrap(), (4, Mutation::Clear));
+
+        assert!(log.resume(&id, 4).unwrap().0.is_empty());
+        assert!(log.resume(&id, 1).is_none(), "Offset 2 left the backlog");
+        assert!(log.resume(&id, 5).is_none(), "Offset ahead of the log");
+        assert!(log.resume("other", 4).is_none(), "Different replication id");
+    }
+}
@@ -1,48 +1,333 @@
-use std::{env, net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 
-use futures_util::{SinkExt, StreamExt};
-use tokio::{net::TcpListener, sync::mpsc};
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use futures_util::{Sink, Stream, StreamExt};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+    task::JoinSet,
+    time::{self, Instant},
+};
+use tokio_tungstenite::{
+    accept_async,
+    tungstenite::{self, Message},
+};
 
-use super::{Replica, ReplicationError};
+use crate::memory::clock;
 
+use super::{
+    HEARTBEAT_INTERVAL, LogEntry, MAX_MISSED_HEARTBEATS, Node, NodeMode, Replica, ReplicaNode,
+    ReplicationError, ReplicationMessage, SNAPSHOT_BATCH, answer_gossip, candidate, import_slots,
+    read_message, send, step_down,
+};
+
+/// Inicia o servidor de replicação, enviando as alterações do `Store` a cada slave conectado.
+///
+/// As conexões pertencem à tarefa do servidor, encerra-la desconecta todos os slaves.
 pub async fn start_server(
     replica: Arc<Replica>,
     ipaddr: SocketAddr,
 ) -> Result<(), ReplicationError> {
     let listener = TcpListener::bind(ipaddr).await?;
     println!("Serviço de replicação iniciado: {:?}", ipaddr);
+    serve(replica, listener).await
+}
 
-    while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(async move {
-            if let Ok(ws_stream) = accept_async(stream).await {
-                let (peer_tx, mut peer_rx) = mpsc::channel::<String>(10);
-                let (mut writer, mut reader) = ws_stream.split();
-
-                tokio::spawn(async move {
-                    while let Some(message) = peer_rx.recv().await {
-                        if let Ok(response) = serde_json::to_string(&message) {
-                            if writer.send(Message::text(response)).await.is_err() {
-                                break;
-                            }
-                        }
+/// Atende as conexões de replicação de um listener ja aberto.
+pub async fn serve(replica: Arc<Replica>, listener: TcpListener) -> Result<(), ReplicationError> {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, peer)) = accepted else {
+                    break;
+                };
+                let replica = replica.clone();
+                connections.spawn(async move {
+                    match serve_slave(stream, &replica).await {
+                        Ok(()) => println!("Conexão de replicação {} encerrada", peer),
+                        Err(e) => eprintln!("Replicação com o slave {} encerrada: {}", peer, e),
                     }
                 });
+            }
+            Some(_) = connections.join_next() => {}
+        }
+    }
 
-                while let Some(Ok(message)) = reader.next().await {
-                    if let Ok(text) = message.to_text() {
-                        println!("{:?}", text);
-                        let _ = peer_tx.send(text.to_string()).await;
-                    } else {
-                        eprintln!("Failed to read message: {:?}", message);
-                        continue;
-                    }
-                }
+    Ok(())
+}
+
+/// Como terminou o envio das alterações para um slave.
+enum StreamEnd {
+    /// O slave encerrou a conexão.
+    Closed,
+    /// O slave ficou atrasado e perdeu alterações a partir do offset informado.
+    Lagged(u64),
+}
+
+/// Registra o slave que se apresentou com `Register` e o mantem sincronizado
+/// ate a conexão terminar, quando o registro é removido.
+///
+/// Conexões que começam com `Election` recebem a situação do nó e terminam,
+/// as que começam com `Demote` avisam que outro nó venceu a eleição, as que começam com `Import` trazem slots migrados por outro master e as
+/// que começam com `Gossip` trocam a visão do cluster.
+async fn serve_slave(stream: TcpStream, replica: &Arc<Replica>) -> Result<(), ReplicationError> {
+    let ws_stream = accept_async(stream).await?;
+    let (mut writer, mut reader) = ws_stream.split();
 
-                println!("Disconnected!!!")
+    let node = match read_message(&mut reader).await? {
+        Some(ReplicationMessage::Register {
+            node_id,
+            address,
+            version,
+        }) => Node::new(NodeMode::Slave, *replica.node().address())
+            .with_id(node_id)
+            .with_address(address)
+            .with_version(version),
+        Some(ReplicationMessage::Election) => {
+            let candidate = ReplicationMessage::Candidate(candidate(replica));
+            return send(&mut writer, &candidate).await;
+        }
+        Some(ReplicationMessage::Demote(master)) => {
+            step_down(replica, master).await;
+            return Ok(());
+        }
+        Some(ReplicationMessage::Import(slots)) => {
+            return import_slots(&mut writer, &mut reader, replica, slots).await;
+        }
+        Some(ReplicationMessage::Gossip(digest)) => {
+            return answer_gossip(&mut writer, replica, digest).await;
+        }
+        Some(other) => {
+            return Err(ReplicationError::ParseError(format!(
+                "expected Register, got {:?}",
+                other
+            )));
+        }
+        None => return Ok(()),
+    };
+
+    let address = *node.address();
+    let node_id = node.id.clone();
+    if !replica.register_node(node).await {
+        let reason = format!("address {} already registered", address);
+        send(&mut writer, &ReplicationMessage::Rejected(reason.clone())).await?;
+        return Err(ReplicationError::Register(reason));
+    }
+    println!("Slave {} registrado no endereço {}", node_id, address);
+
+    let result = match replica.replica_node(address).await {
+        Some(registration) => replicate(&mut writer, &mut reader, replica, &registration).await,
+        None => Err(ReplicationError::Register(format!(
+            "slave {} removed during registration",
+            address
+        ))),
+    };
+
+    if !replica.unregister_node(address).await {
+        return Err(ReplicationError::Unregister(format!(
+            "slave {} was not registered",
+            address
+        )));
+    }
+    result
+}
+
+/// Sincroniza o slave a partir da posição informada no `Sync` e depois envia as alterações seguintes.
+///
+/// Quando o slave fica atrasado e perde alterações do fluxo, a sincronização
+/// é refeita na mesma conexão a partir do ultimo offset enviado.
+async fn replicate<W, R>(
+    writer: &mut W,
+    reader: &mut R,
+    replica: &Replica,
+    registration: &ReplicaNode,
+) -> Result<(), ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let (mut replication_id, mut offset) = match read_message(reader).await? {
+        Some(ReplicationMessage::Sync {
+            replication_id,
+            offset,
+        }) => (replication_id, offset),
+        Some(other) => {
+            return Err(ReplicationError::ParseError(format!(
+                "expected Sync, got {:?}",
+                other
+            )));
+        }
+        None => return Ok(()),
+    };
+
+    loop {
+        let (mut mutations, synced_offset) =
+            resync(writer, replica, replication_id.as_deref(), offset).await?;
+        let streamed = stream_mutations(
+            writer,
+            reader,
+            replica,
+            &mut mutations,
+            synced_offset,
+            registration,
+        );
+        match streamed.await? {
+            StreamEnd::Closed => return Ok(()),
+            StreamEnd::Lagged(last_sent) => {
+                eprintln!(
+                    "Slave atrasado no offset {}, refazendo a sincronização",
+                    last_sent
+                );
+                replication_id = Some(replica.log.id().to_string());
+                offset = last_sent;
             }
-        });
+        }
     }
+}
 
-    Ok(())
+/// Coloca o slave no offset atual do log, enviando somente o que falta quando possivel.
+///
+/// Devolve o receptor das alterações seguintes junto ao offset em que o slave ficou.
+async fn resync<W>(
+    writer: &mut W,
+    replica: &Replica,
+    replication_id: Option<&str>,
+    offset: u64,
+) -> Result<(broadcast::Receiver<LogEntry>, u64), ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let log = &replica.log;
+    if let Some((missing, mutations)) = replication_id.and_then(|id| log.resume(id, offset)) {
+        let replication_id = log.id().to_string();
+        send(
+            writer,
+            &ReplicationMessage::PartialSync {
+                replication_id,
+                offset,
+            },
+        )
+        .await?;
+
+        let mut synced_offset = offset;
+        for (offset, mutation) in missing {
+            send(writer, &ReplicationMessage::Mutation { offset, mutation }).await?;
+            synced_offset = offset;
+        }
+        return Ok((mutations, synced_offset));
+    }
+
+    full_sync(writer, replica).await
+}
+
+/// Envia o snapshot do `Store` e devolve o receptor das alterações posteriores a ele.
+///
+/// O receptor é criado antes da copia, assim toda alteração concorrente ao
+/// snapshot é reenviada depois dele. Como cada alteração grava o estado
+/// completo da `key`, reaplica-la mantem o slave igual ao master. O `Store`
+/// é copiado e enviado um shard por vez, em lotes de `SNAPSHOT_BATCH`.
+async fn full_sync<W>(
+    writer: &mut W,
+    replica: &Replica,
+) -> Result<(broadcast::Receiver<LogEntry>, u64), ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let (offset, mutations) = replica.log.subscribe();
+    let replication_id = replica.log.id().to_string();
+    send(
+        writer,
+        &ReplicationMessage::FullSync {
+            replication_id,
+            offset,
+        },
+    )
+    .await?;
+
+    for shard in 0..replica.store.shard_count() {
+        let mut snapshot = replica.store.snapshot_shard(shard).into_iter().peekable();
+        while snapshot.peek().is_some() {
+            let batch = snapshot.by_ref().take(SNAPSHOT_BATCH).collect();
+            send(writer, &ReplicationMessage::Snapshot(batch)).await?;
+        }
+    }
+    send(writer, &ReplicationMessage::SnapshotEnd).await?;
+
+    Ok((mutations, offset))
+}
+
+/// `Ping` com o offset atual do log e o momento do envio.
+fn ping(replica: &Replica) -> ReplicationMessage {
+    ReplicationMessage::Ping {
+        offset: replica.log.offset(),
+        timestamp: clock::to_unix_millis(clock::now_millis()),
+    }
+}
+
+/// Envia as alterações do fluxo, ignorando as que o slave ja recebeu na sincronização.
+///
+/// Entre as alterações envia um `Ping` e a `Topology` a cada `HEARTBEAT_INTERVAL`
+/// e registra os `Ack` do slave, encerrando a conexão quando ele deixa de responder.
+/// Um `Ping` extra é enviado quando alguem espera confirmações com `wait_for_acks`.
+async fn stream_mutations<W, R>(
+    writer: &mut W,
+    reader: &mut R,
+    replica: &Replica,
+    mutations: &mut broadcast::Receiver<LogEntry>,
+    mut last_sent: u64,
+    registration: &ReplicaNode,
+) -> Result<StreamEnd, ReplicationError>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let mut heartbeat = time::interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    let mut missed_heartbeats = 0;
+    let mut ack_requests = replica.ack_requests();
+    loop {
+        // A ordem das opções importa: o `Ping` pedido por `wait_for_acks` so
+        // sai depois das alterações pendentes, assim o `Ack` ja as cobre.
+        tokio::select! {
+            biased;
+
+            message = reader.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if let ReplicationMessage::Ack { offset } = serde_json::from_str(&text)? {
+                        replica.acknowledge(registration, offset);
+                        missed_heartbeats = 0;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(StreamEnd::Closed),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            },
+            _ = heartbeat.tick() => {
+                if missed_heartbeats >= MAX_MISSED_HEARTBEATS {
+                    return Err(ReplicationError::Timeout(format!(
+                        "no Ack for {} heartbeats",
+                        missed_heartbeats
+                    )));
+                }
+                send(writer, &ping(replica)).await?;
+                send(writer, &ReplicationMessage::Topology(replica.replicas().await)).await?;
+                missed_heartbeats += 1;
+            }
+            mutation = mutations.recv() => {
+                let (offset, mutation) = match mutation {
+                    Ok(mutation) => mutation,
+                    Err(RecvError::Lagged(_)) => return Ok(StreamEnd::Lagged(last_sent)),
+                    Err(RecvError::Closed) => return Ok(StreamEnd::Closed),
+                };
+                if offset <= last_sent {
+                    continue;
+                }
+                send(writer, &ReplicationMessage::Mutation { offset, mutation }).await?;
+                last_sent = offset;
+            }
+            Ok(()) = ack_requests.changed() => {
+                // Pedido fora do intervalo, não conta como heartbeat perdido
+                send(writer, &ping(replica)).await?;
+            }
+        }
+    }
 }
@@ -0,0 +1,545 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::{
+    memory::{CacheValue, MemoryError, WriteCondition, WriteOutcome, clock},
+    replication::{ClusterNode, Route},
+};
+
+use super::{Frame, Protocol, Replica};
+
+type CommandResult = Result<Frame, Frame>;
+
+/// Comandos que alteram o `Store`, rejeitados quando o nó é um slave.
+const WRITE_COMMANDS: [&str; 6] = ["set", "del", "expire", "incr", "mset", "flushall"];
+
+/// Estado de uma conexão RESP entre os comandos.
+#[derive(Debug)]
+pub struct Session {
+    pub protocol: Protocol,
+    /// Marcado pelo `ASKING`, vale somente para o comando seguinte.
+    asking: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            protocol: Protocol::Resp2,
+            asking: false,
+        }
+    }
+}
+
+/// Executa um comando RESP sobre o `Store` da replica.
+///
+/// O primeiro argumento é o nome do comando, sem diferenciar maiusculas, e os
+/// demais seus argumentos. `HELLO` pode alterar o protocolo da conexão.
+///
+/// No modo cluster as `keys` de outro master são respondidas com `-MOVED` e
+/// as de um slot em migração que ja sairam daqui com `-ASK`, ambos com o
+/// endereço RESP do nó, como no Redis Cluster.
+pub async fn execute(args: Vec<Vec<u8>>, replica: &Arc<Replica>, session: &mut Session) -> Frame {
+    let Some((name, args)) = args.split_first() else {
+        return Frame::error("empty command");
+    };
+    let name = String::from_utf8_lossy(name).to_lowercase();
+    let asking = std::mem::take(&mut session.asking);
+    if let Some(error) = redirect(replica.route(&command_keys(&name, args), asking)) {
+        return error;
+    }
+    if WRITE_COMMANDS.contains(&name.as_str()) && replica.is_read_only() {
+        return Frame::Error(format!(
+            "READONLY You can't write against a read only replica, master is {}",
+            replica.node().master_ipaddr()
+        ));
+    }
+
+    let result = match name.as_str() {
+        "ping" => ping(args),
+        "hello" => hello(args, replica, &mut session.protocol),
+        "asking" => asking_next(args, session),
+        "get" => get(args, replica),
+        "set" => set(args, replica).await,
+        "del" => del(args, replica),
+        "exists" => exists(args, replica),
+        "expire" => expire(args, replica).await,
+        "ttl" => ttl(args, replica),
+        "incr" => incr(args, replica),
+        "mget" => mget(args, replica),
+        "mset" => mset(args, replica),
+        "info" => info(replica).await,
+        "flushall" => flushall(args, replica),
+        "replicaof" | "slaveof" => replicaof(args, replica),
+        "wait" => wait(args, replica).await,
+        _ => Err(Frame::error(format!("unknown command '{}'", name))),
+    };
+    result.unwrap_or_else(|error| error)
+}
+
+/// `keys` lidas pelo comando, usadas para encontrar o slot no modo cluster.
+///
+/// Argumentos que não são UTF-8 ficam de fora, o comando os recusa depois.
+fn command_keys<'a>(name: &str, args: &'a [Vec<u8>]) -> Vec<&'a str> {
+    let (keys, step) = match name {
+        "get" | "set" | "expire" | "ttl" | "incr" => (args.get(..1).unwrap_or_default(), 1),
+        "del" | "exists" | "mget" => (args, 1),
+        "mset" => (args, 2),
+        _ => (&[][..], 1),
+    };
+    keys.iter()
+        .step_by(step)
+        .filter_map(|key| std::str::from_utf8(key).ok())
+        .collect()
+}
+
+/// Erro de redirecionamento para as `keys` que não são atendidas por este nó.
+fn redirect(route: Route) -> Option<Frame> {
+    let moved = |kind: &str, slot: u16, node: ClusterNode| match node.services.resp {
+        Some(address) => Frame::Error(format!("{} {} {}", kind, slot, address)),
+        None => Frame::Error(format!(
+            "CLUSTERDOWN Slot {} is served by node {} without the RESP service",
+            slot, node.id
+        )),
+    };
+    match route {
+        Route::Local => None,
+        Route::Moved(slot, node) => Some(moved("MOVED", slot, node)),
+        Route::Ask(slot, node) => Some(moved("ASK", slot, node)),
+        Route::Unassigned(slot) => Some(Frame::Error(format!(
+            "CLUSTERDOWN Hash slot {} not served",
+            slot
+        ))),
+        Route::CrossSlot => Some(Frame::Error(
+            "CROSSSLOT Keys in request don't hash to the same slot".into(),
+        )),
+    }
+}
+
+fn ping(args: &[Vec<u8>]) -> CommandResult {
+    match args {
+        [] => Ok(Frame::Simple("PONG".into())),
+        [message] => Ok(Frame::bulk(message)),
+        _ => Err(wrong_arity("ping")),
+    }
+}
+
+/// Responde com as informações do servidor e troca a versão do protocolo, quando informada.
+fn hello(args: &[Vec<u8>], replica: &Replica, protocol: &mut Protocol) -> CommandResult {
+    if let Some(version) = args.first() {
+        *protocol = match version.as_slice() {
+            b"2" => Protocol::Resp2,
+            b"3" => Protocol::Resp3,
+            _ => {
+                return Err(Frame::Error("NOPROTO unsupported protocol version".into()));
+            }
+        };
+    }
+
+    let mode = match replica.cluster {
+        Some(_) => "cluster",
+        None => "standalone",
+    };
+    let proto = match protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
+    };
+    Ok(Frame::Map(vec![
+        (Frame::bulk("server"), Frame::bulk("crusty-cache")),
+        (
+            Frame::bulk("version"),
+            Frame::bulk(env!("CARGO_PKG_VERSION")),
+        ),
+        (Frame::bulk("proto"), Frame::Integer(proto)),
+        (Frame::bulk("mode"), Frame::bulk(mode)),
+        (
+            Frame::bulk("role"),
+            Frame::bulk(replica.node().mode.to_string()),
+        ),
+        (Frame::bulk("modules"), Frame::Array(Vec::new())),
+    ]))
+}
+
+/// `ASKING` libera o proximo comando em um slot sendo importado, apos um `-ASK`.
+fn asking_next(args: &[Vec<u8>], session: &mut Session) -> CommandResult {
+    if !args.is_empty() {
+        return Err(wrong_arity("asking"));
+    }
+    session.asking = true;
+    Ok(Frame::ok())
+}
+
+fn get(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    let [key] = args else {
+        return Err(wrong_arity("get"));
+    };
+    Ok(match replica.store.get(&parse_key(key)?) {
+        Some(value) => Frame::bulk(value.as_bytes()),
+        None => Frame::Null,
+    })
+}
+
+/// `SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-seconds | PXAT unix-milliseconds]`
+async fn set(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    let [key, value, options @ ..] = args else {
+        return Err(wrong_arity("set"));
+    };
+    let key = parse_key(key)?;
+
+    let mut expires_at = None;
+    let mut condition = None;
+    let mut get = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" | b"XX" if condition.is_some() => return Err(syntax_error()),
+            b"NX" => condition = Some(WriteCondition::Nx),
+            b"XX" => condition = Some(WriteCondition::Xx),
+            b"GET" => get = true,
+            unit @ (b"EX" | b"PX" | b"EXAT" | b"PXAT") => {
+                let amount = options.next().ok_or_else(syntax_error)?;
+                if expires_at.is_some() {
+                    return Err(syntax_error());
+                }
+                expires_at = Some(deadline(unit, parse_integer(amount)?, "set")?);
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let outcome = replica
+        .store
+        .write(
+            key.clone(),
+            CacheValue::new(value),
+            expires_at,
+            condition.as_ref(),
+        )
+        .await?;
+    Ok(match (outcome, get) {
+        (WriteOutcome::Written { .. }, false) => Frame::ok(),
+        (WriteOutcome::Written { previous, .. }, true) => {
+            previous.map_or(Frame::Null, |value| Frame::bulk(value.as_bytes()))
+        }
+        (WriteOutcome::Rejected, false) => Frame::Null,
+        // A escrita não mudou a `key`, o valor atual é o mesmo que a rejeitou
+        (WriteOutcome::Rejected, true) => replica
+            .store
+            .get(&key)
+            .map_or(Frame::Null, |value| Frame::bulk(value.as_bytes())),
+    })
+}
+
+fn del(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    if args.is_empty() {
+        return Err(wrong_arity("del"));
+    }
+    let keys = parse_keys(args)?;
+    Ok(Frame::Integer(replica.store.delete_many(&keys) as i64))
+}
+
+fn exists(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    if args.is_empty() {
+        return Err(wrong_arity("exists"));
+    }
+    let keys = parse_keys(args)?;
+    let count = keys.iter().filter(|key| replica.store.exists(key)).count();
+    Ok(Frame::Integer(count as i64))
+}
+
+/// `EXPIRE key seconds`, um tempo não positivo remove a `key` imediatamente.
+async fn expire(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    let [key, seconds] = args else {
+        return Err(wrong_arity("expire"));
+    };
+    let key = parse_key(key)?;
+    let seconds = parse_integer(seconds)?;
+
+    let updated = if seconds <= 0 {
+        replica.store.delete(&key)
+    } else {
+        let expires_at = deadline(b"EX", seconds, "expire")?;
+        replica.store.expire(&key, Some(expires_at)).await
+    };
+    Ok(Frame::Integer(updated as i64))
+}
+
+/// Tempo de vida restante em segundos, `-2` para `keys` inexistentes e `-1` para as sem expiração.
+fn ttl(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    let [key] = args else {
+        return Err(wrong_arity("ttl"));
+    };
+    Ok(Frame::Integer(match replica.store.ttl(&parse_key(key)?) {
+        None => -2,
+        Some(None) => -1,
+        Some(Some(millis)) => (millis + 500) / 1000,
+    }))
+}
+
+fn incr(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    let [key] = args else {
+        return Err(wrong_arity("incr"));
+    };
+    Ok(Frame::Integer(replica.store.incr_by(&parse_key(key)?, 1)?))
+}
+
+fn mget(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    if args.is_empty() {
+        return Err(wrong_arity("mget"));
+    }
+    let values = replica.store.get_many(&parse_keys(args)?);
+    Ok(Frame::Array(
+        values
+            .into_iter()
+            .map(|value| value.map_or(Frame::Null, |value| Frame::bulk(value.as_bytes())))
+            .collect(),
+    ))
+}
+
+fn mset(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(wrong_arity("mset"));
+    }
+    let items = args
+        .chunks(2)
+        .map(|pair| Ok((parse_key(&pair[0])?, CacheValue::new(&pair[1]))))
+        .collect::<Result<Vec<_>, Frame>>()?;
+    replica.store.set_many(items)?;
+    Ok(Frame::ok())
+}
+
+/// Estatisticas no formato de texto do `INFO` do Redis, ignorando o filtro de seção.
+async fn info(replica: &Replica) -> CommandResult {
+    let stats = replica.store.stats();
+    let replication = replica.replication_stats().await;
+    let mut info = vec![
+        "# Server".to_string(),
+        format!("crusty_cache_version:{}", env!("CARGO_PKG_VERSION")),
+        "# Replication".to_string(),
+        format!("role:{}", replication.role),
+        format!("connected_slaves:{}", replication.connected_slaves),
+    ];
+    for (index, slave) in replication.replicas.iter().enumerate() {
+        info.push(format!(
+            "slave{}:id={},ip={},port={},offset={},lag={},lag_ms={}",
+            index,
+            slave.id,
+            slave.address.ip(),
+            slave.address.port(),
+            slave.offset,
+            slave.lag,
+            slave.lag_ms
+        ));
+    }
+    info.extend([
+        format!("master_repl_offset:{}", replication.offset),
+        "# Memory".to_string(),
+        format!("used_memory:{}", stats.used_memory),
+        format!("maxmemory:{}", stats.max_memory),
+        format!("maxmemory_policy:{}", stats.eviction_policy),
+        "# Stats".to_string(),
+        format!("keyspace_hits:{}", stats.hits),
+        format!("keyspace_misses:{}", stats.misses),
+        "# Keyspace".to_string(),
+        format!("db0:keys={},expires={}", stats.keys, stats.volatile_keys),
+    ]);
+    Ok(Frame::bulk(info.join("\r\n") + "\r\n"))
+}
+
+fn flushall(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    match args {
+        [] => {}
+        [mode] if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") => {}
+        _ => return Err(syntax_error()),
+    }
+    replica.store.clear();
+    Ok(Frame::ok())
+}
+
+/// `REPLICAOF host port` passa a replicar o master informado e `REPLICAOF NO ONE` promove o nó.
+///
+/// A porta é a do servidor de replicação do master.
+fn replicaof(args: &[Vec<u8>], replica: &Arc<Replica>) -> CommandResult {
+    let [host, port] = args else {
+        return Err(wrong_arity("replicaof"));
+    };
+    if host.eq_ignore_ascii_case(b"NO") && port.eq_ignore_ascii_case(b"ONE") {
+        replica.promote().map_err(|e| Frame::error(e.to_string()))?;
+        return Ok(Frame::ok());
+    }
+
+    let master: SocketAddr = format!(
+        "{}:{}",
+        String::from_utf8_lossy(host),
+        String::from_utf8_lossy(port)
+    )
+    .parse()
+    .map_err(|_| Frame::error("invalid master address"))?;
+    if master == *replica.node().address() {
+        return Err(Frame::error("a node can't replicate itself"));
+    }
+    replica.replicate_from(master);
+    Ok(Frame::ok())
+}
+
+/// `WAIT numreplicas timeout` espera os slaves confirmarem as escritas ja
+/// feitas, respondendo quantos confirmaram.
+///
+/// Diferente do Redis, um `timeout` de `0` não bloqueia e so conta as confirmações.
+async fn wait(args: &[Vec<u8>], replica: &Replica) -> CommandResult {
+    let [replicas, timeout] = args else {
+        return Err(wrong_arity("wait"));
+    };
+    let (replicas, timeout) = (parse_integer(replicas)?, parse_integer(timeout)?);
+    if replicas < 0 || timeout < 0 {
+        return Err(Frame::error("numreplicas and timeout must be positive"));
+    }
+
+    let offset = replica.log.offset();
+    let timeout = Duration::from_millis(timeout as u64);
+    let acknowledged = replica
+        .wait_for_acks(offset, replicas as usize, timeout)
+        .await;
+    Ok(Frame::Integer(acknowledged as i64))
+}
+
+/// Converte o tempo de vida informado em um timestamp no relógio do cache.
+fn deadline(unit: &[u8], amount: i64, command: &str) -> Result<i64, Frame> {
+    if amount <= 0 {
+        return Err(Frame::error(format!(
+            "invalid expire time in '{}' command",
+            command
+        )));
+    }
+    Ok(match unit {
+        b"EX" => clock::deadline_from_ttl(Duration::from_secs(amount as u64)),
+        b"PX" => clock::deadline_from_ttl(Duration::from_millis(amount as u64)),
+        b"EXAT" => clock::deadline_from_unix_millis(amount.saturating_mul(1000)),
+        _ => clock::deadline_from_unix_millis(amount),
+    })
+}
+
+fn parse_key(key: &[u8]) -> Result<String, Frame> {
+    String::from_utf8(key.to_vec()).map_err(|_| Frame::error("keys must be valid UTF-8"))
+}
+
+fn parse_keys(keys: &[Vec<u8>]) -> Result<Vec<String>, Frame> {
+    keys.iter().map(|key| parse_key(key)).collect()
+}
+
+fn parse_integer(value: &[u8]) -> Result<i64, Frame> {
+    CacheValue::new(value)
+        .as_integer()
+        .ok_or_else(|| Frame::error("value is not an integer or out of range"))
+}
+
+fn wrong_arity(command: &str) -> Frame {
+    Frame::error(format!(
+        "wrong number of arguments for '{}' command",
+        command
+    ))
+}
+
+fn syntax_error() -> Frame {
+    Frame::error("syntax error")
+}
+
+impl From<MemoryError> for Frame {
+    fn from(error: MemoryError) -> Self {
+        match error {
+            MemoryError::NotNumeric(_) => Frame::error("value is not an integer or out of range"),
+            MemoryError::OutOfMemory(_) => {
+                Frame::Error("OOM command not allowed when used memory > 'maxmemory'".into())
+            }
+            e => Frame::error(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::replication::{
+        Cluster, ClusterNode, Node, NodeMode, Replica, Services, SlotRange, key_slot,
+    };
+
+    use super::{Frame, Session, execute};
+
+    fn command(line: &str) -> Vec<Vec<u8>> {
+        line.split(' ').map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_cluster_redirects() {
+        let node = Node::new(NodeMode::Master, "127.0.0.1:5555".parse().unwrap()).with_services(
+            Services {
+                resp: Some("127.0.0.1:6379".parse().unwrap()),
+                ..Default::default()
+            },
+        );
+        let local = ClusterNode::from(&node);
+        let replica = Arc::new(Replica::new(node).with_cluster(Cluster::new()));
+        let cluster = replica.cluster.as_ref().unwrap();
+        let other = ClusterNode {
+            id: "other".into(),
+            address: "127.0.0.1:5556".parse().unwrap(),
+            services: Services {
+                resp: Some("127.0.0.1:6380".parse().unwrap()),
+                ..Default::default()
+            },
+        };
+        let mut session = Session::new();
+
+        let slot = key_slot("foo");
+        let foo = SlotRange::new(slot, slot).unwrap();
+        cluster
+            .assign(SlotRange::new(0, 16383).unwrap(), local.clone())
+            .unwrap();
+        cluster.assign(foo, other.clone()).unwrap();
+        assert_eq!(
+            execute(command("SET foo 1"), &replica, &mut session).await,
+            Frame::Error(format!("MOVED {} 127.0.0.1:6380", slot))
+        );
+        assert_eq!(replica.store.len(), 0, "Redirected writes are not applied");
+        assert_eq!(
+            execute(command("MGET a b"), &replica, &mut session).await,
+            Frame::Error("CROSSSLOT Keys in request don't hash to the same slot".into())
+        );
+
+        let without_resp = ClusterNode {
+            id: "without_resp".into(),
+            address: "127.0.0.1:5557".parse().unwrap(),
+            services: Services::default(),
+        };
+        cluster.assign(foo, without_resp).unwrap();
+        assert!(matches!(
+            execute(command("GET foo"), &replica, &mut session).await,
+            Frame::Error(message) if message.starts_with("CLUSTERDOWN")
+        ));
+
+        cluster.assign(foo, local.clone()).unwrap();
+        cluster.start_migration(foo, local.address, other).unwrap();
+        assert_eq!(
+            execute(command("GET foo"), &replica, &mut session).await,
+            Frame::Error(format!("ASK {} 127.0.0.1:6380", slot))
+        );
+
+        // O `ASKING` vale somente para o comando seguinte
+        let importing = Node::new(NodeMode::Master, "127.0.0.1:5556".parse().unwrap());
+        let importing = Arc::new(Replica::new(importing).with_cluster(Cluster::new()));
+        let cluster = importing.cluster.as_ref().unwrap();
+        cluster.assign(foo, local).unwrap();
+        cluster.start_import(foo).unwrap();
+        assert_eq!(
+            execute(command("ASKING"), &importing, &mut session).await,
+            Frame::ok()
+        );
+        assert_eq!(
+            execute(command("GET foo"), &importing, &mut session).await,
+            Frame::Null
+        );
+        assert_eq!(
+            execute(command("GET foo"), &importing, &mut session).await,
+            Frame::Error(format!("MOVED {} 127.0.0.1:6379", slot))
+        );
+    }
+}
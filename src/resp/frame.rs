@@ -0,0 +1,268 @@
+use super::RespError;
+
+/// Tamanho maximo aceito para uma bulk string, o mesmo limite padrão do Redis.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Quantidade maxima de elementos aceita em um array de comando.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+
+/// Tamanho maximo de um comando inline, o mesmo limite do Redis.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Tamanho maximo do numero de uma linha de tamanho, sem o `\r\n`.
+const MAX_LENGTH_LINE: usize = 32;
+
+/// Argumentos de um comando junto a quantidade de bytes lidos do buffer.
+type ParsedCommand = (Vec<Vec<u8>>, usize);
+
+/// Versão do protocolo RESP usada nas respostas de uma conexão.
+///
+/// Toda conexão começa em RESP2 e pode mudar para RESP3 com o comando `HELLO 3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+/// Valor do protocolo RESP enviado como resposta.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Frame>),
+    /// Mapa do RESP3, enviado como um array de pares em RESP2.
+    Map(Vec<(Frame, Frame)>),
+    /// Ausencia de valor, `$-1` em RESP2 e `_` em RESP3.
+    Null,
+}
+
+impl Frame {
+    pub fn ok() -> Self {
+        Frame::Simple("OK".into())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Frame::Error(format!("ERR {}", message.into()))
+    }
+
+    pub fn bulk(value: impl AsRef<[u8]>) -> Self {
+        Frame::Bulk(value.as_ref().to_vec())
+    }
+
+    /// Serializa o frame na versão do protocolo da conexão.
+    pub fn encode(&self, protocol: Protocol, out: &mut Vec<u8>) {
+        match self {
+            Frame::Simple(value) => {
+                out.push(b'+');
+                out.extend_from_slice(value.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Frame::Error(message) => {
+                out.push(b'-');
+                out.extend_from_slice(message.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Frame::Integer(number) => {
+                out.extend_from_slice(format!(":{}\r\n", number).as_bytes());
+            }
+            Frame::Bulk(bytes) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Frame::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(protocol, out);
+                }
+            }
+            Frame::Map(pairs) => {
+                match protocol {
+                    Protocol::Resp2 => {
+                        out.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes())
+                    }
+                    Protocol::Resp3 => {
+                        out.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes())
+                    }
+                }
+                for (key, value) in pairs {
+                    key.encode(protocol, out);
+                    value.encode(protocol, out);
+                }
+            }
+            Frame::Null => match protocol {
+                Protocol::Resp2 => out.extend_from_slice(b"$-1\r\n"),
+                Protocol::Resp3 => out.extend_from_slice(b"_\r\n"),
+            },
+        }
+    }
+}
+
+/// Lê um comando completo do inicio do buffer.
+///
+/// Aceita o formato de array de bulk strings enviado pelos clientes e o
+/// formato inline, uma linha com os argumentos separados por espaço.
+///
+/// Retorna `None` quando o buffer ainda não possui o comando inteiro, ou os
+/// argumentos junto a quantidade de bytes consumidos.
+pub fn parse_command(buffer: &[u8]) -> Result<Option<ParsedCommand>, RespError> {
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+    if buffer[0] != b'*' {
+        return parse_inline(buffer);
+    }
+
+    let Some((length, mut position)) = read_number(buffer, 1)? else {
+        return Ok(None);
+    };
+    if length <= 0 {
+        return Ok(Some((Vec::new(), position)));
+    }
+    let length = length as usize;
+    if length > MAX_ARRAY_LEN {
+        return Err(RespError::Protocol("invalid multibulk length".into()));
+    }
+
+    let mut args = Vec::with_capacity(length);
+    for _ in 0..length {
+        match buffer.get(position) {
+            None => return Ok(None),
+            Some(b'$') => {}
+            Some(&other) => {
+                return Err(RespError::Protocol(format!(
+                    "expected '$', got '{}'",
+                    other as char
+                )));
+            }
+        }
+        let Some((size, start)) = read_number(buffer, position + 1)? else {
+            return Ok(None);
+        };
+        if size < 0 || size as usize > MAX_BULK_LEN {
+            return Err(RespError::Protocol("invalid bulk length".into()));
+        }
+        let end = start + size as usize;
+        if buffer.len() < end + 2 {
+            return Ok(None);
+        }
+        if &buffer[end..end + 2] != b"\r\n" {
+            return Err(RespError::Protocol("bulk string without CRLF".into()));
+        }
+        args.push(buffer[start..end].to_vec());
+        position = end + 2;
+    }
+
+    Ok(Some((args, position)))
+}
+
+/// Lê um comando inline, terminado por `\n` ou `\r\n`.
+///
+/// Uma linha maior que `MAX_INLINE_LEN` é um erro de protocolo, mesmo antes
+/// de chegar inteira, assim o buffer da conexão não cresce sem limite.
+fn parse_inline(buffer: &[u8]) -> Result<Option<ParsedCommand>, RespError> {
+    let window = &buffer[..buffer.len().min(MAX_INLINE_LEN + 1)];
+    let Some(end) = window.iter().position(|&byte| byte == b'\n') else {
+        if buffer.len() > MAX_INLINE_LEN {
+            return Err(RespError::Protocol("too big inline request".into()));
+        }
+        return Ok(None);
+    };
+    let args = buffer[..end]
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| arg.to_vec())
+        .collect();
+    Ok(Some((args, end + 1)))
+}
+
+/// Lê o numero de uma linha de tamanho, como `*3\r\n` ou `$5\r\n`, a partir de `start`.
+///
+/// Retorna o numero junto a posição logo apos o `\r\n`. Uma linha maior que
+/// `MAX_LENGTH_LINE` é um erro de protocolo, mesmo antes de chegar inteira.
+fn read_number(buffer: &[u8], start: usize) -> Result<Option<(i64, usize)>, RespError> {
+    let window = &buffer[start..buffer.len().min(start + MAX_LENGTH_LINE + 2)];
+    let Some(offset) = window.windows(2).position(|w| w == b"\r\n") else {
+        if window.len() >= MAX_LENGTH_LINE + 2 {
+            return Err(RespError::Protocol("invalid length".into()));
+        }
+        return Ok(None);
+    };
+    let line = &buffer[start..start + offset];
+    let number = std::str::from_utf8(line)
+        .ok()
+        .filter(|line| !line.starts_with('+'))
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| RespError::Protocol("invalid length".into()))?;
+    Ok(Some((number, start + offset + 2)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let buffer = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n*1\r\n$4\r\nPING";
+        let (args, consumed) = parse_command(buffer).unwrap().unwrap();
+        assert_eq!(
+            args,
+            vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]
+        );
+
+        // O segundo comando ainda não chegou inteiro
+        assert_eq!(parse_command(&buffer[consumed..]).unwrap(), None);
+
+        let (args, consumed) = parse_command(b"GET  key\r\nrest").unwrap().unwrap();
+        assert_eq!(args, vec![b"GET".to_vec(), b"key".to_vec()]);
+        assert_eq!(consumed, 10);
+
+        assert!(parse_command(b"*1\r\n+PING\r\n").is_err());
+    }
+
+    #[test]
+    fn test_inline_length_limit() {
+        let mut buffer = vec![b'a'; MAX_INLINE_LEN];
+        assert_eq!(parse_command(&buffer).unwrap(), None);
+
+        buffer.push(b'a');
+        assert!(
+            parse_command(&buffer).is_err(),
+            "Should reject before the line ends"
+        );
+
+        buffer.push(b'\n');
+        assert!(parse_command(&buffer).is_err());
+    }
+
+    #[test]
+    fn test_length_line_limit() {
+        let mut buffer = b"*".to_vec();
+        buffer.extend(vec![b'1'; MAX_LENGTH_LINE + 1]);
+        assert_eq!(parse_command(&buffer).unwrap(), None);
+
+        buffer.push(b'1');
+        assert!(
+            parse_command(&buffer).is_err(),
+            "Should reject before the CRLF arrives"
+        );
+        assert!(parse_command(b"*1\r\n$99999999999999999999999999999999999").is_err());
+
+        assert!(parse_command(b"*+1\r\n$4\r\nPING\r\n").is_err());
+        assert!(parse_command(b"*1\r\n$+4\r\nPING\r\n").is_err());
+    }
+
+    #[test]
+    fn test_encode_frames() {
+        let frame = Frame::Array(vec![Frame::bulk("a"), Frame::Null, Frame::Integer(3)]);
+        let mut resp2 = Vec::new();
+        frame.encode(Protocol::Resp2, &mut resp2);
+        assert_eq!(resp2, b"*3\r\n$1\r\na\r\n$-1\r\n:3\r\n");
+
+        let mut resp3 = Vec::new();
+        frame.encode(Protocol::Resp3, &mut resp3);
+        assert_eq!(resp3, b"*3\r\n$1\r\na\r\n_\r\n:3\r\n");
+    }
+}
@@ -0,0 +1,34 @@
+mod commands;
+mod frame;
+mod server;
+
+use commands::*;
+use frame::*;
+pub use server::*;
+
+use crate::replication::Replica;
+
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub enum RespError {
+    Tokio(tokio::io::Error),
+    Protocol(String),
+}
+
+impl From<tokio::io::Error> for RespError {
+    fn from(error: tokio::io::Error) -> Self {
+        RespError::Tokio(error)
+    }
+}
+
+impl std::error::Error for RespError {}
+
+impl Display for RespError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RespError::Tokio(error) => write!(f, "RESP error: {}", error),
+            RespError::Protocol(message) => write!(f, "{}", message),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{Frame, Replica, RespError, Session, execute, parse_command};
+
+/// Inicia o listener TCP compativel com o protocolo do Redis.
+///
+/// Os comandos de uma conexão são executados na ordem em que chegam e as
+/// respostas de um mesmo lote lido do socket são enviadas juntas, permitindo
+/// pipelining pelos clientes.
+pub async fn start(replica: Arc<Replica>, ipaddr: SocketAddr) -> Result<(), RespError> {
+    let listener = TcpListener::bind(ipaddr).await?;
+    println!("Serviço RESP iniciado: {}", ipaddr);
+    serve(replica, listener).await
+}
+
+/// Atende as conexões RESP de um listener ja aberto.
+pub async fn serve(replica: Arc<Replica>, listener: TcpListener) -> Result<(), RespError> {
+    while let Ok((stream, _)) = listener.accept().await {
+        let replica = replica.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &replica).await {
+                eprintln!("Conexão RESP encerrada: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_connection(mut stream: TcpStream, replica: &Arc<Replica>) -> Result<(), RespError> {
+    let mut session = Session::new();
+    let mut buffer = Vec::with_capacity(4096);
+    let mut output = Vec::new();
+
+    loop {
+        if stream.read_buf(&mut buffer).await? == 0 {
+            return Ok(());
+        }
+
+        let mut consumed = 0;
+        loop {
+            match parse_command(&buffer[consumed..]) {
+                Ok(Some((args, length))) => {
+                    consumed += length;
+                    if args.is_empty() {
+                        continue;
+                    }
+                    let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+                    let reply = match quit {
+                        true => Frame::ok(),
+                        false => execute(args, replica, &mut session).await,
+                    };
+                    reply.encode(session.protocol, &mut output);
+                    if quit {
+                        stream.write_all(&output).await?;
+                        return Ok(());
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    Frame::error(format!("Protocol error: {}", e))
+                        .encode(session.protocol, &mut output);
+                    stream.write_all(&output).await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        buffer.drain(..consumed);
+        stream.write_all(&output).await?;
+        output.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{
+        replication::{Node, NodeMode, Replica},
+        resp::serve,
+        test_support::listen,
+    };
+
+    async fn start_server() -> TcpStream {
+        let (listener, ipaddr) = listen().await;
+        let mode = NodeMode::try_from("master".to_string()).unwrap();
+        let replica = Arc::new(Replica::new(Node::new(mode, ipaddr)));
+        tokio::spawn(serve(replica, listener));
+
+        TcpStream::connect(ipaddr).await.expect("Failed to connect")
+    }
+
+    /// Envia os comandos e le as respostas ate receber `expected` bytes.
+    async fn roundtrip(stream: &mut TcpStream, request: &[u8], expected: &[u8]) {
+        stream
+            .write_all(request)
+            .await
+            .expect("failed to send command");
+
+        let mut response = vec![0; expected.len()];
+        stream
+            .read_exact(&mut response)
+            .await
+            .expect("failed to read response");
+        assert_eq!(
+            String::from_utf8_lossy(&response),
+            String::from_utf8_lossy(expected)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resp_commands() {
+        let mut stream = start_server().await;
+
+        roundtrip(&mut stream, b"PING\r\n", b"+PONG\r\n").await;
+        roundtrip(
+            &mut stream,
+            b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
+            b"+OK\r\n$5\r\nvalue\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, b"SET key other NX\r\n", b"$-1\r\n").await;
+        roundtrip(&mut stream, b"SET key other XX GET\r\n", b"$5\r\nvalue\r\n").await;
+        roundtrip(
+            &mut stream,
+            b"SET tmp 1 EX 100\r\nTTL tmp\r\n",
+            b"+OK\r\n:100\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            b"TTL key\r\nTTL missing\r\n",
+            b":-1\r\n:-2\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            b"EXPIRE key 0\r\nEXISTS key tmp\r\n",
+            b":1\r\n:1\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            b"INCR counter\r\nINCR counter\r\n",
+            b":1\r\n:2\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            b"INCR tmp\r\nINCR tmp\r\nSET text abc\r\nINCR text\r\n",
+            b":2\r\n:3\r\n+OK\r\n-ERR value is not an integer or out of range\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            b"MSET a 1 b 2\r\nMGET a missing b\r\n",
+            b"+OK\r\n*3\r\n$1\r\n1\r\n$-1\r\n$1\r\n2\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, b"DEL a b missing\r\n", b":2\r\n").await;
+        roundtrip(
+            &mut stream,
+            b"SET key value EX 0\r\n",
+            b"-ERR invalid expire time in 'set' command\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            b"UNKNOWN\r\n",
+            b"-ERR unknown command 'unknown'\r\n",
+        )
+        .await;
+        let hello = format!(
+            "%6\r\n$6\r\nserver\r\n$12\r\ncrusty-cache\r\n$7\r\nversion\r\n${}\r\n{}\r\n\
+             $5\r\nproto\r\n:3\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n$4\r\nrole\r\n\
+             $6\r\nmaster\r\n$7\r\nmodules\r\n*0\r\n",
+            env!("CARGO_PKG_VERSION").len(),
+            env!("CARGO_PKG_VERSION")
+        );
+        roundtrip(&mut stream, b"HELLO 3\r\n", hello.as_bytes()).await;
+        roundtrip(&mut stream, b"GET missing\r\n", b"_\r\n").await;
+        roundtrip(
+            &mut stream,
+            b"FLUSHALL\r\nEXISTS counter\r\n",
+            b"+OK\r\n:0\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, b"QUIT\r\n", b"+OK\r\n").await;
+    }
+}
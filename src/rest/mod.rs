@@ -0,0 +1,30 @@
+mod routes;
+mod server;
+
+use routes::*;
+pub use server::*;
+
+use crate::replication::Replica;
+
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub enum RestError {
+    Tokio(tokio::io::Error),
+}
+
+impl From<tokio::io::Error> for RestError {
+    fn from(error: tokio::io::Error) -> Self {
+        RestError::Tokio(error)
+    }
+}
+
+impl std::error::Error for RestError {}
+
+impl Display for RestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestError::Tokio(error) => write!(f, "REST error: {}", error),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    Method, Request, Response, StatusCode, Uri,
+    body::{Bytes, Incoming},
+    header,
+};
+use serde::Serialize;
+
+use crate::{
+    memory::{CacheValue, MemoryError, StoreStats, clock},
+    replication::{ReplicationStats, Route},
+};
+
+use super::Replica;
+
+/// Tamanho maximo aceito para o corpo de um `PUT` quando o cache não tem
+/// limite de memória, o mesmo de um bulk string no listener RESP.
+const MAX_BODY_LEN: usize = 512 * 1024 * 1024;
+
+/// Header com o tempo de vida em segundos, alternativa ao parametro `ttl`.
+const TTL_HEADER: &str = "x-ttl";
+
+type HttpResponse = Response<Full<Bytes>>;
+
+/// Corpo do `GET /stats`, as estatisticas do cache junto ao estado da replicação.
+#[derive(Serialize)]
+struct Stats {
+    #[serde(flatten)]
+    store: StoreStats,
+    replication: ReplicationStats,
+}
+
+/// Atende uma requisição do gateway HTTP.
+///
+/// - `GET /keys/{key}` devolve os bytes do valor, `404` quando não existe.
+/// - `PUT /keys/{key}` grava o corpo como valor, com tempo de vida opcional em
+///   segundos pelo parametro `?ttl=` ou pelo header `X-TTL`.
+/// - `DELETE /keys/{key}` remove a `key`, `404` quando não existe.
+/// - `GET /health` e `GET /stats` informam o estado do nó, do cache e dos slaves conectados.
+///
+/// No modo cluster as `keys` de outro master são redirecionadas com `307`
+/// para o gateway HTTP dele, ou recusadas com `421` quando ele não possui um.
+pub async fn handle(
+    request: Request<Incoming>,
+    replica: Arc<Replica>,
+) -> Result<HttpResponse, Infallible> {
+    let path = request.uri().path().to_string();
+
+    let response = match path.strip_prefix("/keys/") {
+        Some(key) => match percent_decode(key) {
+            Some(key) if !key.is_empty() => keys(request, key, &replica).await,
+            _ => text(StatusCode::BAD_REQUEST, "invalid key"),
+        },
+        None => match (request.method(), path.as_str()) {
+            (&Method::GET, "/health") => json(
+                StatusCode::OK,
+                &serde_json::json!({"status": "ok", "mode": replica.node().mode.to_string()}),
+            ),
+            (&Method::GET, "/stats") => json(
+                StatusCode::OK,
+                &Stats {
+                    store: replica.store.stats(),
+                    replication: replica.replication_stats().await,
+                },
+            ),
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        },
+    };
+    Ok(response)
+}
+
+async fn keys(request: Request<Incoming>, key: String, replica: &Replica) -> HttpResponse {
+    let asking = request.uri().query().is_some_and(|query| {
+        query
+            .split('&')
+            .any(|pair| pair == "asking" || pair.starts_with("asking="))
+    });
+    if let Some(response) = redirect(request.uri(), replica.route(&[&key], asking)) {
+        return response;
+    }
+
+    let is_write = matches!(*request.method(), Method::PUT | Method::DELETE);
+    if is_write && replica.is_read_only() {
+        return text(StatusCode::FORBIDDEN, "read only replica");
+    }
+
+    match *request.method() {
+        Method::GET => match replica.store.get(&key) {
+            Some(value) => Response::builder()
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .body(Full::new(Bytes::copy_from_slice(value.as_bytes())))
+                .unwrap(),
+            None => text(StatusCode::NOT_FOUND, "key not found"),
+        },
+        Method::PUT => put(request, key, replica)
+            .await
+            .unwrap_or_else(|response| response),
+        Method::DELETE => match replica.store.delete(&key) {
+            true => empty(StatusCode::NO_CONTENT),
+            false => text(StatusCode::NOT_FOUND, "key not found"),
+        },
+        _ => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, "GET, PUT, DELETE")
+            .body(Full::default())
+            .unwrap(),
+    }
+}
+
+/// Tamanho maximo do corpo de um `PUT`, limitado tambem pelo limite de memória
+/// do cache, ja que um valor maior que ele nunca seria aceito.
+fn body_limit(replica: &Replica) -> usize {
+    match replica.store.stats().max_memory {
+        0 => MAX_BODY_LEN,
+        max_memory => MAX_BODY_LEN.min(max_memory as usize),
+    }
+}
+
+async fn put(
+    request: Request<Incoming>,
+    key: String,
+    replica: &Replica,
+) -> Result<HttpResponse, HttpResponse> {
+    let ttl = ttl(&request).map_err(|message| text(StatusCode::BAD_REQUEST, message))?;
+    let limit = body_limit(replica);
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit as u64) {
+        return Err(text(StatusCode::PAYLOAD_TOO_LARGE, "body too large"));
+    }
+    let body = Limited::new(request.into_body(), limit)
+        .collect()
+        .await
+        .map_err(|e| match e.downcast_ref::<LengthLimitError>() {
+            Some(_) => text(StatusCode::PAYLOAD_TOO_LARGE, "body too large"),
+            None => text(
+                StatusCode::BAD_REQUEST,
+                &format!("failed to read body: {}", e),
+            ),
+        })?
+        .to_bytes();
+
+    let value = CacheValue::new(&body);
+    let result = match ttl {
+        Some(ttl) => {
+            let deadline = clock::deadline_from_ttl(ttl);
+            replica.store.set_with_deadline(key, value, deadline).await
+        }
+        None => replica.store.set(key, value),
+    };
+
+    match result {
+        Ok(()) => Ok(empty(StatusCode::NO_CONTENT)),
+        Err(e @ MemoryError::OutOfMemory(_)) => {
+            Err(text(StatusCode::INSUFFICIENT_STORAGE, &e.to_string()))
+        }
+        Err(e) => Err(text(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    }
+}
+
+/// Resposta para as `keys` que não são atendidas por este nó, `None` quando são.
+///
+/// O `307` mantem o metodo e o corpo da requisição. O `Location` de um slot
+/// em migração leva o parametro `asking`, que libera a `key` no destino.
+fn redirect(uri: &Uri, route: Route) -> Option<HttpResponse> {
+    let (slot, node, asking) = match route {
+        Route::Local => return None,
+        Route::Moved(slot, node) => (slot, node, false),
+        Route::Ask(slot, node) => (slot, node, true),
+        Route::Unassigned(slot) => {
+            let message = format!("slot {} is not served by any node", slot);
+            return Some(text(StatusCode::SERVICE_UNAVAILABLE, &message));
+        }
+        Route::CrossSlot => {
+            let message = "keys in request don't hash to the same slot";
+            return Some(text(StatusCode::BAD_REQUEST, message));
+        }
+    };
+    let Some(address) = node.services.http else {
+        let message = format!(
+            "slot {} is served by node {} without the HTTP service",
+            slot, node.id
+        );
+        return Some(text(StatusCode::MISDIRECTED_REQUEST, &message));
+    };
+
+    let query: Vec<&str> = uri
+        .query()
+        .into_iter()
+        .chain(asking.then_some("asking"))
+        .collect();
+    let mut location = format!("http://{}{}", address, uri.path());
+    if !query.is_empty() {
+        location = format!("{}?{}", location, query.join("&"));
+    }
+    Some(
+        Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(header::LOCATION, location)
+            .body(Full::default())
+            .unwrap(),
+    )
+}
+
+/// Lê o tempo de vida em segundos do parametro `ttl`, ou do header `X-TTL` quando ausente.
+fn ttl(request: &Request<Incoming>) -> Result<Option<Duration>, &'static str> {
+    let from_query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("ttl="))
+            .map(str::to_string)
+    });
+    let from_header = || {
+        request
+            .headers()
+            .get(TTL_HEADER)
+            .map(|value| value.to_str().unwrap_or_default().to_string())
+    };
+
+    match from_query.or_else(from_header) {
+        None => Ok(None),
+        Some(seconds) => match seconds.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Ok(Some(Duration::from_secs(seconds))),
+            _ => Err("ttl must be a positive number of seconds"),
+        },
+    }
+}
+
+/// Decodifica os caracteres `%XX` da `key` informada na URL.
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] == b'%' {
+            let hex = input.get(position + 1..position + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            position += 3;
+        } else {
+            decoded.push(bytes[position]);
+            position += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn empty(status: StatusCode) -> HttpResponse {
+    Response::builder()
+        .status(status)
+        .body(Full::default())
+        .unwrap()
+}
+
+fn text(status: StatusCode, message: &str) -> HttpResponse {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from(message.to_string())))
+        .unwrap()
+}
+
+fn json<T: Serialize>(status: StatusCode, body: &T) -> HttpResponse {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap(),
+        Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{StatusCode, Uri, header};
+
+    use crate::replication::{ClusterNode, Route, Services};
+
+    use super::{percent_decode, redirect};
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("user:1").as_deref(), Some("user:1"));
+        assert_eq!(percent_decode("a%2Fb%20c").as_deref(), Some("a/b c"));
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(percent_decode("bad%zz"), None);
+    }
+
+    #[test]
+    fn test_cluster_redirect() {
+        let uri: Uri = "/keys/foo?ttl=10".parse().unwrap();
+        let mut node = ClusterNode {
+            id: "other".into(),
+            address: "127.0.0.1:5556".parse().unwrap(),
+            services: Services {
+                http: Some("127.0.0.1:8081".parse().unwrap()),
+                ..Default::default()
+            },
+        };
+        assert!(redirect(&uri, Route::Local).is_none());
+
+        let location = |route| {
+            let response = redirect(&uri, route).unwrap();
+            assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+            response.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            location(Route::Moved(12182, node.clone())),
+            "http://127.0.0.1:8081/keys/foo?ttl=10"
+        );
+        assert_eq!(
+            location(Route::Ask(12182, node.clone())),
+            "http://127.0.0.1:8081/keys/foo?ttl=10&asking"
+        );
+
+        node.services = Services::default();
+        let response = redirect(&uri, Route::Moved(12182, node)).unwrap();
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+        let response = redirect(&uri, Route::Unassigned(12182)).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,158 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use hyper::{server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+
+use super::{Replica, RestError, handle};
+
+/// Inicia o gateway HTTP do cache.
+///
+/// Cada conexão é atendida em sua propria tarefa, usando HTTP/1.1 com keep-alive.
+pub async fn start(replica: Arc<Replica>, ipaddr: SocketAddr) -> Result<(), RestError> {
+    let listener = TcpListener::bind(ipaddr).await?;
+    println!("Serviço HTTP iniciado: {}", ipaddr);
+    serve(replica, listener).await
+}
+
+/// Atende as conexões HTTP de um listener ja aberto.
+pub async fn serve(replica: Arc<Replica>, listener: TcpListener) -> Result<(), RestError> {
+    while let Ok((stream, _)) = listener.accept().await {
+        let replica = replica.clone();
+        tokio::spawn(async move {
+            let service = service_fn(|request| handle(request, replica.clone()));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("Conexão HTTP encerrada: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{
+        memory::{EvictionPolicy, Store},
+        replication::{Node, NodeMode, Replica},
+        rest::serve,
+        test_support::listen,
+    };
+
+    /// Envia uma requisição HTTP/1.1 e devolve o status junto ao corpo da resposta.
+    async fn request(
+        port: u16,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port))
+            .await
+            .expect("Failed to connect");
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .expect("failed to send request");
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("failed to read response");
+        let status = response[9..12].parse().expect("Invalid status line");
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_rest_gateway() {
+        let (listener, ipaddr) = listen().await;
+        let port = ipaddr.port();
+        let mode = NodeMode::try_from("master".to_string()).unwrap();
+        let replica = Arc::new(Replica::new(Node::new(mode, ipaddr)));
+        tokio::spawn(serve(replica.clone(), listener));
+
+        assert_eq!(request(port, "GET", "/keys/user%3A1", "", "").await.0, 404);
+        assert_eq!(
+            request(port, "PUT", "/keys/user%3A1", "", "hello").await.0,
+            204
+        );
+        assert_eq!(
+            request(port, "GET", "/keys/user:1", "", "").await,
+            (200, "hello".to_string())
+        );
+
+        assert_eq!(request(port, "PUT", "/keys/a?ttl=60", "", "1").await.0, 204);
+        assert_eq!(
+            request(port, "PUT", "/keys/b", "X-TTL: 60\r\n", "2")
+                .await
+                .0,
+            204
+        );
+        assert_eq!(request(port, "PUT", "/keys/c?ttl=0", "", "3").await.0, 400);
+        assert_eq!(replica.store.stats().volatile_keys, 2);
+
+        assert_eq!(request(port, "DELETE", "/keys/a", "", "").await.0, 204);
+        assert_eq!(request(port, "DELETE", "/keys/a", "", "").await.0, 404);
+        assert_eq!(request(port, "POST", "/keys/a", "", "").await.0, 405);
+
+        let (status, body) = request(port, "GET", "/health", "", "").await;
+        assert_eq!(status, 200);
+        assert!(
+            body.contains("\"status\":\"ok\""),
+            "Unexpected body: {}",
+            body
+        );
+
+        let (status, body) = request(port, "GET", "/stats", "", "").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"keys\":2"), "Unexpected body: {}", body);
+        assert!(
+            body.contains("\"connected_slaves\":0"),
+            "Unexpected body: {}",
+            body
+        );
+
+        assert_eq!(request(port, "GET", "/unknown", "", "").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn test_body_limited_by_max_memory() {
+        let (listener, ipaddr) = listen().await;
+        let mode = NodeMode::try_from("master".to_string()).unwrap();
+        let store = Store::with_limit(1024, EvictionPolicy::NoEviction);
+        let replica = Arc::new(Replica::with_store(Node::new(mode, ipaddr), store));
+        tokio::spawn(serve(replica.clone(), listener));
+
+        let large = "x".repeat(2048);
+        assert_eq!(
+            request(ipaddr.port(), "PUT", "/keys/a", "", &large).await.0,
+            413
+        );
+        assert_eq!(
+            request(ipaddr.port(), "PUT", "/keys/a", "", "1").await.0,
+            204
+        );
+    }
+}
@@ -1,7 +1,511 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    memory::{CacheValue, WriteCondition, WriteOutcome, clock},
+    replication::{ClusterNode, Route, SlotRange, gossip_with, migrate_slots},
+};
+
+use super::{Replica, Reply, Responses};
+
+/// Envelope de uma requisição recebida pelo serviço de socket.
+///
+/// O `id` é opcional e escolhido pelo cliente, ele é devolvido na resposta
+/// permitindo enviar varios comandos sem esperar e identificar cada resposta,
+/// que podem chegar fora da ordem de envio.
+///
+/// O `max_staleness`, tambem opcional, limita em milissegundos o atraso
+/// aceito para as leituras atendidas por um slave, que responde `Stale`
+/// quando esta mais atrasado que isso.
+///
+/// Com `wait` a resposta de uma escrita espera ate `replicas` slaves
+/// confirmarem a alteração ou `timeout` milissegundos passarem, e informa
+/// em `acknowledged` quantos confirmaram.
+///
+/// No modo cluster as `keys` de outro master são respondidas com `Moved` e
+/// as de um slot em migração que ja sairam daqui com `Ask`. O `asking` marca
+/// a requisição refeita no destino de um `Ask`.
+///
+/// ```json
+/// {"id": 1, "command": "Get", "data": "user:1"}
+/// {"id": 2, "max_staleness": 2000, "command": "Get", "data": "user:1"}
+/// {"id": 3, "wait": {"replicas": 2, "timeout": 500}, "command": "Set", "data": {"key": "user:1", "value": "aGk="}}
+/// {"id": 4, "asking": true, "command": "Get", "data": "user:1"}
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_staleness: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait: Option<WaitOptions>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub asking: bool,
+    #[serde(flatten)]
+    pub command: Commands,
+}
+
+/// Confirmações dos slaves esperadas por uma escrita.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WaitOptions {
+    pub replicas: usize,
+    /// Tempo maximo de espera em milissegundos.
+    pub timeout: u64,
+}
+
+impl Request {
+    /// Executa o comando e monta a resposta com o `id` da requisição.
+    ///
+    /// Leituras com `max_staleness` são recusadas quando o atraso do nó
+    /// passa do limite ou ainda não é conhecido. O `wait` so vale para escritas
+    /// que foram aplicadas, as recusadas respondem sem esperar os slaves.
+    pub async fn execute(self, replica: &Arc<Replica>) -> Reply {
+        let route = replica.route(&self.command.keys(), self.asking);
+        if let Some(response) = redirect(route) {
+            return Reply {
+                id: self.id,
+                acknowledged: None,
+                response,
+            };
+        }
+
+        let wait = self.wait.filter(|_| self.command.is_write());
+        let response = match self.max_staleness {
+            Some(bound) if self.command.is_read() => match replica.staleness() {
+                Some(staleness) if staleness <= bound => self.command.execute(replica).await,
+                staleness => Responses::Stale(staleness),
+            },
+            _ => self.command.execute(replica).await,
+        };
+
+        // O offset atual cobre a escrita, e talvez escritas concorrentes a ela
+        let acknowledged = match wait {
+            Some(_) if response.is_rejection() => None,
+            Some(wait) => {
+                let timeout = Duration::from_millis(wait.timeout);
+                let offset = replica.log.offset();
+                Some(replica.wait_for_acks(offset, wait.replicas, timeout).await)
+            }
+            None => None,
+        };
+        Reply {
+            id: self.id,
+            acknowledged,
+            response,
+        }
+    }
+
+    /// Lê uma requisição em JSON.
+    ///
+    /// Em caso de falha devolve a mensagem de erro junto ao `id`, quando ele
+    /// puder ser lido, para que o cliente ainda consiga relacionar a resposta.
+    pub fn from_json(text: &str) -> Result<Self, (Option<u64>, String)> {
+        let value: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| (None, format!("Invalid JSON: {}", e)))?;
+        let id = value.get("id").and_then(|id| id.as_u64());
+
+        serde_json::from_value(value).map_err(|e| (id, format!("Invalid command: {}", e)))
+    }
+
+    /// Lê uma requisição em MessagePack, com as mesmas regras de `from_json`.
+    ///
+    /// A requisição deve ser um mapa com os mesmos campos do JSON e o valor
+    /// das `keys` pode ser enviado como bytes.
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, (Option<u64>, String)> {
+        rmp_serde::from_slice(bytes).map_err(|e| {
+            let id = rmp_serde::from_slice::<RequestId>(bytes)
+                .ok()
+                .and_then(|request| request.id);
+            (id, format!("Invalid command: {}", e))
+        })
+    }
+}
+
+/// Resposta para as `keys` que não são atendidas por este nó, `None` quando são.
+fn redirect(route: Route) -> Option<Responses> {
+    match route {
+        Route::Local => None,
+        Route::Moved(slot, node) => Some(Responses::Moved { slot, node }),
+        Route::Ask(slot, node) => Some(Responses::Ask { slot, node }),
+        Route::Unassigned(slot) => Some(Responses::Error(format!(
+            "slot {} is not served by any node",
+            slot
+        ))),
+        Route::CrossSlot => Some(Responses::Error(
+            "keys in request don't hash to the same slot".into(),
+        )),
+    }
+}
+
+/// Somente o `id` de uma requisição, usado quando o restante é invalido.
+#[derive(Deserialize)]
+struct RequestId {
+    #[serde(default)]
+    id: Option<u64>,
+}
+
+/// Comandos aceitos pelo serviço de socket.
+///
+/// Cada mensagem recebida pelo WebSocket deve conter um unico comando, em JSON
+/// ou MessagePack conforme o `Format` da conexão, identificado pelo campo
+/// `command` e com os argumentos em `data`, dentro do envelope `Request`.
+///
+/// ```json
+/// {"command": "Get", "data": "user:1"}
+/// {"command": "GetVersion", "data": "user:1"}
+/// {"command": "Set", "data": {"key": "user:1", "value": "aGk="}}
+/// {"command": "Set", "data": {"key": "user:1", "value": "aGk=", "ex": 60}}
+/// {"command": "Set", "data": {"key": "user:1", "value": "aGk=", "px": 1500}}
+/// {"command": "Set", "data": {"key": "user:1", "value": "aGk=", "pxat": 1767225600000}}
+/// {"command": "Set", "data": {"key": "lock:1", "value": "MQ==", "px": 3000, "condition": "nx"}}
+/// {"command": "Set", "data": {"key": "user:1", "value": "aGk=", "condition": {"version": 3}}}
+/// {"command": "Set", "data": {"key": "user:1", "value": "aGk=", "get": true}}
+/// {"command": "Incr", "data": "visits"}
+/// {"command": "IncrBy", "data": {"key": "visits", "delta": 10}}
+/// {"command": "IncrByFloat", "data": {"key": "price", "delta": -0.5}}
+/// {"command": "Delete", "data": "user:1"}
+/// {"command": "Exists", "data": ["user:1", "user:2"]}
+/// {"command": "Ttl", "data": "user:1"}
+/// {"command": "MGet", "data": ["user:1", "user:2"]}
+/// {"command": "MSet", "data": [{"key": "user:1", "value": "aGk="}, {"key": "user:2", "value": "bw=="}]}
+/// {"command": "MDelete", "data": ["user:1", "user:2"]}
+/// {"command": "Clear"}
+/// {"command": "Info"}
+/// {"command": "Replicas"}
+/// {"command": "ReplicaOf", "data": "127.0.0.1:5555"}
+/// {"command": "ReplicaOf", "data": null}
+/// {"command": "ClusterSlots"}
+/// {"command": "ClusterNodes"}
+/// {"command": "ClusterMeet", "data": "127.0.0.1:5557"}
+/// {"command": "ClusterAssign", "data": {"start": 0, "end": 8191, "node": {"id": "9b1d...", "address": "127.0.0.1:5555", "services": {"socket": "127.0.0.1:50000"}}}}
+/// {"command": "ClusterMigrate", "data": {"start": 0, "end": 99, "target": "127.0.0.1:5557"}}
+/// ```
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "command", content = "data")]
 pub enum Commands {
     Test(String),
+    /// Busca o valor de uma `key`, respondendo com `Value` ou `NotFound`.
+    Get(String),
+    /// Busca o valor de uma `key` junto a sua versão, respondendo com `Versioned` ou `NotFound`.
+    GetVersion(String),
+    /// Insere ou sobrescreve o valor de uma `key`, respondendo com `Ok`.
+    ///
+    /// Com uma condição em `options` a escrita pode ser rejeitada, respondendo
+    /// com `ConditionFailed`, e com `get` a resposta traz o valor anterior.
+    Set {
+        key: String,
+        value: CacheValue,
+        #[serde(flatten)]
+        options: SetOptions,
+    },
+    /// Incrementa em 1 o inteiro da `key`, respondendo com `Integer`.
+    ///
+    /// Os comandos numericos criam a `key` em `0` quando ela não existe e
+    /// respondem com `NotNumeric` quando o valor atual não é um numero.
+    Incr(String),
+    /// Decrementa em 1 o inteiro da `key`, respondendo com `Integer`.
+    Decr(String),
+    /// Soma `delta` ao inteiro da `key`, respondendo com `Integer`.
+    IncrBy {
+        key: String,
+        delta: i64,
+    },
+    /// Subtrai `delta` do inteiro da `key`, respondendo com `Integer`.
+    DecrBy {
+        key: String,
+        delta: i64,
+    },
+    /// Soma `delta` ao numero decimal da `key`, respondendo com `Float`.
+    IncrByFloat {
+        key: String,
+        delta: f64,
+    },
+    /// Remove uma `key`, respondendo com `Ok` ou `NotFound` se ela não existir.
+    Delete(String),
+    /// Conta quantas das `keys` existem, respondendo com `Integer`.
+    Exists(Vec<String>),
+    /// Tempo de vida restante da `key` em milissegundos, respondendo com
+    /// `Integer`, `-1` quando ela não expira, ou `NotFound`.
+    Ttl(String),
+    /// Busca varias `keys`, respondendo com `Values` na ordem pedida e `null` para as ausentes.
+    MGet(Vec<String>),
+    /// Insere varios valores de uma vez, respondendo com `Ok`.
+    ///
+    /// Se um dos valores não puder ser escrito nenhum deles é.
+    MSet(Vec<KeyValue>),
+    /// Remove varias `keys`, respondendo com `Integer` com quantas existiam.
+    MDelete(Vec<String>),
+    /// Remove todas as `key/value` do cache, respondendo com `Ok`.
+    Clear,
+    /// Busca as estatisticas do cache, respondendo com `Info`.
+    Info,
+    /// Lista os slaves conectados ao nó e o atraso de cada um, respondendo com `Replicas`.
+    Replicas,
+    /// Torna o nó um slave do master no endereço de replicação informado, ou
+    /// o promove a master com `null`, respondendo com `Ok`.
+    ReplicaOf(Option<SocketAddr>),
+    /// Lista os intervalos de slots do cluster e o master de cada um, respondendo com `ClusterSlots`.
+    ClusterSlots,
+    /// Lista os nós do cluster com papel, slots e suspeitas de falha, respondendo com `ClusterNodes`.
+    ClusterNodes,
+    /// Troca a visão do cluster com o nó no endereço de replicação informado,
+    /// que passa a participar da fofoca, respondendo com `Ok`.
+    ClusterMeet(SocketAddr),
+    /// Atribui os slots ao master informado na visão deste nó, respondendo com `Ok`.
+    ClusterAssign {
+        #[serde(flatten)]
+        slots: SlotRange,
+        node: ClusterNode,
+    },
+    /// Migra os slots deste master, com suas `keys`, para o master no endereço
+    /// de replicação `target`, respondendo com `Integer` com quantas `keys` foram
+    /// transferidas.
+    ClusterMigrate {
+        #[serde(flatten)]
+        slots: SlotRange,
+        target: SocketAddr,
+    },
+}
+
+/// Par de `key` e valor usado pelos comandos em lote.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: CacheValue,
+}
+
+/// Opções do comando `Set`, serializadas junto aos campos `key` e `value`.
+///
+/// O tempo de vida é opcional e somente uma das opções pode ser informada:
+/// `ex` e `px` são relativos, em segundos e milissegundos, enquanto `exat`
+/// e `pxat` são timestamps Unix absolutos, em segundos e milissegundos.
+///
+/// A `condition` aceita `"nx"` (somente se não existir), `"xx"` (somente se
+/// existir), `{"value": "<base64>"}` e `{"version": n}` para compare-and-swap.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SetOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ex: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub px: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pxat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<WriteCondition>,
+    /// Responde com o valor anterior da `key`, `Value` ou `NotFound`, quando a escrita acontecer.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub get: bool,
+}
+
+impl Commands {
+    /// `keys` usadas pelo comando, que decidem o slot no modo cluster.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Commands::Get(key)
+            | Commands::GetVersion(key)
+            | Commands::Incr(key)
+            | Commands::Decr(key)
+            | Commands::Delete(key)
+            | Commands::Ttl(key)
+            | Commands::Set { key, .. }
+            | Commands::IncrBy { key, .. }
+            | Commands::DecrBy { key, .. }
+            | Commands::IncrByFloat { key, .. } => vec![key],
+            Commands::Exists(keys) | Commands::MGet(keys) | Commands::MDelete(keys) => {
+                keys.iter().map(String::as_str).collect()
+            }
+            Commands::MSet(items) => items.iter().map(|item| item.key.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Indica se o comando lê `keys` do `Store`, as leituras sujeitas ao `max_staleness`.
+    pub fn is_read(&self) -> bool {
+        matches!(
+            self,
+            Commands::Get(_)
+                | Commands::GetVersion(_)
+                | Commands::Exists(_)
+                | Commands::Ttl(_)
+                | Commands::MGet(_)
+        )
+    }
+
+    /// Indica se o comando altera o `Store`.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Commands::Set { .. }
+                | Commands::Incr(_)
+                | Commands::Decr(_)
+                | Commands::IncrBy { .. }
+                | Commands::DecrBy { .. }
+                | Commands::IncrByFloat { .. }
+                | Commands::Delete(_)
+                | Commands::MSet(_)
+                | Commands::MDelete(_)
+                | Commands::Clear
+        )
+    }
+
+    /// Executa o comando contra o `Store` da replica e devolve a resposta para o cliente.
+    ///
+    /// Escritas são rejeitadas com `ReadOnly` quando o nó é um slave.
+    pub async fn execute(self, replica: &Arc<Replica>) -> Responses {
+        if self.is_write() && replica.is_read_only() {
+            return Responses::ReadOnly {
+                master: *replica.node().master_ipaddr(),
+            };
+        }
+
+        match self {
+            Commands::Test(s) => Responses::Test(s),
+            Commands::Get(key) => match replica.store.get(&key) {
+                Some(value) => Responses::Value(value),
+                None => Responses::NotFound,
+            },
+            Commands::GetVersion(key) => match replica.store.get_versioned(&key) {
+                Some((value, version)) => Responses::Versioned { value, version },
+                None => Responses::NotFound,
+            },
+            Commands::Set {
+                key,
+                value,
+                options,
+            } => {
+                let expires_at =
+                    match expiration(options.ex, options.px, options.exat, options.pxat) {
+                        Ok(expires_at) => expires_at,
+                        Err(e) => return Responses::Error(e),
+                    };
+
+                if options.condition.is_none() && !options.get {
+                    return match expires_at {
+                        None => replica.store.set(key, value).into(),
+                        Some(deadline) => replica
+                            .store
+                            .set_with_deadline(key, value, deadline)
+                            .await
+                            .into(),
+                    };
+                }
+
+                let outcome = replica
+                    .store
+                    .write(key, value, expires_at, options.condition.as_ref())
+                    .await;
+                match outcome {
+                    Ok(WriteOutcome::Written { previous, .. }) if options.get => match previous {
+                        Some(value) => Responses::Value(value),
+                        None => Responses::NotFound,
+                    },
+                    Ok(WriteOutcome::Written { .. }) => Responses::Ok,
+                    Ok(WriteOutcome::Rejected) => Responses::ConditionFailed,
+                    Err(e) => e.into(),
+                }
+            }
+            Commands::Incr(key) => replica.store.incr_by(&key, 1).into(),
+            Commands::Decr(key) => replica.store.incr_by(&key, -1).into(),
+            Commands::IncrBy { key, delta } => replica.store.incr_by(&key, delta).into(),
+            Commands::DecrBy { key, delta } => match delta.checked_neg() {
+                Some(delta) => replica.store.incr_by(&key, delta).into(),
+                None => Responses::Error("decrement would overflow".into()),
+            },
+            Commands::IncrByFloat { key, delta } => {
+                match replica.store.incr_by_float(&key, delta) {
+                    Ok(number) => Responses::Float(number),
+                    Err(e) => e.into(),
+                }
+            }
+            Commands::Delete(key) => match replica.store.delete(&key) {
+                true => Responses::Ok,
+                false => Responses::NotFound,
+            },
+            Commands::Exists(keys) => {
+                let count = keys.iter().filter(|key| replica.store.exists(key)).count();
+                Responses::Integer(count as i64)
+            }
+            Commands::Ttl(key) => match replica.store.ttl(&key) {
+                Some(ttl) => Responses::Integer(ttl.unwrap_or(-1)),
+                None => Responses::NotFound,
+            },
+            Commands::MGet(keys) => Responses::Values(replica.store.get_many(&keys)),
+            Commands::MSet(items) => replica
+                .store
+                .set_many(items.into_iter().map(|i| (i.key, i.value)).collect())
+                .into(),
+            Commands::MDelete(keys) => Responses::Integer(replica.store.delete_many(&keys) as i64),
+            Commands::Clear => {
+                replica.store.clear();
+                Responses::Ok
+            }
+            Commands::Info => Responses::Info(replica.store.stats()),
+            Commands::Replicas => Responses::Replicas(replica.replicas().await),
+            Commands::ReplicaOf(Some(master)) if master == *replica.node().address() => {
+                Responses::Error("a node can't replicate itself".into())
+            }
+            Commands::ReplicaOf(Some(master)) => {
+                replica.replicate_from(master);
+                Responses::Ok
+            }
+            Commands::ReplicaOf(None) => match replica.promote() {
+                Ok(()) => Responses::Ok,
+                Err(e) => Responses::Error(e.to_string()),
+            },
+            Commands::ClusterSlots => match &replica.cluster {
+                Some(cluster) => Responses::ClusterSlots(cluster.assignments()),
+                None => Responses::Error("cluster mode is disabled".into()),
+            },
+            Commands::ClusterNodes => match &replica.cluster {
+                Some(cluster) => Responses::ClusterNodes(cluster.nodes(&replica.node().id)),
+                None => Responses::Error("cluster mode is disabled".into()),
+            },
+            Commands::ClusterMeet(address) => match gossip_with(replica, address).await {
+                Ok(()) => Responses::Ok,
+                Err(e) => Responses::Error(e.to_string()),
+            },
+            Commands::ClusterAssign { slots, node } => match &replica.cluster {
+                Some(cluster) => match cluster.assign(slots, node) {
+                    Ok(()) => Responses::Ok,
+                    Err(e) => Responses::Error(e.to_string()),
+                },
+                None => Responses::Error("cluster mode is disabled".into()),
+            },
+            Commands::ClusterMigrate { slots, target } => {
+                match migrate_slots(replica, slots, target).await {
+                    Ok(moved) => Responses::Integer(moved as i64),
+                    Err(e) => Responses::Error(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Converte as opções de tempo de vida do `Set` em um timestamp no relógio do cache.
+///
+/// Retorna `None` quando nenhuma opção foi informada e erro quando mais de uma foi.
+fn expiration(
+    ex: Option<u64>,
+    px: Option<u64>,
+    exat: Option<i64>,
+    pxat: Option<i64>,
+) -> Result<Option<i64>, String> {
+    match (ex, px, exat, pxat) {
+        (None, None, None, None) => Ok(None),
+        (Some(seconds), None, None, None) => {
+            Ok(Some(clock::deadline_from_ttl(Duration::from_secs(seconds))))
+        }
+        (None, Some(millis), None, None) => Ok(Some(clock::deadline_from_ttl(
+            Duration::from_millis(millis),
+        ))),
+        (None, None, Some(seconds), None) => Ok(Some(clock::deadline_from_unix_millis(
+            seconds.saturating_mul(1000),
+        ))),
+        (None, None, None, Some(millis)) => Ok(Some(clock::deadline_from_unix_millis(millis))),
+        _ => Err("ex, px, exat and pxat are mutually exclusive".into()),
+    }
 }
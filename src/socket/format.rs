@@ -0,0 +1,51 @@
+use tokio_tungstenite::tungstenite::Message;
+
+use super::{Reply, Request};
+
+/// Formato de serialização usado por uma conexão.
+///
+/// O formato é definido pelo primeiro frame recebido: frames de texto usam
+/// JSON, com os valores em base64, e frames binarios usam MessagePack, com os
+/// valores como bytes. Depois de definido, o formato não muda ate o fim da conexão.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+}
+
+impl Format {
+    /// Identifica o formato pelo tipo do frame, `None` para frames de controle.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        match message {
+            Message::Text(_) => Some(Format::Json),
+            Message::Binary(_) => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Lê uma requisição do frame, que deve estar no formato da conexão.
+    pub fn decode(&self, message: &Message) -> Result<Request, (Option<u64>, String)> {
+        match (self, message) {
+            (Format::Json, Message::Text(text)) => Request::from_json(text),
+            (Format::MessagePack, Message::Binary(bytes)) => Request::from_msgpack(bytes),
+            _ => Err((None, format!("Unexpected frame for {} connection", self))),
+        }
+    }
+
+    /// Serializa a resposta no formato da conexão.
+    pub fn encode(&self, reply: &Reply) -> Option<Message> {
+        match self {
+            Format::Json => serde_json::to_string(reply).ok().map(Message::text),
+            Format::MessagePack => rmp_serde::to_vec_named(reply).ok().map(Message::binary),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Json => write!(f, "json"),
+            Format::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
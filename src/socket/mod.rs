@@ -1,8 +1,10 @@
 mod commands;
+mod format;
 mod responses;
 mod server;
 
 use commands::*;
+use format::*;
 use responses::*;
 pub use server::*;
 
@@ -1,7 +1,155 @@
+use std::net::SocketAddr;
+
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    memory::{CacheValue, MemoryError, StoreStats},
+    replication::{ClusterNode, ClusterNodeInfo, ReplicaInfo, SlotAssignment},
+};
+
+/// Envelope de uma resposta enviada pelo serviço de socket.
+///
+/// Carrega o mesmo `id` da `Request` que a originou, ou nenhum quando a
+/// requisição não possuia um ou não pode ser lida.
+///
+/// Quando a requisição pediu `wait`, `acknowledged` traz quantos slaves
+/// confirmaram a escrita.
+///
+/// ```json
+/// {"id": 1, "response": "Value", "data": "aGk="}
+/// {"id": 3, "acknowledged": 2, "response": "Ok"}
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged: Option<usize>,
+    #[serde(flatten)]
+    pub response: Responses,
+}
+
+/// Respostas enviadas pelo serviço de socket.
+///
+/// Toda resposta é serializada em JSON, identificada pelo campo `response`
+/// e com o conteudo, quando existir, em `data`.
+///
+/// ```json
+/// {"response": "Value", "data": "aGk="}
+/// {"response": "Ok"}
+/// {"response": "NotFound"}
+/// {"response": "Values", "data": ["aGk=", null]}
+/// {"response": "Versioned", "data": {"value": "aGk=", "version": 3}}
+/// {"response": "ConditionFailed"}
+/// {"response": "Integer", "data": 11}
+/// {"response": "Float", "data": 10.5}
+/// {"response": "NotNumeric", "data": "value is not an integer"}
+/// {"response": "Error", "data": "mensagem de erro"}
+/// {"response": "ReadOnly", "data": {"master": "127.0.0.1:5555"}}
+/// {"response": "Stale", "data": 3200}
+/// {"response": "Moved", "data": {"slot": 12182, "node": {"id": "7c2e...", "address": "127.0.0.1:5557", "services": {"socket": "127.0.0.1:50002"}}}}
+/// {"response": "Ask", "data": {"slot": 12182, "node": {"id": "7c2e...", "address": "127.0.0.1:5557", "services": {"socket": "127.0.0.1:50002"}}}}
+/// {"response": "Info", "data": {"keys": 1, "value_bytes": 2, "used_memory": 71, ...}}
+/// {"response": "Replicas", "data": [{"id": "9b1d...", "address": "127.0.0.1:5556", "version": "0.1.0", "offset": 40, "lag": 2}]}
+/// {"response": "ClusterSlots", "data": [{"start": 0, "end": 8191, "node": {"id": "9b1d...", "address": "127.0.0.1:5555", "services": {"socket": "127.0.0.1:50000"}}}]}
+/// {"response": "ClusterNodes", "data": [{"id": "9b1d...", "address": "127.0.0.1:5555", "services": {"socket": "127.0.0.1:50000"}, "role": "master", "myself": true, "state": "ok", "suspected_by": [], "slots": [{"start": 0, "end": 8191}], "heartbeat": 12}]}
+/// ```
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "response", content = "data")]
 pub enum Responses {
     Test(String),
+    /// Valor encontrado para a `key` solicitada.
+    Value(CacheValue),
+    /// Operação concluida com sucesso.
+    Ok,
+    /// A `key` solicitada não existe no cache.
+    NotFound,
+    /// Valores de varias `keys` na ordem pedida, `null` para as ausentes.
+    Values(Vec<Option<CacheValue>>),
+    /// Valor encontrado junto a versão usada em compare-and-swap.
+    Versioned {
+        value: CacheValue,
+        version: u64,
+    },
+    /// A condição da escrita não foi atendida e nada foi alterado.
+    ConditionFailed,
+    /// Resultado de uma operação numerica inteira.
+    Integer(i64),
+    /// Resultado de uma operação numerica decimal.
+    Float(f64),
+    /// O valor da `key` não é numerico e a operação não foi aplicada.
+    NotNumeric(String),
+    /// Falha ao processar o comando.
+    Error(String),
+    /// Escrita enviada a um slave, que deve ser refeita no master informado.
+    ReadOnly {
+        master: SocketAddr,
+    },
+    /// O slave esta mais atrasado que o `max_staleness` da leitura, com o
+    /// atraso atual em milissegundos ou `null` enquanto ele não sincronizou.
+    Stale(Option<u64>),
+    /// O slot das `keys` pertence a outro master do cluster, que deve receber
+    /// esta e as proximas requisições do slot.
+    Moved {
+        slot: u16,
+        node: ClusterNode,
+    },
+    /// O slot esta sendo migrado para o master informado, somente esta
+    /// requisição deve ser refeita nele, com `asking`.
+    Ask {
+        slot: u16,
+        node: ClusterNode,
+    },
+    /// Estatisticas do cache.
+    Info(StoreStats),
+    /// Slaves conectados ao nó.
+    Replicas(Vec<ReplicaInfo>),
+    /// Intervalos de slots do cluster e o master de cada um.
+    ClusterSlots(Vec<SlotAssignment>),
+    /// Nós do cluster na visão do nó que respondeu.
+    ClusterNodes(Vec<ClusterNodeInfo>),
+}
+
+impl Responses {
+    /// Indica se o comando foi recusado sem alterar nada, como em uma escrita
+    /// com condição não atendida ou enviada ao nó errado.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            Responses::ConditionFailed
+                | Responses::NotNumeric(_)
+                | Responses::Error(_)
+                | Responses::ReadOnly { .. }
+                | Responses::Stale(_)
+                | Responses::Moved { .. }
+                | Responses::Ask { .. }
+        )
+    }
+}
+
+impl From<MemoryError> for Responses {
+    fn from(error: MemoryError) -> Self {
+        match error {
+            MemoryError::NotNumeric(msg) => Responses::NotNumeric(msg),
+            e => Responses::Error(e.to_string()),
+        }
+    }
+}
+
+impl From<Result<(), MemoryError>> for Responses {
+    fn from(result: Result<(), MemoryError>) -> Self {
+        match result {
+            Ok(()) => Responses::Ok,
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl From<Result<i64, MemoryError>> for Responses {
+    fn from(result: Result<i64, MemoryError>) -> Self {
+        match result {
+            Ok(number) => Responses::Integer(number),
+            Err(e) => e.into(),
+        }
+    }
 }
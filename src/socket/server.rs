@@ -1,51 +1,91 @@
-use std::{env, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 
 use futures_util::{SinkExt, StreamExt};
-use tokio::{net::TcpListener, sync::mpsc};
+use tokio::{
+    net::TcpListener,
+    sync::{Semaphore, mpsc},
+};
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 
-use super::{Commands, Replica, Responses, SocketError};
+use super::{Format, Replica, Reply, Responses, SocketError};
 
-pub async fn start(replica: Arc<Replica>) -> Result<(), SocketError> {
-    let default_port = env::var("CR_SERVICE_PORT").unwrap_or_else(|_| "50000".to_string());
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", default_port)).await?;
+/// Quantidade maxima de comandos em execução ao mesmo tempo por conexão.
+///
+/// Ao atingir o limite a leitura de novas mensagens espera, aplicando
+/// contrapressão ao cliente em vez de acumular tarefas sem limite.
+const MAX_IN_FLIGHT: usize = 128;
+
+pub async fn start(replica: Arc<Replica>, ipaddr: SocketAddr) -> Result<(), SocketError> {
+    let listener = TcpListener::bind(ipaddr).await?;
     println!(
         "Serviço de CACHE iniciado: {} - {}",
-        replica.node.master_ipaddr(),
-        replica.node.mode
+        ipaddr,
+        replica.node().mode
     );
+    serve(replica, listener).await
+}
 
+/// Atende os clientes de um listener ja aberto.
+pub async fn serve(replica: Arc<Replica>, listener: TcpListener) -> Result<(), SocketError> {
     while let Ok((stream, _)) = listener.accept().await {
+        let replica = replica.clone();
         tokio::spawn(async move {
             if let Ok(ws_stream) = accept_async(stream).await {
-                let (peer_tx, mut peer_rx) = mpsc::channel::<Responses>(10);
+                let (peer_tx, mut peer_rx) = mpsc::channel::<Message>(MAX_IN_FLIGHT);
+                let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
                 let (mut write, mut read) = ws_stream.split();
 
                 // Spawn para enviar resposta a cada conexão
                 tokio::spawn(async move {
                     while let Some(message) = peer_rx.recv().await {
-                        if let Ok(response) = serde_json::to_string(&message) {
-                            if write.send(Message::text(response)).await.is_err() {
-                                break;
-                            }
+                        if write.send(message).await.is_err() {
+                            break;
                         }
                     }
                 });
 
-                // Loop para ler mensagens do cliente
+                // Loop para ler mensagens do cliente, cada comando roda em sua
+                // propria tarefa e responde assim que termina.
+                let mut format: Option<Format> = None;
                 while let Some(Ok(message)) = read.next().await {
-                    if let Ok(text) = message.to_text() {
-                        if let Ok(command) = serde_json::from_str::<Commands>(text) {
-                            match command {
-                                Commands::Test(s) => {
-                                    let response = Responses::Test(s);
-                                    let _ = peer_tx.send(response).await;
+                    match message {
+                        Message::Text(_) | Message::Binary(_) => {
+                            let format = *format.get_or_insert_with(|| {
+                                Format::from_message(&message).unwrap_or(Format::Json)
+                            });
+                            let request = match format.decode(&message) {
+                                Ok(request) => request,
+                                Err((id, error)) => {
+                                    let reply = Reply {
+                                        id,
+                                        acknowledged: None,
+                                        response: Responses::Error(error),
+                                    };
+                                    if let Some(message) = format.encode(&reply) {
+                                        let _ = peer_tx.send(message).await;
+                                    }
+                                    continue;
                                 }
-                            }
+                            };
+
+                            let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                                break;
+                            };
+                            let replica = replica.clone();
+                            let peer_tx = peer_tx.clone();
+                            tokio::spawn(async move {
+                                let reply = request.execute(&replica).await;
+                                if let Some(message) = format.encode(&reply) {
+                                    let _ = peer_tx.send(message).await;
+                                }
+                                drop(permit);
+                            });
+                        }
+                        Message::Close(_) => break,
+                        _ => {
+                            eprintln!("Failed to read message: {:?}", message);
+                            continue;
                         }
-                    } else {
-                        eprintln!("Failed to read message: {:?}", message);
-                        continue;
                     }
                 }
 
@@ -59,49 +99,654 @@ pub async fn start(replica: Arc<Replica>) -> Result<(), SocketError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio_tungstenite::connect_async;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
 
     use crate::{
-        replication::{Node, NodeMode},
-        socket::start,
+        memory::{CacheValue, Mutation, WriteCondition, clock},
+        replication::{Cluster, ClusterNode, Node, NodeMode, Services, SlotRange, key_slot},
+        socket::{Commands, KeyValue, Request, SetOptions, WaitOptions, serve},
+        test_support::listen,
     };
 
-    fn create_node() -> Node {
+    fn create_node(port: u16) -> Node {
         let mode = NodeMode::try_from("master".to_string()).unwrap();
-        let ipaddr = format!("{}:{}", "127.0.0.1", 8080).parse().unwrap();
+        let ipaddr = format!("{}:{}", "127.0.0.1", port).parse().unwrap();
         Node::new(mode, ipaddr)
     }
 
-    #[tokio::test]
-    async fn test_server() {
-        let node = create_node();
-        let replica = Arc::new(Replica::new(node));
+    async fn start_server() -> (Arc<Replica>, WebSocketStream<MaybeTlsStream<TcpStream>>) {
+        let (listener, ipaddr) = listen().await;
+        let replica = Arc::new(Replica::new(create_node(ipaddr.port())));
 
-        let replica_clone = replica.clone();
-        tokio::spawn(async move {
-            let _ = start(replica_clone).await;
-        });
-        tokio::time::sleep(tokio::time::Duration::from_millis(250)).await;
+        tokio::spawn(serve(replica.clone(), listener));
 
         // Conectar ao servidor WebSocket
-        let url = format!("ws://{}", replica.node.master_ipaddr());
-        let (mut ws_stream, _) = connect_async(url).await.expect("Failed to connect");
+        let url = format!("ws://{}", ipaddr);
+        let (ws_stream, _) = connect_async(url).await.expect("Failed to connect");
+
+        (replica, ws_stream)
+    }
 
-        let command = serde_json::to_string(&Commands::Test("Hello".into()))
-            .expect("Failed to crate command");
+    async fn send_command(
+        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+        command: &Commands,
+    ) -> Responses {
+        let command = serde_json::to_string(command).expect("Failed to crate command");
         ws_stream
             .send(Message::Text(command.into()))
             .await
             .expect("failed to send command");
 
-        if let Some(Ok(Message::Text(response))) = ws_stream.next().await {
-            let parsed: Responses =
-                serde_json::from_str(&response).expect("Failed to parse response");
-            assert_eq!(
-                parsed,
-                Responses::Test("Hello".into()),
-                "Unexpected response"
-            );
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(response))) => {
+                serde_json::from_str(&response).expect("Failed to parse response")
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        let (_, mut ws_stream) = start_server().await;
+
+        let parsed = send_command(&mut ws_stream, &Commands::Test("Hello".into())).await;
+        assert_eq!(
+            parsed,
+            Responses::Test("Hello".into()),
+            "Unexpected response"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_key_value_commands() {
+        let (replica, mut ws_stream) = start_server().await;
+
+        let set = Commands::Set {
+            key: "key".into(),
+            value: CacheValue::new("value"),
+            options: SetOptions::default(),
+        };
+        assert_eq!(send_command(&mut ws_stream, &set).await, Responses::Ok);
+        assert_eq!(replica.store.len(), 1, "Store should have a len of 1");
+
+        let get = Commands::Get("key".into());
+        assert_eq!(
+            send_command(&mut ws_stream, &get).await,
+            Responses::Value(CacheValue::new("value"))
+        );
+
+        let delete = Commands::Delete("key".into());
+        assert_eq!(send_command(&mut ws_stream, &delete).await, Responses::Ok);
+        assert_eq!(
+            send_command(&mut ws_stream, &delete).await,
+            Responses::NotFound
+        );
+        assert_eq!(
+            send_command(&mut ws_stream, &get).await,
+            Responses::NotFound
+        );
+
+        send_command(&mut ws_stream, &set).await;
+        assert_eq!(
+            send_command(&mut ws_stream, &Commands::Clear).await,
+            Responses::Ok
+        );
+        assert_eq!(replica.store.len(), 0, "Store should be empty after clear");
+
+        send_command(&mut ws_stream, &set).await;
+        match send_command(&mut ws_stream, &Commands::Info).await {
+            Responses::Info(stats) => assert_eq!(stats.keys, 1, "Info should report one key"),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+
+        let set_with_ttl = Commands::Set {
+            key: "key".into(),
+            value: CacheValue::new("value"),
+            options: SetOptions {
+                ex: Some(1),
+                px: Some(1000),
+                ..Default::default()
+            },
+        };
+        assert!(
+            matches!(
+                send_command(&mut ws_stream, &set_with_ttl).await,
+                Responses::Error(_)
+            ),
+            "ex and px together should be rejected"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_command() {
+        let (_, mut ws_stream) = start_server().await;
+
+        ws_stream
+            .send(Message::Text("{\"command\": \"Unknown\"}".into()))
+            .await
+            .expect("failed to send command");
+
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(response))) => {
+                let parsed: Responses =
+                    serde_json::from_str(&response).expect("Failed to parse response");
+                assert!(matches!(parsed, Responses::Error(_)), "Should be an error");
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conditional_set_commands() {
+        let (_, mut ws_stream) = start_server().await;
+
+        let set_nx = Commands::Set {
+            key: "lock".into(),
+            value: CacheValue::new("owner-1"),
+            options: SetOptions {
+                condition: Some(WriteCondition::Nx),
+                ..Default::default()
+            },
+        };
+        assert_eq!(send_command(&mut ws_stream, &set_nx).await, Responses::Ok);
+        assert_eq!(
+            send_command(&mut ws_stream, &set_nx).await,
+            Responses::ConditionFailed
+        );
+
+        let version = match send_command(&mut ws_stream, &Commands::GetVersion("lock".into())).await
+        {
+            Responses::Versioned { version, .. } => version,
+            other => panic!("Unexpected response: {:?}", other),
+        };
+
+        let swap = Commands::Set {
+            key: "lock".into(),
+            value: CacheValue::new("owner-2"),
+            options: SetOptions {
+                condition: Some(WriteCondition::Version(version)),
+                get: true,
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            send_command(&mut ws_stream, &swap).await,
+            Responses::Value(CacheValue::new("owner-1"))
+        );
+        assert_eq!(
+            send_command(&mut ws_stream, &swap).await,
+            Responses::ConditionFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_counter_commands() {
+        let (_, mut ws_stream) = start_server().await;
+
+        let incr = Commands::Incr("visits".into());
+        assert_eq!(
+            send_command(&mut ws_stream, &incr).await,
+            Responses::Integer(1)
+        );
+
+        let incr_by = Commands::IncrBy {
+            key: "visits".into(),
+            delta: 10,
+        };
+        assert_eq!(
+            send_command(&mut ws_stream, &incr_by).await,
+            Responses::Integer(11)
+        );
+
+        let decr = Commands::Decr("visits".into());
+        assert_eq!(
+            send_command(&mut ws_stream, &decr).await,
+            Responses::Integer(10)
+        );
+
+        let set = Commands::Set {
+            key: "name".into(),
+            value: CacheValue::new("crusty"),
+            options: SetOptions::default(),
+        };
+        send_command(&mut ws_stream, &set).await;
+        assert!(matches!(
+            send_command(&mut ws_stream, &Commands::Incr("name".into())).await,
+            Responses::NotNumeric(_)
+        ));
+
+        let incr_float = Commands::IncrByFloat {
+            key: "price".into(),
+            delta: 1.5,
+        };
+        assert_eq!(
+            send_command(&mut ws_stream, &incr_float).await,
+            Responses::Float(1.5)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_commands() {
+        let (_, mut ws_stream) = start_server().await;
+
+        let items = (0..50)
+            .map(|i| KeyValue {
+                key: format!("key-{}", i),
+                value: CacheValue::new(format!("value-{i}")),
+            })
+            .collect();
+        assert_eq!(
+            send_command(&mut ws_stream, &Commands::MSet(items)).await,
+            Responses::Ok
+        );
+
+        let keys = vec!["key-49".into(), "missing".into(), "key-0".into()];
+        assert_eq!(
+            send_command(&mut ws_stream, &Commands::MGet(keys.clone())).await,
+            Responses::Values(vec![
+                Some(CacheValue::new("value-49")),
+                None,
+                Some(CacheValue::new("value-0")),
+            ])
+        );
+
+        assert_eq!(
+            send_command(&mut ws_stream, &Commands::MDelete(keys)).await,
+            Responses::Integer(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests() {
+        let (_, mut ws_stream) = start_server().await;
+
+        // Envia todos os comandos antes de ler qualquer resposta
+        for id in 0..20u64 {
+            let request = Request {
+                id: Some(id),
+                max_staleness: None,
+                wait: None,
+                asking: false,
+                command: Commands::Set {
+                    key: format!("key:{}", id),
+                    value: CacheValue::new(id.to_string()),
+                    options: SetOptions::default(),
+                },
+            };
+            let text = serde_json::to_string(&request).expect("Failed to create request");
+            ws_stream
+                .send(Message::Text(text.into()))
+                .await
+                .expect("failed to send command");
+        }
+        ws_stream
+            .send(Message::Text(
+                "{\"id\": 99, \"command\": \"Unknown\"}".into(),
+            ))
+            .await
+            .expect("failed to send command");
+
+        let mut ids = Vec::new();
+        for _ in 0..21 {
+            match ws_stream.next().await {
+                Some(Ok(Message::Text(response))) => {
+                    let reply: Reply =
+                        serde_json::from_str(&response).expect("Failed to parse response");
+                    match reply.id {
+                        Some(99) => assert!(matches!(reply.response, Responses::Error(_))),
+                        _ => assert_eq!(reply.response, Responses::Ok),
+                    }
+                    ids.push(reply.id.expect("Reply should echo the request id"));
+                }
+                other => panic!("Unexpected message: {:?}", other),
+            }
+        }
+
+        ids.sort();
+        let expected: Vec<u64> = (0..20).chain([99]).collect();
+        assert_eq!(ids, expected, "Every request should be answered once");
+
+        let get = Commands::Get("key:7".into());
+        assert_eq!(
+            send_command(&mut ws_stream, &get).await,
+            Responses::Value(CacheValue::new("7"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binary_frames() {
+        let (_, mut ws_stream) = start_server().await;
+
+        let blob = CacheValue::new([0u8, 159, 146, 150, 255]);
+        let requests = [
+            Request {
+                id: Some(1),
+                max_staleness: None,
+                wait: None,
+                asking: false,
+                command: Commands::Set {
+                    key: "blob".into(),
+                    value: blob.clone(),
+                    options: SetOptions::default(),
+                },
+            },
+            Request {
+                id: Some(2),
+                max_staleness: None,
+                wait: None,
+                asking: false,
+                command: Commands::Get("blob".into()),
+            },
+        ];
+
+        for request in requests {
+            let bytes = rmp_serde::to_vec_named(&request).expect("Failed to create request");
+            ws_stream
+                .send(Message::Binary(bytes.into()))
+                .await
+                .expect("failed to send command");
+
+            let reply: Reply = match ws_stream.next().await {
+                Some(Ok(Message::Binary(response))) => {
+                    rmp_serde::from_slice(&response).expect("Failed to parse response")
+                }
+                other => panic!("Unexpected message: {:?}", other),
+            };
+            let expected = match request.id {
+                Some(1) => Responses::Ok,
+                _ => Responses::Value(blob.clone()),
+            };
+            assert_eq!(reply.id, request.id);
+            assert_eq!(reply.response, expected);
+        }
+
+        // A conexão ja esta em MessagePack, frames de texto são rejeitados
+        ws_stream
+            .send(Message::Text(
+                "{\"command\": \"Get\", \"data\": \"blob\"}".into(),
+            ))
+            .await
+            .expect("failed to send command");
+        match ws_stream.next().await {
+            Some(Ok(Message::Binary(response))) => {
+                let reply: Reply =
+                    rmp_serde::from_slice(&response).expect("Failed to parse response");
+                assert!(matches!(reply.response, Responses::Error(_)));
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_values_as_base64() {
+        let (_, mut ws_stream) = start_server().await;
+
+        ws_stream
+            .send(Message::Text(
+                "{\"command\": \"Set\", \"data\": {\"key\": \"k\", \"value\": \"aGk=\"}}".into(),
+            ))
+            .await
+            .expect("failed to send command");
+        let _ = ws_stream.next().await;
+
+        ws_stream
+            .send(Message::Text(
+                "{\"command\": \"Get\", \"data\": \"k\"}".into(),
+            ))
+            .await
+            .expect("failed to send command");
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(response))) => {
+                assert_eq!(
+                    response.as_str(),
+                    "{\"response\":\"Value\",\"data\":\"aGk=\"}"
+                );
+            }
+            other => panic!("Unexpected message: {:?}", other),
         }
     }
+
+    #[tokio::test]
+    async fn test_slave_rejects_writes() {
+        let mode = NodeMode::try_from("slave".to_string()).unwrap();
+        let replica = Arc::new(Replica::new(Node::new(
+            mode,
+            "127.0.0.1:5555".parse().unwrap(),
+        )));
+
+        let set = Commands::Set {
+            key: "key".into(),
+            value: CacheValue::new("value"),
+            options: SetOptions::default(),
+        };
+        assert_eq!(
+            set.execute(&replica).await,
+            Responses::ReadOnly {
+                master: "127.0.0.1:5555".parse().unwrap()
+            }
+        );
+        assert_eq!(
+            Commands::Get("key".into()).execute(&replica).await,
+            Responses::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replica_of() {
+        let mode = NodeMode::try_from("slave".to_string()).unwrap();
+        let replica = Arc::new(Replica::new(Node::new(
+            mode,
+            "127.0.0.1:5555".parse().unwrap(),
+        )));
+
+        let own_address = Some(*replica.node().address());
+        assert!(matches!(
+            Commands::ReplicaOf(own_address).execute(&replica).await,
+            Responses::Error(_)
+        ));
+
+        assert_eq!(
+            Commands::ReplicaOf(None).execute(&replica).await,
+            Responses::Ok
+        );
+        assert!(replica.node().is_master(), "Slave must be promoted");
+        assert_eq!(
+            Commands::Incr("visits".into()).execute(&replica).await,
+            Responses::Integer(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_staleness() {
+        let mode = NodeMode::try_from("slave".to_string()).unwrap();
+        let replica = Arc::new(Replica::new(Node::new(
+            mode,
+            "127.0.0.1:5555".parse().unwrap(),
+        )));
+        let deadline = clock::deadline_from_ttl(Duration::from_secs(60));
+        let set = Mutation::Set {
+            key: "key".into(),
+            value: CacheValue::new("value"),
+            expires_at: Some(clock::to_unix_millis(deadline)),
+        };
+        replica.store.apply(set).await.unwrap();
+
+        let request = |max_staleness, command| Request {
+            id: Some(1),
+            max_staleness,
+            wait: None,
+            asking: false,
+            command,
+        };
+        let exists = || Commands::Exists(vec!["key".into(), "other".into()]);
+
+        // Sem sincronização o atraso do slave é desconhecido
+        let reply = request(Some(1000), exists()).execute(&replica).await;
+        assert_eq!(reply.response, Responses::Stale(None));
+        let reply = request(None, exists()).execute(&replica).await;
+        assert_eq!(reply.response, Responses::Integer(1));
+
+        replica.master_link.synced("id".into(), 1);
+        replica.master_link.touch();
+        let reply = request(Some(1000), Commands::Ttl("key".into()))
+            .execute(&replica)
+            .await;
+        assert!(
+            matches!(reply.response, Responses::Integer(ttl) if ttl > 59_000),
+            "Unexpected reply: {:?}",
+            reply
+        );
+
+        // O master ja tinha o offset 2 no ultimo `Ping`
+        replica.master_link.pinged(2, clock::now_millis());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let reply = request(Some(10), Commands::Get("key".into()))
+            .execute(&replica)
+            .await;
+        assert!(matches!(reply.response, Responses::Stale(Some(staleness)) if staleness >= 30));
+
+        // Sem escritas no master o slave continua atualizado mesmo sem mensagens novas
+        replica.master_link.applied(2);
+        let reply = request(Some(10), Commands::Get("key".into()))
+            .execute(&replica)
+            .await;
+        assert_eq!(reply.response, Responses::Value(CacheValue::new("value")));
+
+        // O limite não se aplica a comandos que não leem keys
+        let reply = request(Some(10), Commands::Info).execute(&replica).await;
+        assert!(matches!(reply.response, Responses::Info(_)));
+    }
+
+    #[tokio::test]
+    async fn test_wait_without_replicas() {
+        let replica = Arc::new(Replica::new(create_node(5555)));
+        let request = Request {
+            id: Some(1),
+            max_staleness: None,
+            wait: Some(WaitOptions {
+                replicas: 1,
+                timeout: 50,
+            }),
+            asking: false,
+            command: Commands::Incr("visits".into()),
+        };
+
+        let reply = request.execute(&replica).await;
+        assert_eq!(reply.response, Responses::Integer(1));
+        assert_eq!(reply.acknowledged, Some(0));
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            "{\"id\":1,\"acknowledged\":0,\"response\":\"Integer\",\"data\":1}"
+        );
+
+        // Escrita recusada responde sem esperar os slaves
+        let mode = NodeMode::try_from("slave".to_string()).unwrap();
+        let slave = Arc::new(Replica::new(Node::new(
+            mode,
+            "127.0.0.1:5555".parse().unwrap(),
+        )));
+        let request = Request {
+            id: Some(2),
+            max_staleness: None,
+            wait: Some(WaitOptions {
+                replicas: 1,
+                timeout: 5_000,
+            }),
+            asking: false,
+            command: Commands::Incr("visits".into()),
+        };
+        let started = tokio::time::Instant::now();
+        let reply = request.execute(&slave).await;
+        assert!(matches!(reply.response, Responses::ReadOnly { .. }));
+        assert_eq!(reply.acknowledged, None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_redirects() {
+        let node = create_node(5555);
+        let local = ClusterNode::from(&node);
+        let other = ClusterNode {
+            id: "other".into(),
+            address: "127.0.0.1:5556".parse().unwrap(),
+            services: Services {
+                socket: Some("127.0.0.1:50001".parse().unwrap()),
+                ..Default::default()
+            },
+        };
+        let replica = Arc::new(Replica::new(node).with_cluster(Cluster::new()));
+        let request = |command: Commands, asking: bool| Request {
+            id: Some(7),
+            max_staleness: None,
+            wait: None,
+            asking,
+            command,
+        };
+
+        let all = SlotRange::new(0, 16383).unwrap();
+        let assign = Commands::ClusterAssign {
+            slots: all,
+            node: local.clone(),
+        };
+        assert_eq!(assign.execute(&replica).await, Responses::Ok);
+        let reply = request(Commands::Get("foo".into()), false)
+            .execute(&replica)
+            .await;
+        assert_eq!(reply.response, Responses::NotFound);
+
+        let slot = key_slot("foo");
+        let assign = Commands::ClusterAssign {
+            slots: SlotRange::new(slot, slot).unwrap(),
+            node: other.clone(),
+        };
+        assert_eq!(assign.execute(&replica).await, Responses::Ok);
+        let reply = request(Commands::Incr("foo".into()), false)
+            .execute(&replica)
+            .await;
+        assert_eq!(
+            reply.response,
+            Responses::Moved {
+                slot,
+                node: other.clone()
+            }
+        );
+        assert_eq!(replica.store.len(), 0, "Redirected writes are not applied");
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            format!(
+                "{{\"id\":7,\"response\":\"Moved\",\"data\":{{\"slot\":{},\"node\":{{\"id\":\"other\",\"address\":\"127.0.0.1:5556\",\"services\":{{\"socket\":\"127.0.0.1:50001\"}}}}}}}}",
+                slot
+            )
+        );
+
+        let reply = request(Commands::MGet(vec!["a".into(), "b".into()]), false)
+            .execute(&replica)
+            .await;
+        assert!(matches!(reply.response, Responses::Error(_)), "Cross slot");
+        let reply = request(Commands::MGet(vec!["{a}1".into(), "{a}2".into()]), false)
+            .execute(&replica)
+            .await;
+        assert_eq!(reply.response, Responses::Values(vec![None, None]));
+        let reply = request(Commands::Info, false).execute(&replica).await;
+        assert!(matches!(reply.response, Responses::Info(_)));
+
+        let Responses::ClusterSlots(assignments) = Commands::ClusterSlots.execute(&replica).await
+        else {
+            panic!("Expected ClusterSlots");
+        };
+        assert_eq!(assignments.len(), 3);
+        assert_eq!(assignments[1].node, other);
+
+        let standalone = Arc::new(Replica::new(create_node(5555)));
+        assert!(matches!(
+            Commands::ClusterSlots.execute(&standalone).await,
+            Responses::Error(_)
+        ));
+        let reply = request(Commands::Get("foo".into()), false)
+            .execute(&standalone)
+            .await;
+        assert_eq!(reply.response, Responses::NotFound);
+    }
 }
@@ -0,0 +1,37 @@
+//! Utilitarios dos testes que abrem conexões.
+
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use tokio::net::TcpListener;
+
+/// Abre um listener em uma porta livre escolhida pelo sistema.
+///
+/// O listener ja aceita conexões antes do servidor começar a atende-las,
+/// assim os testes não precisam esperar o servidor subir.
+pub async fn listen() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind a local port");
+    let address = listener.local_addr().unwrap();
+    (listener, address)
+}
+
+/// Espera ate a condição ser verdadeira, falhando apos alguns segundos.
+pub async fn wait_until(condition: impl Fn() -> bool) {
+    wait_for(|| std::future::ready(condition())).await;
+}
+
+/// Como `wait_until`, para condições que precisam de `await`.
+pub async fn wait_for<F, Fut>(condition: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    for _ in 0..500 {
+        if condition().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Condition was not met in time");
+}